@@ -0,0 +1,41 @@
+//! Embeds the user programs into the kernel, see `process::programs`.
+//!
+//! `make user` builds them into the target directory. The programs found
+//! there are included with `include_bytes!`, missing ones are left out
+//! with a warning, so the kernel also builds without them.
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// The programs of the `user` crate.
+const PROGRAMS: &[&str] = &["hello", "cat", "counter"];
+
+fn main() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let target_dir = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| manifest_dir.join("target"));
+    let directory = target_dir.join("x86_64-user").join("release");
+
+    // Cargo looks at everything inside a watched directory, so this also
+    // notices programs which are built after the kernel
+    println!("cargo:rerun-if-changed={}", directory.display());
+
+    let mut source = String::from("static BUILT_IN: &[(&str, &[u8])] = &[\n");
+    for name in PROGRAMS {
+        let path = directory.join(name);
+        if path.is_file() {
+            source.push_str(&format!("    ({:?}, include_bytes!({:?})),\n", name, path));
+        } else {
+            println!(
+                "cargo:warning=user program `{}` is not built, run `make user`",
+                name
+            );
+        }
+    }
+    source.push_str("];\n");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out_dir.join("user_programs.rs"), source).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+}
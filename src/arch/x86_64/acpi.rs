@@ -0,0 +1,314 @@
+//! # ACPI
+//!
+//! Just enough ACPI table parsing for power management: the FADT gives the
+//! PM1 control blocks and the reset register, and the `\_S5` object in the
+//! DSDT gives the sleep type values needed to power off.
+//!
+//! The tables are read through `phys_to_virt`.
+use core::mem::size_of;
+use core::ptr;
+use core::slice;
+use spin::Once;
+use x86_64::PhysAddr;
+
+use super::memory::paging::phys_to_virt;
+
+static ACPI: Once<Acpi> = Once::new();
+
+/// Generic address structure, describes a register in memory or IO space.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+pub const ADDRESS_SPACE_IO: u8 = 1;
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // ACPI 2.0 and later
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Size of the ACPI 1.0 part of the RSDP.
+const RSDP_V1_SIZE: usize = 20;
+
+#[repr(C, packed)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+/// The fields of the FADT used by the kernel, at their offsets in the table.
+mod fadt {
+    pub const DSDT: usize = 40;
+    pub const SMI_COMMAND: usize = 48;
+    pub const ACPI_ENABLE: usize = 52;
+    pub const PM1A_CONTROL_BLOCK: usize = 64;
+    pub const PM1B_CONTROL_BLOCK: usize = 68;
+    pub const FLAGS: usize = 112;
+    pub const RESET_REGISTER: usize = 116;
+    pub const RESET_VALUE: usize = 128;
+    pub const X_DSDT: usize = 140;
+
+    /// Flag which is set when the reset register is supported.
+    pub const RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+}
+
+/// Power management information from the FADT and DSDT.
+#[derive(Debug, Clone, Copy)]
+pub struct Acpi {
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control: u16,
+    pub pm1b_control: u16,
+    pub reset_register: Option<(GenericAddress, u8)>,
+    /// SLP_TYPa and SLP_TYPb values for the S5 (soft off) state.
+    pub s5_sleep_types: Option<(u8, u8)>,
+}
+
+/// Finds and parses the ACPI tables. Returns `None` if the machine has no
+/// (valid) ACPI tables. Paging must be initialized.
+pub fn init() -> Option<&'static Acpi> {
+    assert_has_not_been_called!("ACPI should only be initialized once!");
+
+    let acpi = match Tables.parse() {
+        Some(acpi) => acpi,
+        None => {
+            warn!("No ACPI tables found");
+            return None;
+        }
+    };
+
+    debug!("ACPI: {:x?}", acpi);
+
+    Some(ACPI.call_once(|| acpi))
+}
+
+pub fn get() -> Option<&'static Acpi> {
+    ACPI.r#try()
+}
+
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// Reads tables from physical memory.
+struct Tables;
+
+impl Tables {
+    fn bytes(&self, address: u64, length: usize) -> &'static [u8] {
+        let start = phys_to_virt(PhysAddr::new(address));
+        unsafe { slice::from_raw_parts(start.as_ptr(), length) }
+    }
+
+    fn read<T>(&self, address: u64) -> T {
+        unsafe { ptr::read_unaligned(phys_to_virt(PhysAddr::new(address)).as_ptr()) }
+    }
+
+    fn parse(&self) -> Option<Acpi> {
+        let rsdp_address = self.find_rsdp()?;
+        let rsdp: Rsdp = self.read(rsdp_address);
+
+        let fadt = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+            self.find_table(rsdp.xsdt_address, size_of::<u64>(), b"FACP")
+        } else {
+            self.find_table(rsdp.rsdt_address as u64, size_of::<u32>(), b"FACP")
+        }?;
+
+        let header: SdtHeader = self.read(fadt);
+        let length = header.length as usize;
+        let field_u32 = |offset: usize| -> u32 { self.read(fadt + offset as u64) };
+
+        // The reset register and the 64 bit DSDT pointer were added in ACPI 2.0
+        let reset_register = if length > fadt::RESET_VALUE
+            && field_u32(fadt::FLAGS) & fadt::RESET_REGISTER_SUPPORTED != 0
+        {
+            Some((
+                self.read(fadt + fadt::RESET_REGISTER as u64),
+                self.read(fadt + fadt::RESET_VALUE as u64),
+            ))
+        } else {
+            None
+        };
+
+        let x_dsdt: u64 = if length >= fadt::X_DSDT + 8 {
+            self.read(fadt + fadt::X_DSDT as u64)
+        } else {
+            0
+        };
+        let dsdt = if x_dsdt != 0 {
+            x_dsdt
+        } else {
+            field_u32(fadt::DSDT) as u64
+        };
+
+        Some(Acpi {
+            smi_command: field_u32(fadt::SMI_COMMAND),
+            acpi_enable: self.read(fadt + fadt::ACPI_ENABLE as u64),
+            pm1a_control: field_u32(fadt::PM1A_CONTROL_BLOCK) as u16,
+            pm1b_control: field_u32(fadt::PM1B_CONTROL_BLOCK) as u16,
+            reset_register,
+            s5_sleep_types: self.table_body(dsdt).and_then(find_s5_sleep_types),
+        })
+    }
+
+    /// Searches the first KiB of the EBDA and the BIOS area from 0xE0000 to
+    /// 0xFFFFF for the RSDP signature, on 16 byte boundaries.
+    fn find_rsdp(&self) -> Option<u64> {
+        let ebda = (self.read::<u16>(0x40E) as u64) << 4;
+
+        let mut candidates = (ebda..ebda + 1024)
+            .step_by(16)
+            .chain((0xE0000..0x100000).step_by(16));
+
+        candidates.find(|&address| {
+            self.bytes(address, 8) == b"RSD PTR " && checksum(self.bytes(address, RSDP_V1_SIZE))
+        })
+    }
+
+    /// Finds a table in the RSDT or XSDT, which contain pointers of
+    /// `pointer_size` bytes after the header. A root table with a bad
+    /// length or checksum has no tables.
+    fn find_table(&self, root: u64, pointer_size: usize, signature: &[u8; 4]) -> Option<u64> {
+        let entries = self.table_body(root)?.len() / pointer_size;
+        let first = root + size_of::<SdtHeader>() as u64;
+
+        (0..entries)
+            .map(|i| {
+                let entry = first + (i * pointer_size) as u64;
+                if pointer_size == size_of::<u64>() {
+                    self.read::<u64>(entry)
+                } else {
+                    self.read::<u32>(entry) as u64
+                }
+            })
+            .find(|&table| {
+                let header: SdtHeader = self.read(table);
+                header.signature == *signature
+                    && checksum(self.bytes(table, header.length as usize))
+            })
+    }
+
+    /// Returns the table contents after the header.
+    fn table_body(&self, table: u64) -> Option<&'static [u8]> {
+        if table == 0 {
+            return None;
+        }
+
+        let header: SdtHeader = self.read(table);
+        let length = header.length as usize;
+        if length < size_of::<SdtHeader>() || !checksum(self.bytes(table, length)) {
+            return None;
+        }
+
+        Some(self.bytes(
+            table + size_of::<SdtHeader>() as u64,
+            length - size_of::<SdtHeader>(),
+        ))
+    }
+}
+
+/// Finds the `\_S5` package in AML code and returns the first two
+/// elements, SLP_TYPa and SLP_TYPb.
+///
+/// This is not an AML interpreter, it looks for the byte pattern most
+/// firmware uses:
+///
+/// ```text
+/// NameOp ["\"] "_S5_" PackageOp PkgLength NumElements
+///     [BytePrefix] SLP_TYPa [BytePrefix] SLP_TYPb ...
+/// ```
+fn find_s5_sleep_types(aml: &[u8]) -> Option<(u8, u8)> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const BYTE_PREFIX: u8 = 0x0A;
+
+    let position = aml.windows(4).enumerate().position(|(i, window)| {
+        window == b"_S5_"
+            && ((i >= 1 && aml[i - 1] == NAME_OP)
+                || (i >= 2 && aml[i - 2] == NAME_OP && aml[i - 1] == b'\\'))
+    })?;
+
+    let mut bytes = aml.get(position + 4..)?.iter().copied();
+
+    if bytes.next()? != PACKAGE_OP {
+        return None;
+    }
+
+    // Bits 6 and 7 of the first PkgLength byte give the number of
+    // following length bytes.
+    let length_bytes = bytes.next()? >> 6;
+    for _ in 0..length_bytes {
+        bytes.next()?;
+    }
+    let _elements = bytes.next()?;
+
+    let mut element = || -> Option<u8> {
+        match bytes.next()? {
+            BYTE_PREFIX => bytes.next(),
+            // ZeroOp and OneOp encode their own value
+            value => Some(value),
+        }
+    };
+
+    Some((element()?, element()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checksum_sums_to_zero() {
+        assert!(checksum(&[0x10, 0xF0]));
+        assert!(!checksum(&[0x10, 0xF1]));
+    }
+
+    #[test]
+    fn s5_with_byte_prefix() {
+        // Name (\_S5, Package (0x04) { 0x05, 0x05, Zero, Zero })
+        let aml = [
+            0x10, 0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x0A, 0x04, 0x0A, 0x05, 0x0A, 0x05,
+            0x00, 0x00,
+        ];
+
+        assert_eq!(find_s5_sleep_types(&aml), Some((5, 5)));
+    }
+
+    #[test]
+    fn s5_with_zero_op() {
+        // Name (_S5, Package (0x02) { Zero, Zero })
+        let aml = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x04, 0x02, 0x00, 0x00];
+
+        assert_eq!(find_s5_sleep_types(&aml), Some((0, 0)));
+    }
+
+    #[test]
+    fn s5_missing() {
+        // A method named _S5_ is not the sleep package
+        let aml = [0x14, b'_', b'S', b'5', b'_', 0x12, 0x04, 0x02, 0x00, 0x00];
+
+        assert_eq!(find_s5_sleep_types(&aml), None);
+    }
+}
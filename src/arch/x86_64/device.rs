@@ -1,5 +1,6 @@
-use crate::device::{pic8259};
+use crate::device::{pic8259, serial};
 
 pub fn init() {
     pic8259::init();
+    serial::init();
 }
\ No newline at end of file
@@ -1,70 +1,123 @@
+use x86_64::instructions::interrupts;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
-use x86_64::VirtAddr;
+use x86_64::{PrivilegeLevel, VirtAddr};
+
+use super::memory;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
-//const STACK_SIZE: usize = 4096;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+pub const PAGE_FAULT_IST_INDEX: u16 = 3;
+
+/// Size in pages of the stacks in the IST. The page fault handler logs
+/// with formatting, so it gets as much room as the double fault handler.
+const DOUBLE_FAULT_STACK_PAGES: usize = 4;
+const NMI_STACK_PAGES: usize = 2;
+const MACHINE_CHECK_STACK_PAGES: usize = 2;
+const PAGE_FAULT_STACK_PAGES: usize = 4;
+
+/// Size in pages of the stack the CPU switches to when an interrupt
+/// arrives in ring 3, until a task sets its own with `set_kernel_stack`.
+const KERNEL_STACK_PAGES: usize = 8;
+
+// GDT = GlobalDescriptorTable
+// TSS = TaskStateSegment
+// IST = InterruptStackTable, contains 7 stacks
+//
+// The TSS of the CPU. The stacks are allocated by `init`, the privilege
+// stack is changed per task with `set_kernel_stack`. The CPU reads the
+// TSS by its address, so it stays in a static.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
+/// Allocates the interrupt stacks and loads the GDT and the TSS. The
+/// memory controller must be initialized.
 pub fn init() {
-    use x86_64::instructions::segmentation::set_cs;
+    use x86_64::instructions::segmentation::{load_ss, set_cs};
     use x86_64::instructions::tables::load_tss;
 
+    assert_has_not_been_called!("The GDT should only be initialized once!");
+
+    unsafe {
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            alloc_stack(DOUBLE_FAULT_STACK_PAGES, "double fault stack");
+        TSS.interrupt_stack_table[NMI_IST_INDEX as usize] =
+            alloc_stack(NMI_STACK_PAGES, "NMI stack");
+        TSS.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] =
+            alloc_stack(MACHINE_CHECK_STACK_PAGES, "machine check stack");
+        TSS.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] =
+            alloc_stack(PAGE_FAULT_STACK_PAGES, "page fault stack");
+
+        // Used on ring 3 to ring 0 transitions
+        TSS.privilege_stack_table[0] = alloc_stack(KERNEL_STACK_PAGES, "kernel entry stack");
+    }
+
     GDT.0.load();
 
     unsafe {
-        set_cs(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+        set_cs(GDT.1.kernel_code);
+        load_ss(GDT.1.kernel_data);
+        load_tss(GDT.1.tss);
     }
 }
 
-// GDT = GlobalDescriptorTable
-// TSS = TaskStateSegment  
-// IST = InterruptStackTable, contains 7 stacks
-//
-// Create static TSS, create the IST and include
-// the double fault stack in the 0th enty of the IST
-lazy_static! {
+/// Sets the stack the CPU switches to when an interrupt or system call
+/// arrives in ring 3. Called on a task switch with the kernel stack of the
+/// new task.
+pub fn set_kernel_stack(top: VirtAddr) {
+    interrupts::without_interrupts(|| {
+        unsafe { TSS.privilege_stack_table[0] = top };
+        super::syscall::set_kernel_stack(top);
+    });
+}
 
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            // No memory management yet so use a static array.
-            // Use a static mutable array so the stack remains
-            // readable.
-            //
-            // TODO: make safe later
-            const STACK_SIZE: usize = 4096;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-
-            // Write the top address of the double fault stack to the IST 0th index
-            // We use the top address because x86 stacks grow downwards (high to low)
-            stack_end
-        };
-
-        tss
-    };
+/// Returns the stack set with `set_kernel_stack`.
+pub fn kernel_stack() -> VirtAddr {
+    interrupts::without_interrupts(|| unsafe { TSS.privilege_stack_table[0] })
+}
+
+/// Returns the segment selectors of the GDT.
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// Allocates a stack with a guard page and returns its top address. We use
+/// the top address because x86 stacks grow downwards (high to low).
+fn alloc_stack(pages: usize, name: &'static str) -> VirtAddr {
+    let stack = memory::alloc_stack(pages, name)
+        .unwrap_or_else(|error| panic!("Allocating the {} failed: {}", name, error));
+
+    VirtAddr::new(stack.top() as u64)
 }
 
-// Create the global descriptor table.
+// Create the global descriptor table. The user data segment comes right
+// before the user code segment, the order `sysret` expects.
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(unsafe { &TSS }));
         (
             gdt,
             Selectors {
-                code_selector,
-                tss_selector,
+                kernel_code,
+                kernel_data,
+                user_data: SegmentSelector::new(user_data.index(), PrivilegeLevel::Ring3),
+                user_code: SegmentSelector::new(user_code.index(), PrivilegeLevel::Ring3),
+                tss,
             },
         )
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    /// Requested privilege level 3, ready to be loaded in user mode.
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
 }
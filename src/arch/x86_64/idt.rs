@@ -1,9 +1,11 @@
 extern crate x86_64;
 
 use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::PrivilegeLevel;
 
 use super::gdt;
 use super::interrupts::{exceptions, irq};
+use super::syscall;
 use crate::device::pic8259;
 
 pub fn init() {
@@ -16,15 +18,39 @@ lazy_static! {
 
         idt.breakpoint
             .set_handler_fn(exceptions::breakpoint_handler);
+        idt.divide_error
+            .set_handler_fn(exceptions::divide_error_handler);
+        idt.invalid_opcode
+            .set_handler_fn(exceptions::invalid_opcode_handler);
+        idt.general_protection_fault
+            .set_handler_fn(exceptions::general_protection_fault_handler);
+        // These can arrive on a broken stack, so they run on their own
+        // stacks from the IST
         unsafe {
             idt.double_fault
                 .set_handler_fn(exceptions::double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            idt.non_maskable_interrupt
+                .set_handler_fn(exceptions::nmi_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+            idt.machine_check
+                .set_handler_fn(exceptions::machine_check_handler)
+                .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+            idt.page_fault
+                .set_handler_fn(exceptions::page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
         }
 
         idt[pic8259::TIMER_INTERRUPT_ID as usize].set_handler_fn(irq::timer_interrupt_handler);
         idt[pic8259::KEYBOARD_INTERRUPT_ID as usize]
             .set_handler_fn(irq::keyboard_interrupt_handler);
+        idt[pic8259::COM1_INTERRUPT_ID as usize].set_handler_fn(irq::com1_interrupt_handler);
+        idt[pic8259::COM2_INTERRUPT_ID as usize].set_handler_fn(irq::com2_interrupt_handler);
+
+        // User code may use int 0x80 for system calls
+        idt[syscall::SYSCALL_INTERRUPT_ID as usize]
+            .set_handler_fn(syscall::int80_handler())
+            .set_privilege_level(PrivilegeLevel::Ring3);
 
         idt
     };
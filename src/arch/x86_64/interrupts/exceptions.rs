@@ -1,14 +1,116 @@
-use x86_64::structures::idt::InterruptStackFrame;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
+
+use crate::arch::memory;
+use crate::arch::user::{self, UserExit};
 
 
 pub extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut InterruptStackFrame) {
-    kprintln!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+    warn!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+pub extern "x86-interrupt" fn divide_error_handler(stack_frame: &mut InterruptStackFrame) {
+    exit_on_user_fault(stack_frame, "divide error");
+    error!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+    loop {}
+}
+
+pub extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: &mut InterruptStackFrame) {
+    exit_on_user_fault(stack_frame, "invalid opcode");
+    error!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+    loop {}
+}
+
+pub extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: &mut InterruptStackFrame,
+    error_code: u64,
+) {
+    exit_on_user_fault(stack_frame, "general protection fault");
+    error!(
+        "EXCEPTION: GENERAL PROTECTION FAULT ({:#x})\n{:#?}",
+        error_code, stack_frame
+    );
+    loop {}
 }
 
 pub extern "x86-interrupt" fn double_fault_handler(
     stack_frame: &mut InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
-    kprintln!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+    // A fault in the page fault handler itself ends up here with the
+    // address still in CR2
+    report_stack_overflow();
+    error!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
     loop {}
-}
\ No newline at end of file
+}
+
+/// Number of non-maskable interrupts received.
+static NMI_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Only counts: an NMI can arrive while a console lock is held, logging
+/// here could deadlock.
+pub extern "x86-interrupt" fn nmi_handler(_stack_frame: &mut InterruptStackFrame) {
+    NMI_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn nmi_count() -> usize {
+    NMI_COUNT.load(Ordering::Relaxed)
+}
+
+pub extern "x86-interrupt" fn machine_check_handler(stack_frame: &mut InterruptStackFrame) -> ! {
+    error!("EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame);
+    loop {}
+}
+
+pub extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: &mut InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    // The first access to a lazy page
+    if memory::handle_page_fault(Cr2::read(), error_code) {
+        return;
+    }
+
+    exit_on_user_fault(stack_frame, "page fault");
+    report_stack_overflow();
+    error!(
+        "EXCEPTION: PAGE FAULT at {:?} ({:?})\n{:#?}",
+        Cr2::read(),
+        error_code,
+        stack_frame
+    );
+    loop {}
+}
+
+/// Ends the run of the user code if the exception arrived in ring 3, the
+/// kernel goes on after `enter_user_mode`.
+fn exit_on_user_fault(stack_frame: &InterruptStackFrame, exception: &'static str) {
+    if !user::from_user_mode(stack_frame) {
+        return;
+    }
+
+    let instruction = stack_frame.instruction_pointer;
+    warn!("{} in user mode at {:?}", exception, instruction);
+    unsafe {
+        user::exit_user_mode(UserExit::Fault {
+            exception,
+            instruction,
+        })
+    }
+}
+
+/// Logs which stack overflowed if the faulting address is in a guard page.
+fn report_stack_overflow() {
+    let address = Cr2::read();
+
+    if let Some(stack) = memory::guard_page_hit(address) {
+        error!(
+            "STACK OVERFLOW: {:?} is in the guard page of \"{}\" ({:#x}-{:#x})",
+            address,
+            stack.name,
+            stack.start.as_u64(),
+            stack.end().as_u64()
+        );
+    }
+}
@@ -1,12 +1,40 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::structures::idt::InterruptStackFrame;
 use x86_64::instructions::port::Port;
-use crate::device::{keyboard, pic8259};
+use crate::arch::user;
+use crate::device::{keyboard, pic8259, serial};
+use crate::process::signal;
 use crate::time;
 
-pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+pub const IRQ_LINES: usize = 16;
 
-    // This function requires memory to be initialized. But the PIC interrupts are
-    // off until the end of arch init() so by now we should have a heap.
+/// Number of interrupts received per IRQ line.
+static IRQ_COUNTS: [AtomicUsize; IRQ_LINES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+fn count(irq: u8) {
+    IRQ_COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn irq_count(irq: u8) -> usize {
+    IRQ_COUNTS[irq as usize].load(Ordering::Relaxed)
+}
+
+/// Ends the running process if the interrupt arrived in ring 3 and a
+/// signal kills it, like one sent by Ctrl+C. Must be called after the
+/// end of interrupt, with no lock held.
+fn exit_if_killed(stack_frame: &InterruptStackFrame) {
+    if user::from_user_mode(stack_frame) {
+        signal::exit_if_killed();
+    }
+}
+
+pub extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
+    count(pic8259::TIMER_IRQ);
     time::TIME.tick();
 
     unsafe {
@@ -14,9 +42,12 @@ pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: &mut Interru
             .lock()
             .notify_end_of_interrupt(pic8259::TIMER_INTERRUPT_ID)
     }
+    exit_if_killed(stack_frame);
 }
 
-pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+pub extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
+    count(pic8259::KEYBOARD_IRQ);
+
     let scancodeport = &mut Port::new(0x60);
 
     let scancode: u8 = unsafe { scancodeport.read() };
@@ -24,9 +55,47 @@ pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut Inte
     let mut keyboard_guard = keyboard::KEYBOARD.lock();
     keyboard_guard.queue_scancode(scancode);
 
+    while keyboard_guard.has_scancode() {
+        if let Some(key) = keyboard_guard.process_scancode() {
+            key.push_console_input();
+        }
+    }
+    drop(keyboard_guard);
+
     unsafe {
         pic8259::PICS
             .lock()
             .notify_end_of_interrupt(pic8259::KEYBOARD_INTERRUPT_ID)
     }
+    exit_if_killed(stack_frame);
+}
+
+pub extern "x86-interrupt" fn com1_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
+    count(pic8259::COM1_IRQ);
+
+    // COM1 and COM3 share this line
+    serial::COM1.lock().handle_interrupt();
+    serial::COM3.lock().handle_interrupt();
+
+    unsafe {
+        pic8259::PICS
+            .lock()
+            .notify_end_of_interrupt(pic8259::COM1_INTERRUPT_ID)
+    }
+    exit_if_killed(stack_frame);
+}
+
+pub extern "x86-interrupt" fn com2_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
+    count(pic8259::COM2_IRQ);
+
+    // COM2 and COM4 share this line
+    serial::COM2.lock().handle_interrupt();
+    serial::COM4.lock().handle_interrupt();
+
+    unsafe {
+        pic8259::PICS
+            .lock()
+            .notify_end_of_interrupt(pic8259::COM2_INTERRUPT_ID)
+    }
+    exit_if_killed(stack_frame);
 }
@@ -0,0 +1,450 @@
+//! # Address spaces
+//!
+//! Every address space has its own P4 table. The P4 entries of the user
+//! part, `USER_SPACE_START` to `USER_SPACE_END`, belong to the address
+//! space, all other entries are copied from the kernel P4 table. The
+//! tables below them are shared, so the kernel half looks the same in
+//! every address space.
+//!
+//! The P4 entries of the kernel space are allocated by `init`, before any
+//! address space is created, so kernel mappings made later show up in all
+//! address spaces.
+//!
+//! The page tables of an address space are edited through the physical
+//! memory mapping, so it does not need to be active. Dropping an address
+//! space frees its page tables and every frame mapped in the user part,
+//! frames shared by `duplicate` lose a reference.
+use spin::Once;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    mapper::{TranslateError, UnmapError},
+    page_table::PageTableEntry,
+    FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags,
+    PhysFrame, Size1GiB, Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::area_frame_allocator::AreaFrameAllocator;
+use super::cow;
+use super::lazy;
+use super::paging::{self, page_table, physical_memory_offset, zeroed_frame};
+use super::vmm::{KERNEL_SPACE_END, KERNEL_SPACE_START};
+use super::{with_controller, MemoryError};
+
+/// Start of the part of an address space which belongs to it, P4 entry 16.
+pub const USER_SPACE_START: u64 = 0x_0800_0000_0000;
+/// End (exclusive) of the user part, the end of the lower half.
+pub const USER_SPACE_END: u64 = 0x_8000_0000_0000;
+
+/// Bytes mapped by one P4 entry.
+const P4_ENTRY_SIZE: u64 = 512 * Size1GiB::SIZE;
+
+/// The P4 table the kernel booted with.
+static KERNEL_P4: Once<PhysFrame> = Once::new();
+
+/// Remembers the kernel P4 table and allocates its entries for the kernel
+/// space, so they never change after address spaces copied them.
+pub(super) fn init(frame_allocator: &mut AreaFrameAllocator) {
+    let (p4_frame, _) = Cr3::read();
+    KERNEL_P4.call_once(|| p4_frame);
+
+    let p4 = unsafe { page_table(p4_frame) };
+    for index in p4_index(KERNEL_SPACE_START)..=p4_index(KERNEL_SPACE_END - 1) {
+        if p4[index].is_unused() {
+            let frame = zeroed_frame(frame_allocator).expect("No frame for a kernel P3 table");
+            p4[index].set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+    }
+
+    for index in p4_index(USER_SPACE_START)..p4_index(USER_SPACE_END) {
+        if !p4[index].is_unused() {
+            warn!(
+                "P4 entry {} is used by the kernel, it is not available to address spaces",
+                index
+            );
+        }
+    }
+}
+
+/// Returns the P4 table the kernel booted with.
+pub fn kernel_p4() -> Option<PhysFrame> {
+    KERNEL_P4.r#try().copied()
+}
+
+/// Loads the kernel P4 table.
+pub fn switch_to_kernel() {
+    if let Some(p4_frame) = kernel_p4() {
+        switch_to(p4_frame);
+    }
+}
+
+/// Checks that the `len` bytes at `address` are user memory of the active
+/// address space, which user code may read, or write if `write` is set.
+/// Lazy and copy-on-write pages pass, they are handled on the access.
+pub fn check_user_range(address: VirtAddr, len: u64, write: bool) -> Result<(), MemoryError> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    let start = address.as_u64();
+    let end = start.checked_add(len).ok_or(MemoryError::NotUserAddress)?;
+    if start < USER_SPACE_START || end > USER_SPACE_END {
+        return Err(MemoryError::NotUserAddress);
+    }
+
+    let (p4_frame, _) = Cr3::read();
+    let first = Page::<Size4KiB>::containing_address(address);
+    let last = Page::containing_address(VirtAddr::new(end - 1));
+
+    for page in Page::range_inclusive(first, last) {
+        let entry =
+            unsafe { paging::find_p1_entry(p4_frame, page) }.ok_or(MemoryError::NotMapped)?;
+        let flags = entry.flags();
+
+        if !lazy::is_lazy(entry) && !flags.contains(PageTableFlags::PRESENT) {
+            return Err(MemoryError::NotMapped);
+        }
+        if !flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+            return Err(MemoryError::NotUserAddress);
+        }
+        if write && !flags.contains(PageTableFlags::WRITABLE) && !cow::is_cow(entry) {
+            return Err(MemoryError::ReadOnly);
+        }
+    }
+
+    Ok(())
+}
+
+pub struct AddressSpace {
+    p4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    /// Creates an address space with an empty user part.
+    pub fn new() -> Result<AddressSpace, MemoryError> {
+        let kernel_p4_frame = kernel_p4().ok_or(MemoryError::Uninitialized)?;
+        let p4_frame = with_controller(|controller| {
+            zeroed_frame(&mut controller.frame_allocator).ok_or(MemoryError::OutOfFrames)
+        })?;
+
+        let kernel_p4 = unsafe { page_table(kernel_p4_frame) };
+        let p4 = unsafe { page_table(p4_frame) };
+
+        for (entry, kernel_entry) in p4.iter_mut().zip(kernel_p4.iter()) {
+            if kernel_entry.is_unused() {
+                continue;
+            }
+
+            // The recursive entry has to point to the new table itself
+            if kernel_entry.addr() == kernel_p4_frame.start_address() {
+                entry.set_frame(p4_frame, kernel_entry.flags());
+            } else {
+                *entry = kernel_entry.clone();
+            }
+        }
+
+        Ok(AddressSpace { p4_frame })
+    }
+
+    pub fn p4_frame(&self) -> PhysFrame {
+        self.p4_frame
+    }
+
+    /// Whether the CPU uses this address space.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.p4_frame
+    }
+
+    /// Makes this the address space of the CPU.
+    pub fn switch(&self) {
+        switch_to(self.p4_frame);
+    }
+
+    /// Maps `page` to a new, zeroed frame. The page must be in the user
+    /// part, it is mapped user accessible.
+    pub fn map(&mut self, page: Page, flags: PageTableFlags) -> Result<PhysFrame, MemoryError> {
+        self.check_user_page(page)?;
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+        self.with_mapper(|mapper, frame_allocator| {
+            let frame = zeroed_frame(frame_allocator).ok_or(MemoryError::OutOfFrames)?;
+
+            match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+                Ok(flush) => {
+                    flush.flush();
+                    Ok(frame)
+                }
+                Err(error) => {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    Err(error.into())
+                }
+            }
+        })
+    }
+
+    /// Reserves `count` pages from `start`, which are mapped to zeroed
+    /// frames on their first access.
+    pub fn map_lazy(
+        &mut self,
+        start: Page,
+        count: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), MemoryError> {
+        if count == 0 {
+            return Ok(());
+        }
+        self.check_user_page(start)?;
+        self.check_user_page(start + (count - 1))?;
+
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let p4_frame = self.p4_frame;
+        let pages = Page::range(start, start + count);
+
+        with_controller(|controller| {
+            let result =
+                lazy::reserve_pages(p4_frame, pages, flags, &mut controller.frame_allocator);
+            if result.is_err() {
+                lazy::release_pages(p4_frame, pages);
+            }
+            result
+        })
+    }
+
+    /// Unmaps `page` and frees its frame, or drops its reference if the
+    /// frame is shared.
+    pub fn unmap(&mut self, page: Page) -> Result<(), MemoryError> {
+        self.check_user_page(page)?;
+        let p4_frame = self.p4_frame;
+
+        self.with_mapper(|mapper, frame_allocator| match mapper.unmap(page) {
+            Ok((frame, flush)) => {
+                flush.flush();
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                Ok(())
+            }
+            // A lazy page which was never touched
+            Err(UnmapError::PageNotMapped) => {
+                match unsafe { paging::find_p1_entry(p4_frame, page) } {
+                    Some(entry) if lazy::is_lazy(entry) => {
+                        entry.set_unused();
+                        Ok(())
+                    }
+                    _ => Err(MemoryError::NotMapped),
+                }
+            }
+            Err(error) => Err(error.into()),
+        })
+    }
+
+    /// Creates an address space with the same user part as this one. The
+    /// frames are shared, and writable pages are copied on the first write
+    /// in either address space.
+    pub fn duplicate(&mut self) -> Result<AddressSpace, MemoryError> {
+        let kernel_p4_frame = kernel_p4().ok_or(MemoryError::Uninitialized)?;
+        let copy = AddressSpace::new()?;
+        let (p4_frame, copy_p4_frame) = (self.p4_frame, copy.p4_frame);
+
+        let result = with_controller(|controller| {
+            let frame_allocator = &mut controller.frame_allocator;
+            let kernel_p4 = unsafe { page_table(kernel_p4_frame) };
+            let p4 = unsafe { page_table(p4_frame) };
+
+            for i4 in p4_index(USER_SPACE_START)..p4_index(USER_SPACE_END) {
+                if !kernel_p4[i4].is_unused() {
+                    continue;
+                }
+                let p3 = match unsafe { next_table(&p4[i4])? } {
+                    Some(table) => table,
+                    None => continue,
+                };
+
+                for (i3, entry) in p3.iter().enumerate() {
+                    let p2 = match unsafe { next_table(entry)? } {
+                        Some(table) => table,
+                        None => continue,
+                    };
+
+                    for (i2, entry) in p2.iter().enumerate() {
+                        let p1 = match unsafe { next_table(entry)? } {
+                            Some(table) => table,
+                            None => continue,
+                        };
+
+                        for (i1, entry) in p1.iter_mut().enumerate() {
+                            if entry.is_unused() {
+                                continue;
+                            }
+
+                            let page = page_at(i4, i3, i2, i1);
+                            let target = unsafe {
+                                paging::create_p1_entry(
+                                    copy_p4_frame,
+                                    page,
+                                    PageTableFlags::USER_ACCESSIBLE,
+                                    frame_allocator,
+                                )?
+                            };
+                            cow::share_entry(entry, target, frame_allocator)?;
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        });
+
+        // Pages of this address space may have become read-only
+        if self.is_active() {
+            tlb::flush_all();
+        }
+
+        result.map(|()| copy)
+    }
+
+    /// Returns the physical address `address` is mapped to in this address
+    /// space.
+    pub fn translate(&self, address: VirtAddr) -> Result<PhysAddr, MemoryError> {
+        let page = Page::<Size4KiB>::containing_address(address);
+        let mapper =
+            unsafe { OffsetPageTable::new(page_table(self.p4_frame), physical_memory_offset()) };
+
+        match mapper.translate_page(page) {
+            Ok(frame) => Ok(frame.start_address() + (address - page.start_address())),
+            Err(TranslateError::PageNotMapped) => Err(MemoryError::NotMapped),
+            Err(TranslateError::ParentEntryHugePage) => Err(MemoryError::HugePage),
+            Err(TranslateError::InvalidFrameAddress(_)) => Err(MemoryError::InvalidFrame),
+        }
+    }
+
+    /// Runs `f` with a mapper for the page tables of this address space and
+    /// the frame allocator.
+    fn with_mapper<R, F>(&mut self, f: F) -> Result<R, MemoryError>
+    where
+        F: FnOnce(&mut OffsetPageTable, &mut AreaFrameAllocator) -> Result<R, MemoryError>,
+    {
+        let p4_frame = self.p4_frame;
+        with_controller(|controller| {
+            let p4 = unsafe { page_table(p4_frame) };
+            let mut mapper = unsafe { OffsetPageTable::new(p4, physical_memory_offset()) };
+            f(&mut mapper, &mut controller.frame_allocator)
+        })
+    }
+
+    /// Only the P4 entries in the user part which the kernel does not use
+    /// belong to the address space.
+    fn check_user_page(&self, page: Page) -> Result<(), MemoryError> {
+        let address = page.start_address().as_u64();
+        let kernel_p4_frame = kernel_p4().ok_or(MemoryError::Uninitialized)?;
+        let kernel_p4 = unsafe { page_table(kernel_p4_frame) };
+
+        if address < USER_SPACE_START
+            || address >= USER_SPACE_END
+            || !kernel_p4[page.p4_index()].is_unused()
+        {
+            return Err(MemoryError::NotUserAddress);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if self.is_active() {
+            switch_to_kernel();
+        }
+
+        let kernel_p4_frame = match kernel_p4() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let p4_frame = self.p4_frame;
+
+        let result = with_controller(|controller| {
+            let frame_allocator = &mut controller.frame_allocator;
+            let kernel_p4 = unsafe { page_table(kernel_p4_frame) };
+            let p4 = unsafe { page_table(p4_frame) };
+
+            for index in p4_index(USER_SPACE_START)..p4_index(USER_SPACE_END) {
+                let entry = &mut p4[index];
+                if entry.is_unused() || entry.addr() == kernel_p4[index].addr() {
+                    continue;
+                }
+
+                unsafe {
+                    free_table(
+                        PhysFrame::containing_address(entry.addr()),
+                        3,
+                        frame_allocator,
+                    )
+                };
+                entry.set_unused();
+            }
+
+            unsafe { frame_allocator.deallocate_frame(p4_frame) };
+            Ok(())
+        });
+
+        if let Err(error) = result {
+            warn!("Freeing address space {:?} failed: {}", p4_frame, error);
+        }
+    }
+}
+
+fn switch_to(p4_frame: PhysFrame) {
+    let (current, flags) = Cr3::read();
+    if current != p4_frame {
+        unsafe { Cr3::write(p4_frame, flags) };
+    }
+}
+
+fn p4_index(address: u64) -> usize {
+    (address / P4_ENTRY_SIZE) as usize
+}
+
+/// Returns the user page with the given table indices.
+fn page_at(i4: usize, i3: usize, i2: usize, i1: usize) -> Page {
+    let address = (i4 as u64) << 39 | (i3 as u64) << 30 | (i2 as u64) << 21 | (i1 as u64) << 12;
+    Page::containing_address(VirtAddr::new(address))
+}
+
+/// Returns the table `entry` points to, or `None` if it is not present.
+unsafe fn next_table<'a>(entry: &PageTableEntry) -> Result<Option<&'a mut PageTable>, MemoryError> {
+    let flags = entry.flags();
+    if !flags.contains(PageTableFlags::PRESENT) {
+        Ok(None)
+    } else if flags.contains(PageTableFlags::HUGE_PAGE) {
+        Err(MemoryError::HugePage)
+    } else {
+        Ok(Some(page_table(PhysFrame::containing_address(entry.addr()))))
+    }
+}
+
+/// Frees the table of `level` in `frame`, the tables below it and all
+/// frames they map.
+unsafe fn free_table(frame: PhysFrame, level: u8, frame_allocator: &mut AreaFrameAllocator) {
+    for entry in page_table(frame).iter() {
+        // Lazy pages which were never touched have no frame
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        let huge = entry.flags().contains(PageTableFlags::HUGE_PAGE);
+        match (level, huge) {
+            (3, true) => frame_allocator
+                .deallocate_frame(PhysFrame::<Size1GiB>::containing_address(entry.addr())),
+            (2, true) => frame_allocator
+                .deallocate_frame(PhysFrame::<Size2MiB>::containing_address(entry.addr())),
+            (1, _) => frame_allocator
+                .deallocate_frame(PhysFrame::<Size4KiB>::containing_address(entry.addr())),
+            _ => free_table(
+                PhysFrame::containing_address(entry.addr()),
+                level - 1,
+                frame_allocator,
+            ),
+        }
+    }
+
+    frame_allocator.deallocate_frame(frame);
+}
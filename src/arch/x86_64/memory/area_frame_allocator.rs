@@ -1,11 +1,25 @@
 use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegionType};
 use x86_64::structures::paging::{
-    FrameAllocator, FrameDeallocator, PhysFrame, frame::PhysFrameRange, Size4KiB,
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, frame::PhysFrameRange, Size1GiB,
+    Size2MiB, Size4KiB,
 };
+use core::ptr;
 use x86_64::PhysAddr;
 
+use super::paging::phys_to_virt;
+use super::MemoryError;
+
+/// Marks the end of the free list, frame 0 is never usable.
+const NO_FRAME: u64 = 0;
+
 pub struct AreaFrameAllocator {
     pub memory_map: MemoryMap,
+    /// Physical address of the last freed frame. Every free frame stores
+    /// the address of the next one in its first bytes.
+    free_list: u64,
+    /// Number of extra references to every usable frame, see `share_frame`.
+    /// Lives in frames taken from the memory map, through `phys_to_virt`.
+    shares: &'static mut [u16],
 }
 
 /// Use the boot info memory map to create a area frame allocator
@@ -16,12 +30,136 @@ impl AreaFrameAllocator {
             mm.add_region(reg.clone());
         }
 
-        AreaFrameAllocator { memory_map: mm }
+        let frames = mm
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable)
+            .map(|region| region.range.end_frame_number)
+            .max()
+            .unwrap_or(0);
+
+        let mut allocator = AreaFrameAllocator {
+            memory_map: mm,
+            free_list: NO_FRAME,
+            shares: &mut [],
+        };
+        allocator.shares = allocator.allocate_shares(frames as usize);
+        allocator
+    }
+
+    /// Allocates a zeroed reference count for `frames` frames.
+    fn allocate_shares(&mut self, frames: usize) -> &'static mut [u16] {
+        let bytes = (frames * 2) as u64;
+        let range = self
+            .allocate_contiguous((bytes + Size4KiB::SIZE - 1) / Size4KiB::SIZE)
+            .expect("No memory for the frame reference counts");
+
+        let shares = phys_to_virt(range.start.start_address()).as_mut_ptr::<u16>();
+        unsafe {
+            ptr::write_bytes(shares, 0, frames);
+            core::slice::from_raw_parts_mut(shares, frames)
+        }
+    }
+
+    /// Adds a reference to `frame`, for mapping it at one more place. The
+    /// frame is only freed when every reference was deallocated.
+    pub fn share_frame(&mut self, frame: PhysFrame<Size4KiB>) -> Result<(), MemoryError> {
+        let number = (frame.start_address().as_u64() / Size4KiB::SIZE) as usize;
+        let shares = self
+            .shares
+            .get_mut(number)
+            .ok_or(MemoryError::InvalidFrame)?;
+
+        *shares = shares
+            .checked_add(1)
+            .ok_or(MemoryError::TooManyReferences)?;
+        Ok(())
+    }
+
+    /// Returns how many references `frame` has.
+    pub fn references(&self, frame: PhysFrame<Size4KiB>) -> u32 {
+        let number = (frame.start_address().as_u64() / Size4KiB::SIZE) as usize;
+        self.shares
+            .get(number)
+            .map_or(1, |&shares| u32::from(shares) + 1)
+    }
+
+    /// Returns the link to the next free frame stored in `frame`.
+    fn link(&self, frame: u64) -> *mut u64 {
+        phys_to_virt(PhysAddr::new(frame)).as_mut_ptr()
+    }
+
+    fn pop_free_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if self.free_list == NO_FRAME {
+            return None;
+        }
+
+        let frame = self.free_list;
+        self.free_list = unsafe { *self.link(frame) };
+
+        Some(PhysFrame::containing_address(PhysAddr::new(frame)))
+    }
+
+    /// Allocates `count` physically contiguous frames.
+    pub fn allocate_contiguous(&mut self, count: u64) -> Option<PhysFrameRange<Size4KiB>> {
+        let region = self.memory_map.iter_mut().find(|region| {
+            region.region_type == MemoryRegionType::Usable
+                && region.range.end_frame_number - region.range.start_frame_number >= count
+        })?;
+
+        let start = region.range.start_frame_number;
+        region.range.start_frame_number += count;
+
+        let frame = |number: u64| PhysFrame::containing_address(PhysAddr::new(number * 4096));
+        Some(PhysFrame::range(frame(start), frame(start + count)))
+    }
+
+    /// Allocates `count` contiguous frames, starting at a frame number which
+    /// is a multiple of `align`. The frames skipped to get there go on the
+    /// free list.
+    pub fn allocate_aligned(&mut self, count: u64, align: u64) -> Option<PhysFrameRange<Size4KiB>> {
+        let align_up = |number: u64| (number + align - 1) / align * align;
+
+        let region = self.memory_map.iter_mut().find(|region| {
+            region.region_type == MemoryRegionType::Usable
+                && align_up(region.range.start_frame_number) + count
+                    <= region.range.end_frame_number
+        })?;
+
+        let skipped = region.range.start_frame_number;
+        let start = align_up(skipped);
+        region.range.start_frame_number = start + count;
+
+        let frame = |number: u64| PhysFrame::containing_address(PhysAddr::new(number * 4096));
+        for number in skipped..start {
+            unsafe { self.deallocate_frame(frame(number)) };
+        }
+
+        Some(PhysFrame::range(frame(start), frame(start + count)))
+    }
+
+    /// Allocates a frame of size `S`, made of aligned 4 KiB frames.
+    fn allocate_huge_frame<S: PageSize>(&mut self) -> Option<PhysFrame<S>> {
+        let count = S::SIZE / Size4KiB::SIZE;
+        let range = self.allocate_aligned(count, count)?;
+        Some(PhysFrame::containing_address(range.start.start_address()))
+    }
+
+    /// Puts the 4 KiB frames of `frame` on the free list.
+    unsafe fn deallocate_huge_frame<S: PageSize>(&mut self, frame: PhysFrame<S>) {
+        let start = PhysFrame::<Size4KiB>::containing_address(frame.start_address());
+        for i in 0..S::SIZE / Size4KiB::SIZE {
+            self.deallocate_frame(start + i);
+        }
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for AreaFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        // Freed frames first
+        if let Some(frame) = self.pop_free_frame() {
+            return Some(frame);
+        }
+
         // Find the next region with type usable
         let region = &mut self.memory_map
             .iter_mut()
@@ -33,10 +171,8 @@ unsafe impl FrameAllocator<Size4KiB> for AreaFrameAllocator {
         //
         // The region is borrowed.
         
-        let frame_range: &mut FrameRange = &mut region
-            .as_mut()
-            .expect("Could not find usable memory region")
-            .range;
+        // No usable memory left
+        let frame_range: &mut FrameRange = &mut region.as_mut()?.range;
 
         // if frame_range.start_frame_number == frame_range.end_frame_number {
         //     let type = &mut region.as_mut().expect("Could not find usable memory region").region_type;
@@ -84,7 +220,7 @@ unsafe impl FrameAllocator<Size4KiB> for AreaFrameAllocator {
             Finally we return the allocated PhysFrame.
         */
         if let Some(frame) = phys_range.next() {
-            kprintln!(
+            trace!(
                 "Allocating fr num: {}, last available: {}, phys frame: {:?}",
                 frame_range.start_frame_number, frame_range.end_frame_number, frame
             );
@@ -99,10 +235,48 @@ unsafe impl FrameAllocator<Size4KiB> for AreaFrameAllocator {
     }
 }
 
+/// Freed frames go on a free list, which is threaded through the frames
+/// themselves through `phys_to_virt`. A shared frame only loses a
+/// reference.
 impl FrameDeallocator<Size4KiB> for AreaFrameAllocator {
-    #[allow(unused)]
     unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
-        unimplemented!()
+        let address = frame.start_address().as_u64();
+        debug_assert_ne!(address, NO_FRAME, "frame 0 can not be freed");
+
+        if let Some(shares) = self.shares.get_mut((address / Size4KiB::SIZE) as usize) {
+            if *shares > 0 {
+                *shares -= 1;
+                return;
+            }
+        }
+
+        *self.link(address) = self.free_list;
+        self.free_list = address;
     }
 }
 
+
+unsafe impl FrameAllocator<Size2MiB> for AreaFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        self.allocate_huge_frame()
+    }
+}
+
+unsafe impl FrameAllocator<Size1GiB> for AreaFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size1GiB>> {
+        self.allocate_huge_frame()
+    }
+}
+
+/// Huge frames are split up, they go on the free list as 4 KiB frames.
+impl FrameDeallocator<Size2MiB> for AreaFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        self.deallocate_huge_frame(frame);
+    }
+}
+
+impl FrameDeallocator<Size1GiB> for AreaFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size1GiB>) {
+        self.deallocate_huge_frame(frame);
+    }
+}
@@ -0,0 +1,111 @@
+//! # Copy-on-write
+//!
+//! A frame can be mapped at several places, for example in a copy of an
+//! address space. Every mapping holds a reference to the frame, counted by
+//! the frame allocator, and deallocating the frame drops one of them.
+//!
+//! Writable pages are shared read-only and marked with `COW`. The first
+//! write to such a page faults, and `handle_fault` gives the page its own
+//! copy of the frame, or makes it writable again if no other mapping is
+//! left.
+use core::ptr;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    page_table::PageTableEntry, FrameAllocator, FrameDeallocator, Page, PageSize, PageTableFlags,
+    PhysFrame, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+use super::area_frame_allocator::AreaFrameAllocator;
+use super::lazy;
+use super::paging::{self, phys_to_virt};
+use super::MemoryError;
+
+/// Marks a page which is shared read-only, but may be written to.
+pub const COW: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Whether `entry` maps a shared page which is copied on a write.
+pub fn is_cow(entry: &PageTableEntry) -> bool {
+    let flags = entry.flags();
+    flags.contains(COW) && flags.contains(PageTableFlags::PRESENT)
+}
+
+/// Maps the page of the P1 entry `entry` in `target` too, which must be
+/// unused. Writable pages become copy-on-write in both entries. Lazy pages
+/// which were never touched are copied as they are.
+pub fn share_entry(
+    entry: &mut PageTableEntry,
+    target: &mut PageTableEntry,
+    frame_allocator: &mut AreaFrameAllocator,
+) -> Result<(), MemoryError> {
+    let flags = entry.flags();
+
+    if entry.is_unused() {
+        return Ok(());
+    } else if lazy::is_lazy(entry) {
+        *target = entry.clone();
+        return Ok(());
+    } else if !target.is_unused() {
+        return Err(MemoryError::AlreadyMapped);
+    }
+
+    let frame = PhysFrame::containing_address(entry.addr());
+    frame_allocator.share_frame(frame)?;
+
+    let flags = if flags.contains(PageTableFlags::WRITABLE) {
+        (flags - PageTableFlags::WRITABLE) | COW
+    } else {
+        flags
+    };
+    entry.set_flags(flags);
+    target.set_frame(frame, flags);
+
+    Ok(())
+}
+
+/// Makes the page containing `address` writable if it is a copy-on-write
+/// page of the active address space, which user code may access if the
+/// fault is from `user` mode. Returns false if it is not.
+pub fn handle_fault(
+    address: VirtAddr,
+    user: bool,
+    frame_allocator: &mut AreaFrameAllocator,
+) -> Result<bool, MemoryError> {
+    let (p4_frame, _) = Cr3::read();
+    let page = Page::containing_address(address);
+
+    let entry = match unsafe { paging::find_p1_entry(p4_frame, page) } {
+        Some(entry) if is_cow(entry) && lazy::allows(entry, user) => entry,
+        _ => return Ok(false),
+    };
+
+    let frame = PhysFrame::containing_address(entry.addr());
+    let flags = (entry.flags() - COW) | PageTableFlags::WRITABLE;
+
+    // The last mapping can keep the frame
+    if frame_allocator.references(frame) == 1 {
+        entry.set_flags(flags);
+        tlb::flush(address);
+        return Ok(true);
+    }
+
+    let copy = frame_allocator
+        .allocate_frame()
+        .ok_or(MemoryError::OutOfFrames)?;
+    unsafe {
+        ptr::copy_nonoverlapping(
+            phys_to_virt(frame.start_address()).as_ptr::<u8>(),
+            phys_to_virt(copy.start_address()).as_mut_ptr::<u8>(),
+            Size4KiB::SIZE as usize,
+        );
+    }
+    entry.set_frame(copy, flags);
+    tlb::flush(address);
+
+    // Drops the reference of this mapping
+    unsafe { frame_allocator.deallocate_frame(frame) };
+
+    trace!("Copied {:?} to {:?} for {:?}", frame, copy, address);
+    Ok(true)
+}
@@ -0,0 +1,107 @@
+//! Benchmark of the slab caches against the plain linked list heap.
+//!
+//! Both backends get their own region taken from the kernel heap, and run
+//! the same alloc/free pattern for every size. Time is measured in TSC
+//! cycles.
+use alloc::alloc::{alloc, dealloc};
+use core::alloc::Layout;
+use core::arch::x86_64::_rdtsc;
+use core::ptr::NonNull;
+
+use super::{Backend, LinkedListBackend, SlabBackend};
+
+const REGION_SIZE: usize = 4 * 1024 * 1024;
+const OBJECTS: usize = 256;
+const ROUNDS: usize = 16;
+
+/// Object sizes to benchmark.
+pub const SIZES: [usize; 6] = [16, 64, 256, 1024, 2048, 4096];
+
+/// Average cycles per allocation plus free for one object size.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchResult {
+    pub size: usize,
+    pub slab_cycles: u64,
+    pub linked_list_cycles: u64,
+}
+
+/// Memory for one allocator under test, freed on drop.
+struct Region {
+    start: *mut u8,
+    layout: Layout,
+}
+
+impl Region {
+    fn new() -> Region {
+        let layout = Layout::from_size_align(REGION_SIZE, 4096).unwrap();
+        let start = unsafe { alloc(layout) };
+        assert!(!start.is_null(), "no memory for the heap benchmark");
+        Region { start, layout }
+    }
+
+    fn init<B: Backend>(&self, mut backend: B) -> B {
+        unsafe {
+            backend.init(self.start as usize, REGION_SIZE);
+        }
+        backend
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.start, self.layout) };
+    }
+}
+
+/// Allocates `OBJECTS` objects, frees every other one, fills the holes
+/// again and frees everything, `ROUNDS` times. Returns the average cycles
+/// per allocation and free.
+fn workload<B: Backend>(allocator: &mut B, size: usize) -> u64 {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    let mut objects: [Option<NonNull<u8>>; OBJECTS] = [None; OBJECTS];
+    let mut operations = 0;
+
+    let start = unsafe { _rdtsc() };
+
+    for _ in 0..ROUNDS {
+        for object in objects.iter_mut() {
+            *object = allocator.allocate(layout);
+        }
+        for object in objects.iter_mut().step_by(2) {
+            if let Some(ptr) = object.take() {
+                unsafe { allocator.deallocate(ptr, layout) };
+            }
+        }
+        for object in objects.iter_mut().step_by(2) {
+            *object = allocator.allocate(layout);
+        }
+        for object in objects.iter_mut() {
+            if let Some(ptr) = object.take() {
+                unsafe { allocator.deallocate(ptr, layout) };
+            }
+        }
+        operations += OBJECTS + OBJECTS / 2;
+    }
+
+    let cycles = unsafe { _rdtsc() } - start;
+    cycles / operations as u64
+}
+
+/// Runs the benchmark and calls `report` with the result for every size.
+pub fn run<F: FnMut(&BenchResult)>(mut report: F) {
+    for &size in SIZES.iter() {
+        let region = Region::new();
+        let mut linked_list = region.init(LinkedListBackend::new());
+        let linked_list_cycles = workload(&mut linked_list, size);
+
+        let region = Region::new();
+        let mut slab = region.init(SlabBackend::new());
+        let slab_cycles = workload(&mut slab, size);
+
+        report(&BenchResult {
+            size,
+            slab_cycles,
+            linked_list_cycles,
+        });
+    }
+}
@@ -1,69 +1,129 @@
-// use core::alloc::{Alloc, AllocErr, Layout};
-// use core::ptr::NonNull;
-// use core::sync::atomic::{AtomicUsize, Ordering};
-
-// /// A simple allocator that allocates memory linearly and ignores freed memory.
-// #[derive(Debug)]
-// pub struct BumpAllocator {
-//     heap_start: usize,
-//     heap_end: usize,
-//     next: AtomicUsize,
-// }
-
-// impl BumpAllocator {
-//     pub fn new(heap_start: usize, heap_end: usize) -> Self {
-//         Self {
-//             heap_start,
-//             heap_end,
-//             next: AtomicUsize::new(heap_start),
-//         }
-//     }
-// }
-
-
-
-// unsafe impl<'a> Alloc for &'a BumpAllocator {
-//     unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
-//         loop {
-//             // load current state of the `next` field
-//             let current_next = self.next.load(Ordering::Relaxed);
-//             let alloc_start = align_up(current_next, layout.align());
-//             let alloc_end = alloc_start.saturating_add(layout.size());
-
-//             if alloc_end <= self.heap_end {
-//                 // update the `next` pointer if it still has the value `current_next`
-//                 let next_now =
-//                     self.next
-//                         .compare_and_swap(current_next, alloc_end, Ordering::Relaxed);
-//                 if next_now == current_next {
-//                     // next address was successfully updated, allocation succeeded
-//                     return Ok(NonNull::new(alloc_start as *mut u8).unwrap());
-//                 }
-//             } else {
-//                 return Err(AllocErr);
-//             }
-//         }
-//     }
-
-//     unsafe fn dealloc(&mut self, _ptr: NonNull<u8>, _layout: Layout) {
-//         // do nothing, leak memory
-//     }
-// }
-
-// /// Align downwards. Returns the greatest x with alignment `align`
-// /// so that x <= addr. The alignment must be a power of 2.
-// pub fn align_down(addr: usize, align: usize) -> usize {
-//     if align.is_power_of_two() {
-//         addr & !(align - 1)
-//     } else if align == 0 {
-//         addr
-//     } else {
-//         panic!("`align` must be a power of 2");
-//     }
-// }
-
-// /// Align upwards. Returns the smallest x with alignment `align`
-// /// so that x >= addr. The alignment must be a power of 2.
-// pub fn align_up(addr: usize, align: usize) -> usize {
-//     align_down(addr + align - 1, align)
-// }
\ No newline at end of file
+//! A bump allocator backend.
+//!
+//! Allocates memory linearly and only reclaims it when every allocation
+//! has been freed. Fast and predictable, but the heap only shrinks back
+//! when it is completely empty.
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use super::Backend;
+
+pub struct BumpBackend {
+    bottom: usize,
+    top: usize,
+    next: usize,
+    allocations: usize,
+}
+
+impl BumpBackend {
+    pub const fn new() -> BumpBackend {
+        BumpBackend {
+            bottom: 0,
+            top: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+}
+
+impl Backend for BumpBackend {
+    const NAME: &'static str = "bump";
+
+    unsafe fn init(&mut self, bottom: usize, size: usize) {
+        self.bottom = bottom;
+        self.top = bottom + size;
+        self.next = bottom;
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let start = align_up(self.next, layout.align());
+        let end = start.checked_add(layout.size())?;
+
+        if end > self.top {
+            return None;
+        }
+
+        self.next = end;
+        self.allocations += 1;
+        NonNull::new(start as *mut u8)
+    }
+
+    unsafe fn deallocate(&mut self, _ptr: NonNull<u8>, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.bottom;
+        }
+    }
+
+    unsafe fn extend(&mut self, by: usize) {
+        self.top += by;
+    }
+
+    fn top(&self) -> usize {
+        self.top
+    }
+
+    fn size(&self) -> usize {
+        self.top - self.bottom
+    }
+
+    fn used(&self) -> usize {
+        self.next - self.bottom
+    }
+}
+
+/// Align upwards. Returns the smallest x with alignment `align`
+/// so that x >= addr. The alignment must be a power of 2.
+pub fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn allocates_linearly_and_aligned() {
+        let mut bump = BumpBackend::new();
+        unsafe { bump.init(0x1000, 0x100) };
+
+        let first = bump.allocate(layout(3, 1)).unwrap();
+        let second = bump.allocate(layout(8, 8)).unwrap();
+
+        assert_eq!(first.as_ptr() as usize, 0x1000);
+        assert_eq!(second.as_ptr() as usize, 0x1008);
+        assert_eq!(bump.used(), 0x10);
+    }
+
+    #[test]
+    fn full() {
+        let mut bump = BumpBackend::new();
+        unsafe { bump.init(0x1000, 0x100) };
+
+        assert!(bump.allocate(layout(0x100, 1)).is_some());
+        assert!(bump.allocate(layout(1, 1)).is_none());
+
+        unsafe { bump.extend(0x100) };
+        assert!(bump.allocate(layout(1, 1)).is_some());
+        assert_eq!(bump.size(), 0x200);
+    }
+
+    #[test]
+    fn resets_when_empty() {
+        let mut bump = BumpBackend::new();
+        unsafe { bump.init(0x1000, 0x100) };
+
+        let first = bump.allocate(layout(16, 8)).unwrap();
+        let second = bump.allocate(layout(16, 8)).unwrap();
+
+        unsafe { bump.deallocate(first, layout(16, 8)) };
+        assert_eq!(bump.used(), 32);
+
+        unsafe { bump.deallocate(second, layout(16, 8)) };
+        assert_eq!(bump.used(), 0);
+    }
+}
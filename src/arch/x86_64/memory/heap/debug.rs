@@ -0,0 +1,418 @@
+//! A debugging heap backend, enabled with the `heap-debug` feature.
+//!
+//! Wraps another backend and adds to every allocation:
+//!
+//! - a header with the size, a sequence number and the call sites, which
+//!   links the allocation into a list of live allocations
+//! - redzones of `REDZONE_SIZE` bytes before and after the data, which
+//!   are checked when the allocation is freed
+//!
+//! New allocations are filled with `ALLOC_POISON` and freed ones with
+//! `FREE_POISON`, so use of uninitialized or freed memory stands out.
+//!
+//! The call sites are found by walking the frame pointers, the return
+//! addresses can be resolved with `addr2line -e target/.../rust_kernel`.
+//! Frame pointers are only kept with `-Cforce-frame-pointers=yes`, which
+//! `make heap-debug` passes. The walk stays inside the kernel stack it
+//! runs on, so without them it records nothing useful but does not fault.
+//! Allocations on the boot stack, or made while the memory controller is
+//! locked, get no call sites.
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+
+use super::slab::{CacheStats, CACHE_COUNT};
+use super::Backend;
+
+pub const REDZONE_SIZE: usize = 16;
+pub const REDZONE_BYTE: u8 = 0xFD;
+pub const ALLOC_POISON: u8 = 0xCD;
+pub const FREE_POISON: u8 = 0xDD;
+
+/// Number of return addresses recorded per allocation.
+pub const CALL_SITES: usize = 6;
+
+const MAGIC: u64 = 0x4B48_4541_5044_4247; // "GBDPAEHK"
+const FREED_MAGIC: u64 = 0x4445_4546_5044_4247; // "GBDPFEED"
+
+/// Stored right before the front redzone of every allocation.
+#[repr(C)]
+struct Header {
+    previous: Option<NonNull<Header>>,
+    next: Option<NonNull<Header>>,
+    /// The layout the caller asked for.
+    size: usize,
+    align: usize,
+    sequence: u64,
+    call_sites: [usize; CALL_SITES],
+    /// Last, so it survives the free list node the inner backend writes
+    /// at the start of freed memory.
+    magic: u64,
+}
+
+/// A live allocation, as reported by `DebugBackend::for_each_allocation`.
+#[derive(Debug, Clone, Copy)]
+pub struct AllocationInfo {
+    pub address: usize,
+    pub size: usize,
+    /// Allocations are numbered in the order they were made.
+    pub sequence: u64,
+    /// Return addresses, innermost first, zero when the stack ended.
+    pub call_sites: [usize; CALL_SITES],
+    pub redzones_intact: bool,
+}
+
+pub struct DebugBackend<B> {
+    inner: B,
+    /// The most recent allocation.
+    head: Option<NonNull<Header>>,
+    sequence: u64,
+}
+
+// The header list points into memory owned by the inner backend.
+unsafe impl<B: Send> Send for DebugBackend<B> {}
+
+impl<B> DebugBackend<B> {
+    pub const fn new(inner: B) -> DebugBackend<B> {
+        DebugBackend {
+            inner,
+            head: None,
+            sequence: 0,
+        }
+    }
+}
+
+/// Where the parts of an allocation are.
+struct Frame {
+    /// Layout of the whole allocation in the inner backend.
+    outer: Layout,
+    /// Offset of the data from the start of the allocation.
+    data_offset: usize,
+}
+
+impl Frame {
+    fn new(layout: &Layout) -> Option<Frame> {
+        let align = layout.align().max(align_of::<Header>());
+        let data_offset = round_up(size_of::<Header>() + REDZONE_SIZE, align);
+        let size = data_offset + layout.size() + REDZONE_SIZE;
+
+        Some(Frame {
+            outer: Layout::from_size_align(size, align).ok()?,
+            data_offset,
+        })
+    }
+}
+
+impl<B: Backend> DebugBackend<B> {
+    /// Calls `f` for every live allocation, the most recent first.
+    pub fn for_each_allocation(&self, f: &mut dyn FnMut(&AllocationInfo)) {
+        let mut current = self.head;
+
+        while let Some(header) = current {
+            let header = unsafe { header.as_ref() };
+            let data = data_of(header);
+
+            f(&AllocationInfo {
+                address: data as usize,
+                size: header.size,
+                sequence: header.sequence,
+                call_sites: header.call_sites,
+                redzones_intact: unsafe { redzones_intact(data, header.size) },
+            });
+
+            current = header.next;
+        }
+    }
+
+    unsafe fn unlink(&mut self, header: &mut Header) {
+        match header.previous {
+            Some(mut previous) => previous.as_mut().next = header.next,
+            None => self.head = header.next,
+        }
+        if let Some(mut next) = header.next {
+            next.as_mut().previous = header.previous;
+        }
+    }
+}
+
+impl<B: Backend> Backend for DebugBackend<B> {
+    const NAME: &'static str = "debug";
+
+    unsafe fn init(&mut self, bottom: usize, size: usize) {
+        self.inner.init(bottom, size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let frame = Frame::new(&layout)?;
+        let start = self.inner.allocate(frame.outer)?.as_ptr();
+
+        self.sequence += 1;
+
+        unsafe {
+            let data = start.add(frame.data_offset);
+            let header = header_of(data);
+
+            header.write(Header {
+                previous: None,
+                next: self.head,
+                size: layout.size(),
+                align: layout.align(),
+                sequence: self.sequence,
+                call_sites: call_sites(),
+                magic: MAGIC,
+            });
+            if let Some(mut head) = self.head {
+                head.as_mut().previous = NonNull::new(header);
+            }
+            self.head = NonNull::new(header);
+
+            ptr::write_bytes(data.sub(REDZONE_SIZE), REDZONE_BYTE, REDZONE_SIZE);
+            ptr::write_bytes(data, ALLOC_POISON, layout.size());
+            ptr::write_bytes(data.add(layout.size()), REDZONE_BYTE, REDZONE_SIZE);
+
+            NonNull::new(data)
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let data = ptr.as_ptr();
+        let header = &mut *header_of(data);
+
+        match header.magic {
+            MAGIC => {}
+            FREED_MAGIC => panic!("heap: double free of {:p}", data),
+            _ => panic!("heap: free of {:p}, which is not allocated", data),
+        }
+
+        if header.size != layout.size() || header.align != layout.align() {
+            panic!(
+                "heap: {:p} allocated with size {} align {}, freed with size {} align {}",
+                data,
+                header.size,
+                header.align,
+                layout.size(),
+                layout.align()
+            );
+        }
+
+        if !redzones_intact(data, header.size) {
+            panic!(
+                "heap: redzone of {:p} ({} bytes, allocation #{}) overwritten, allocated at {:x?}",
+                data, header.size, header.sequence, header.call_sites
+            );
+        }
+
+        self.unlink(header);
+
+        let frame = Frame::new(&layout).expect("invalid layout");
+        let start = data.sub(frame.data_offset);
+
+        ptr::write_bytes(start, FREE_POISON, frame.outer.size());
+        (*header_of(data)).magic = FREED_MAGIC;
+
+        self.inner.deallocate(NonNull::new_unchecked(start), frame.outer);
+    }
+
+    unsafe fn extend(&mut self, by: usize) {
+        self.inner.extend(by);
+    }
+
+    fn top(&self) -> usize {
+        self.inner.top()
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    fn used(&self) -> usize {
+        self.inner.used()
+    }
+
+    fn cache_stats(&self) -> Option<[CacheStats; CACHE_COUNT]> {
+        self.inner.cache_stats()
+    }
+
+    fn for_each_allocation(&self, f: &mut dyn FnMut(&AllocationInfo)) -> bool {
+        DebugBackend::for_each_allocation(self, f);
+        true
+    }
+}
+
+fn header_of(data: *mut u8) -> *mut Header {
+    unsafe { data.sub(REDZONE_SIZE + size_of::<Header>()) as *mut Header }
+}
+
+fn data_of(header: &Header) -> *mut u8 {
+    unsafe { (header as *const Header as *mut u8).add(size_of::<Header>() + REDZONE_SIZE) }
+}
+
+unsafe fn redzones_intact(data: *mut u8, size: usize) -> bool {
+    let front = core::slice::from_raw_parts(data.sub(REDZONE_SIZE), REDZONE_SIZE);
+    let back = core::slice::from_raw_parts(data.add(size), REDZONE_SIZE);
+
+    front
+        .iter()
+        .chain(back.iter())
+        .all(|&byte| byte == REDZONE_BYTE)
+}
+
+fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+/// Returns the return addresses of the callers of the allocator, found by
+/// following the saved frame pointers.
+#[cfg(not(test))]
+#[inline(never)]
+fn call_sites() -> [usize; CALL_SITES] {
+    use x86_64::structures::paging::{PageSize, Size4KiB};
+    use x86_64::VirtAddr;
+
+    // Frames of the allocator itself: DebugBackend::allocate and the
+    // KernelHeap functions.
+    const SKIP: usize = 2;
+
+    let mut call_sites = [0; CALL_SITES];
+    let mut frame: usize;
+    let stack_pointer: u64;
+    unsafe {
+        llvm_asm!("mov $0, rbp" : "=r"(frame) : : : "intel");
+        llvm_asm!("mov $0, rsp" : "=r"(stack_pointer) : : : "intel");
+    }
+
+    // Only frames on the mapped part of the current stack are read
+    let stack = match super::super::stack_containing(VirtAddr::new(stack_pointer)) {
+        Some(stack) => stack,
+        None => return call_sites,
+    };
+    let bottom = (stack.start + Size4KiB::SIZE).as_u64() as usize;
+    let top = stack.end().as_u64() as usize;
+
+    for i in 0..SKIP + CALL_SITES {
+        // Stop at the end of the stack or on a frame pointer which does
+        // not look like one.
+        if frame < bottom || frame + 16 > top || frame % 8 != 0 {
+            break;
+        }
+
+        let (next, return_address) =
+            unsafe { (*(frame as *const usize), *((frame + 8) as *const usize)) };
+
+        if i >= SKIP {
+            call_sites[i - SKIP] = return_address;
+        }
+
+        if next <= frame {
+            break;
+        }
+        frame = next;
+    }
+
+    call_sites
+}
+
+#[cfg(test)]
+fn call_sites() -> [usize; CALL_SITES] {
+    [0; CALL_SITES]
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::LinkedListBackend;
+    use super::*;
+    use std::alloc::alloc;
+    use std::vec::Vec;
+
+    const MEMORY_SIZE: usize = 64 * 1024;
+
+    fn backend() -> DebugBackend<LinkedListBackend> {
+        let layout = Layout::from_size_align(MEMORY_SIZE, 4096).unwrap();
+        let memory = unsafe { alloc(layout) };
+
+        let mut backend = DebugBackend::new(LinkedListBackend::new());
+        unsafe { backend.init(memory as usize, MEMORY_SIZE) };
+        backend
+    }
+
+    fn live(backend: &DebugBackend<LinkedListBackend>) -> Vec<AllocationInfo> {
+        let mut allocations = Vec::new();
+        backend.for_each_allocation(&mut |allocation| allocations.push(*allocation));
+        allocations
+    }
+
+    #[test]
+    fn tracks_live_allocations() {
+        let mut backend = backend();
+        let layout = Layout::from_size_align(24, 8).unwrap();
+
+        let first = backend.allocate(layout).unwrap();
+        let second = backend.allocate(layout).unwrap();
+        let third = backend.allocate(layout).unwrap();
+
+        unsafe { backend.deallocate(second, layout) };
+
+        let allocations = live(&backend);
+        assert_eq!(allocations.len(), 2);
+        assert_eq!(allocations[0].address, third.as_ptr() as usize);
+        assert_eq!(allocations[0].sequence, 3);
+        assert_eq!(allocations[1].address, first.as_ptr() as usize);
+        assert!(allocations
+            .iter()
+            .all(|allocation| allocation.redzones_intact));
+
+        unsafe {
+            backend.deallocate(first, layout);
+            backend.deallocate(third, layout);
+        }
+        assert!(live(&backend).is_empty());
+        assert_eq!(backend.used(), 0);
+    }
+
+    #[test]
+    fn alignment_and_poison() {
+        let mut backend = backend();
+        let layout = Layout::from_size_align(100, 256).unwrap();
+
+        let data = backend.allocate(layout).unwrap().as_ptr();
+        assert_eq!(data as usize % 256, 0);
+
+        let bytes = unsafe { core::slice::from_raw_parts(data, 100) };
+        assert!(bytes.iter().all(|&byte| byte == ALLOC_POISON));
+
+        unsafe { backend.deallocate(NonNull::new_unchecked(data), layout) };
+        let bytes = unsafe { core::slice::from_raw_parts(data, 100) };
+        assert!(bytes.iter().all(|&byte| byte == FREE_POISON));
+    }
+
+    #[test]
+    #[should_panic(expected = "redzone")]
+    fn detects_overflow() {
+        let mut backend = backend();
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        let data = backend.allocate(layout).unwrap();
+        unsafe {
+            *data.as_ptr().add(16) = 0;
+        }
+
+        assert!(!live(&backend)[0].redzones_intact);
+        unsafe { backend.deallocate(data, layout) };
+    }
+
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn detects_double_free() {
+        let mut backend = backend();
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        // Keep the memory allocated in the inner backend, so the header is
+        // not reused
+        let _other = backend.allocate(layout).unwrap();
+        let data = backend.allocate(layout).unwrap();
+        unsafe {
+            backend.deallocate(data, layout);
+            backend.deallocate(data, layout);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! The kernel heap, which grows on demand.
+//!
+//! When an allocation does not fit, the heap asks its grow function to map
+//! more memory directly after the current top and extends its backend,
+//! until it reaches its limit. The new top is aligned to `GROW_STEP`, so
+//! the grow function gets whole 2 MiB chunks it can map with huge pages.
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+
+use super::slab::{CacheStats, CACHE_COUNT};
+use super::{AllocationInfo, Backend};
+
+/// The heap grows to a multiple of this address, the size of a huge page.
+pub const GROW_STEP: usize = 2 * 1024 * 1024;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Maps `size` bytes of memory at `start`, returns false if that failed.
+pub type GrowFn = fn(start: usize, size: usize) -> bool;
+
+/// A snapshot of the heap usage.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    /// Bytes currently backed by memory.
+    pub size: usize,
+    /// The size the heap may grow to.
+    pub limit: usize,
+    /// Bytes handed out by the backend, including alignment padding and
+    /// free objects in the slab caches.
+    pub used: usize,
+    pub free: usize,
+    /// The highest `used` value so far.
+    pub peak: usize,
+    /// Bytes the callers asked for.
+    pub requested: usize,
+    /// Number of allocations which are currently alive.
+    pub allocations: usize,
+    /// How many times the heap was grown.
+    pub grow_count: usize,
+}
+
+impl HeapStats {
+    /// Percentage of the used memory lost to padding, rounding and free
+    /// slab objects.
+    pub fn fragmentation(&self) -> usize {
+        if self.used == 0 {
+            return 0;
+        }
+        (self.used - self.requested.min(self.used)) * 100 / self.used
+    }
+}
+
+struct Inner<B> {
+    backend: B,
+    limit: usize,
+    grow: Option<GrowFn>,
+    stats: HeapStats,
+}
+
+impl<B: Backend> Inner<B> {
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        match self.backend.allocate(layout) {
+            Some(allocation) => Some(allocation),
+            None if self.grow(&layout) => self.backend.allocate(layout),
+            None => None,
+        }
+    }
+
+    /// Grows the heap so an allocation with `layout` fits at its top.
+    fn grow(&mut self, layout: &Layout) -> bool {
+        let grow = match self.grow {
+            Some(grow) => grow,
+            None => return false,
+        };
+
+        // The free space at the top may be too small, so the allocation may
+        // need all of its size plus the alignment padding in new memory.
+        let needed = align_up(layout.size() + layout.align(), PAGE_SIZE);
+        let available = self.limit - self.backend.size();
+        if needed > available {
+            return false;
+        }
+
+        let top = self.backend.top();
+        let by = (align_up(top + needed, GROW_STEP) - top).min(available);
+        if !grow(top, by) {
+            return false;
+        }
+
+        unsafe {
+            self.backend.extend(by);
+        }
+        self.stats.grow_count += 1;
+        true
+    }
+}
+
+pub struct KernelHeap<B> {
+    inner: Mutex<Inner<B>>,
+}
+
+impl<B> KernelHeap<B> {
+    /// Creates an empty heap. All allocations fail until it is initialized.
+    pub const fn new(backend: B) -> KernelHeap<B> {
+        KernelHeap {
+            inner: Mutex::new(Inner {
+                backend,
+                limit: 0,
+                grow: None,
+                stats: HeapStats {
+                    size: 0,
+                    limit: 0,
+                    used: 0,
+                    free: 0,
+                    peak: 0,
+                    requested: 0,
+                    allocations: 0,
+                    grow_count: 0,
+                },
+            }),
+        }
+    }
+}
+
+impl<B: Backend> KernelHeap<B> {
+    /// Initializes the heap with `size` mapped bytes at `bottom`. The heap
+    /// grows up to `limit` bytes by calling `grow`.
+    ///
+    /// # Unsafety
+    ///
+    /// `bottom..bottom + size` must be mapped and unused, and the range up
+    /// to `bottom + limit` must be reserved for the heap. Must be called at
+    /// most once.
+    pub unsafe fn init(&self, bottom: usize, size: usize, limit: usize, grow: GrowFn) {
+        let mut inner = self.inner.lock();
+        inner.backend.init(bottom, size);
+        inner.limit = limit.max(size);
+        inner.grow = Some(grow);
+    }
+
+    /// Name of the backend.
+    pub fn backend_name(&self) -> &'static str {
+        B::NAME
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let inner = self.inner.lock();
+        let backend = &inner.backend;
+        HeapStats {
+            size: backend.size(),
+            limit: inner.limit,
+            used: backend.used(),
+            free: backend.size() - backend.used(),
+            ..inner.stats
+        }
+    }
+
+    /// Returns the statistics of the slab caches, if the backend has them.
+    pub fn cache_stats(&self) -> Option<[CacheStats; CACHE_COUNT]> {
+        self.inner.lock().backend.cache_stats()
+    }
+
+    /// Calls `f` for every live allocation, if the backend tracks them.
+    /// The heap is locked, so `f` must not allocate.
+    pub fn for_each_allocation(&self, f: &mut dyn FnMut(&AllocationInfo)) -> bool {
+        self.inner.lock().backend.for_each_allocation(f)
+    }
+}
+
+unsafe impl<B: Backend> GlobalAlloc for KernelHeap<B> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut inner = self.inner.lock();
+
+        match inner.allocate(layout) {
+            Some(allocation) => {
+                let used = inner.backend.used();
+                let stats = &mut inner.stats;
+                stats.requested += layout.size();
+                stats.allocations += 1;
+                stats.peak = stats.peak.max(used);
+                allocation.as_ptr()
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut inner = self.inner.lock();
+        inner.backend.deallocate(NonNull::new_unchecked(ptr), layout);
+        inner.stats.requested -= layout.size();
+        inner.stats.allocations -= 1;
+    }
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{BumpBackend, LinkedListBackend, SlabBackend};
+    use super::*;
+
+    const MEMORY_SIZE: usize = 4 * GROW_STEP;
+
+    fn grow_ok(_start: usize, _size: usize) -> bool {
+        true
+    }
+
+    fn grow_fail(_start: usize, _size: usize) -> bool {
+        false
+    }
+
+    /// Creates a heap in `GROW_STEP` aligned memory of `MEMORY_SIZE`
+    /// bytes, which is leaked.
+    fn heap_with<B: Backend>(backend: B, size: usize, limit: usize, grow: GrowFn) -> KernelHeap<B> {
+        let layout = Layout::from_size_align(MEMORY_SIZE, GROW_STEP).unwrap();
+        let memory = unsafe { std::alloc::alloc(layout) };
+        assert!(!memory.is_null());
+
+        let heap = KernelHeap::new(backend);
+        unsafe {
+            heap.init(memory as usize, size, limit, grow);
+        }
+        heap
+    }
+
+    fn heap(size: usize, limit: usize, grow: GrowFn) -> KernelHeap<LinkedListBackend> {
+        heap_with(LinkedListBackend::new(), size, limit, grow)
+    }
+
+    #[test]
+    fn grows_when_full() {
+        let heap = heap(PAGE_SIZE, MEMORY_SIZE, grow_ok);
+
+        let layout = Layout::from_size_align(2 * PAGE_SIZE, 8).unwrap();
+        let allocation = unsafe { heap.alloc(layout) };
+        assert!(!allocation.is_null());
+
+        let stats = heap.stats();
+        assert_eq!(stats.grow_count, 1);
+        // Up to the next step
+        assert_eq!(stats.size, GROW_STEP);
+
+        unsafe { heap.dealloc(allocation, layout) };
+        assert_eq!(heap.stats().allocations, 0);
+        assert_eq!(heap.stats().peak, 2 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn stops_at_limit() {
+        let heap = heap(PAGE_SIZE, 2 * PAGE_SIZE, grow_ok);
+
+        let layout = Layout::from_size_align(4 * PAGE_SIZE, 8).unwrap();
+        assert!(unsafe { heap.alloc(layout) }.is_null());
+
+        assert_eq!(heap.stats().size, PAGE_SIZE);
+    }
+
+    #[test]
+    fn failed_grow() {
+        let heap = heap(PAGE_SIZE, MEMORY_SIZE, grow_fail);
+
+        let layout = Layout::from_size_align(2 * PAGE_SIZE, 8).unwrap();
+        assert!(unsafe { heap.alloc(layout) }.is_null());
+        assert_eq!(heap.stats().grow_count, 0);
+    }
+
+    #[test]
+    fn small_allocations_use_slabs() {
+        let heap = heap_with(SlabBackend::new(), PAGE_SIZE, MEMORY_SIZE, grow_ok);
+
+        let layout = Layout::from_size_align(12, 4).unwrap();
+        let first = unsafe { heap.alloc(layout) };
+        let second = unsafe { heap.alloc(layout) };
+        assert_eq!(second as usize - first as usize, 16);
+
+        // One slab of the 16 byte cache, which takes the whole first page
+        assert_eq!(heap.stats().used, PAGE_SIZE);
+        assert_eq!(heap.cache_stats().unwrap()[1].in_use, 2);
+
+        unsafe {
+            heap.dealloc(first, layout);
+            heap.dealloc(second, layout);
+        }
+        assert_eq!(heap.cache_stats().unwrap()[1].in_use, 0);
+        assert_eq!(heap.stats().requested, 0);
+    }
+
+    #[test]
+    fn bump_backend_grows() {
+        let heap = heap_with(BumpBackend::new(), PAGE_SIZE, MEMORY_SIZE, grow_ok);
+
+        let layout = Layout::from_size_align(PAGE_SIZE, 8).unwrap();
+        let first = unsafe { heap.alloc(layout) };
+        let second = unsafe { heap.alloc(layout) };
+
+        assert_eq!(second as usize - first as usize, PAGE_SIZE);
+        assert_eq!(heap.stats().grow_count, 1);
+        assert!(heap.cache_stats().is_none());
+    }
+
+    #[test]
+    fn fragmentation() {
+        let stats = HeapStats {
+            used: 200,
+            requested: 150,
+            ..HeapStats::default()
+        };
+
+        assert_eq!(stats.fragmentation(), 25);
+        assert_eq!(HeapStats::default().fragmentation(), 0);
+    }
+}
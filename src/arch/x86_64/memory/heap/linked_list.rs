@@ -0,0 +1,86 @@
+//! The linked list heap backend, a first fit allocator from the
+//! `linked_list_allocator` crate.
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use linked_list_allocator::Heap;
+
+use super::Backend;
+
+pub struct LinkedListBackend {
+    heap: Heap,
+}
+
+impl LinkedListBackend {
+    pub const fn new() -> LinkedListBackend {
+        LinkedListBackend {
+            heap: Heap::empty(),
+        }
+    }
+}
+
+impl Backend for LinkedListBackend {
+    const NAME: &'static str = "linked list";
+
+    unsafe fn init(&mut self, bottom: usize, size: usize) {
+        self.heap.init(bottom, size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        self.heap.allocate_first_fit(layout).ok()
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.heap.deallocate(ptr, layout);
+    }
+
+    unsafe fn extend(&mut self, by: usize) {
+        self.heap.extend(by);
+    }
+
+    fn top(&self) -> usize {
+        self.heap.top()
+    }
+
+    fn size(&self) -> usize {
+        self.heap.size()
+    }
+
+    fn used(&self) -> usize {
+        self.heap.used()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::alloc::{alloc, dealloc};
+
+    const SIZE: usize = 4096;
+
+    #[test]
+    fn allocate_free_and_extend() {
+        let region_layout = Layout::from_size_align(2 * SIZE, 4096).unwrap();
+        let region = unsafe { alloc(region_layout) } as usize;
+
+        let mut backend = LinkedListBackend::new();
+        unsafe { backend.init(region, SIZE) };
+
+        let layout = Layout::from_size_align(SIZE, 8).unwrap();
+        let allocation = backend.allocate(layout).unwrap();
+        assert_eq!(allocation.as_ptr() as usize, region);
+        assert_eq!(backend.used(), SIZE);
+        assert!(backend.allocate(layout).is_none());
+
+        unsafe { backend.extend(SIZE) };
+        assert_eq!(backend.top(), region + 2 * SIZE);
+        let second = backend.allocate(layout).unwrap();
+
+        unsafe {
+            backend.deallocate(allocation, layout);
+            backend.deallocate(second, layout);
+        }
+        assert_eq!(backend.used(), 0);
+
+        unsafe { dealloc(region as *mut u8, region_layout) };
+    }
+}
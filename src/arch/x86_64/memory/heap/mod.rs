@@ -1,63 +1,153 @@
+//! # Kernel heap
+//!
+//! The heap allocator is split in `KernelHeap`, which implements
+//! `GlobalAlloc`, grows the heap and keeps statistics, and a `Backend`
+//! which manages the memory. The backend is selected with a cargo feature:
+//!
+//! - `heap-slab` (default): slab caches in front of a linked list heap
+//! - `heap-linked-list`: a first fit linked list heap
+//! - `heap-bump`: a bump allocator, for debugging and comparison
+//!
+//! When several are enabled, bump wins over linked list, which wins over
+//! slab.
+//!
+//! The `heap-debug` feature wraps the backend in a `DebugBackend`, which
+//! adds redzones, poisoning and tracking of live allocations.
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr::NonNull;
+
+pub mod bench;
 pub mod bump_allocator;
+pub mod debug;
+mod kernel_heap;
+pub mod linked_list;
+pub mod slab;
 
-//use core::alloc::{Alloc, GlobalAlloc, Layout};
-use core::ptr::NonNull;
-use core::sync::atomic::{Ordering, AtomicBool};
-use linked_list_allocator::Heap;
-use spin::Mutex;
-
-//pub const HEAP_START: u64 = 0o_000_001_000_000_0000;
-pub const HEAP_START: u64 = 0x_0400_0000_0000; // 4.398.046.511.104, 4.39TB
-pub const HEAP_SIZE: u64 = 1024 * 1024; // 1mb
-
-// pub struct HeapAllocator {
-//     inner: Mutex<Heap>,
-//     status: AtomicBool,
-// }
-
-// impl HeapAllocator {
-//     /// Creates an empty heap. All allocate calls will return `None`.
-//     pub const fn new() -> Self {
-//         HeapAllocator {
-//             inner: Mutex::new(Heap::empty()),
-//             status: AtomicBool::new(false),
-//         }
-//     }
-
-//     /// Initializes an empty heap
-//     ///
-//     /// # Unsafety
-//     ///
-//     /// This function must be called at most once and must only be used on an
-//     /// empty heap.
-//     pub unsafe fn init(&self, heap_bottom: usize, heap_size: usize) {
-//         self.inner.lock().init(heap_bottom, heap_size);
-//         self.status.store(true, Ordering::SeqCst);
-//     }
-
-//     pub fn get_status(&self) -> bool {
-//         self.status.load(Ordering::SeqCst)
-//     }
-// }
-
-// /// Wrappers for inner Alloc implementation
-// unsafe impl GlobalAlloc for HeapAllocator {
-//     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-//         //kprintln!("Allocating {} bytes", layout.size());
-
-//         self.inner
-//             .lock()
-//             .alloc(layout)
-//             .ok()
-//             .map_or(0 as *mut u8, |allocation| allocation.as_ptr())
-//     }
-
-//     #[inline]
-//     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-//         //kprintln!("Freeing {} bytes", layout.size());
-
-//         self.inner
-//             .lock()
-//             .dealloc(NonNull::new_unchecked(ptr), layout);
-//     }
-// }
+pub use self::bump_allocator::BumpBackend;
+pub use self::debug::{AllocationInfo, DebugBackend};
+pub use self::kernel_heap::{HeapStats, KernelHeap};
+pub use self::linked_list::LinkedListBackend;
+pub use self::slab::{CacheStats, SlabBackend, CACHE_COUNT};
+
+#[cfg(feature = "heap-bump")]
+pub type DefaultBackend = BumpBackend;
+#[cfg(all(feature = "heap-linked-list", not(feature = "heap-bump")))]
+pub type DefaultBackend = LinkedListBackend;
+#[cfg(not(any(feature = "heap-bump", feature = "heap-linked-list")))]
+pub type DefaultBackend = SlabBackend;
+
+/// The backend of the kernel heap.
+#[cfg(feature = "heap-debug")]
+pub type KernelBackend = DebugBackend<DefaultBackend>;
+#[cfg(not(feature = "heap-debug"))]
+pub type KernelBackend = DefaultBackend;
+
+pub const fn kernel_backend() -> KernelBackend {
+    #[cfg(feature = "heap-debug")]
+    return DebugBackend::new(DefaultBackend::new());
+    #[cfg(not(feature = "heap-debug"))]
+    return DefaultBackend::new();
+}
+
+/// Manages the heap memory for a `KernelHeap`, which takes care of
+/// locking.
+pub trait Backend {
+    const NAME: &'static str;
+
+    /// Initializes the backend with the memory from `bottom` to
+    /// `bottom + size`.
+    ///
+    /// # Unsafety
+    ///
+    /// The memory must be mapped and unused. Must be called at most once.
+    unsafe fn init(&mut self, bottom: usize, size: usize);
+
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>>;
+
+    /// # Unsafety
+    ///
+    /// `ptr` must have been allocated by this backend with `layout`.
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Adds `by` bytes at the top of the heap.
+    ///
+    /// # Unsafety
+    ///
+    /// The memory from `top()` to `top() + by` must be mapped and unused.
+    unsafe fn extend(&mut self, by: usize);
+
+    fn top(&self) -> usize;
+
+    fn size(&self) -> usize;
+
+    fn used(&self) -> usize;
+
+    /// Statistics of the slab caches, if the backend has them.
+    fn cache_stats(&self) -> Option<[CacheStats; CACHE_COUNT]> {
+        None
+    }
+
+    /// Calls `f` for every live allocation. Returns false if the backend
+    /// does not track allocations.
+    fn for_each_allocation(&self, _f: &mut dyn FnMut(&AllocationInfo)) -> bool {
+        false
+    }
+}
+
+pub use super::vmm::HEAP_START;
+pub const HEAP_SIZE: u64 = 1024 * 1024; // 1mb, mapped at boot
+/// Virtual address space reserved for the heap, it never grows past this.
+pub const HEAP_MAX_SIZE: u64 = 1024 * 1024 * 1024; // 1gb
+/// How far the heap may grow unless the `heap_max` option says otherwise.
+pub const HEAP_DEFAULT_LIMIT: u64 = 64 * 1024 * 1024; // 64mb
+
+/// Returns the heap limit from the `heap_max` command line option, for
+/// example `heap_max=16M`.
+pub fn limit() -> u64 {
+    let limit = match crate::cmdline::get("heap_max") {
+        Some(value) => crate::cmdline::parse_size(value).unwrap_or_else(|| {
+            warn!("Invalid heap_max option {:?}", value);
+            HEAP_DEFAULT_LIMIT
+        }),
+        None => HEAP_DEFAULT_LIMIT,
+    };
+
+    limit.max(HEAP_SIZE).min(HEAP_MAX_SIZE)
+}
+
+/// Prints the live allocations of the kernel heap. Returns false if the
+/// heap does not track them, see the `heap-debug` feature.
+///
+/// The heap is locked while printing, so `out` must not allocate.
+pub fn dump_allocations(out: &mut dyn fmt::Write) -> bool {
+    let mut count = 0;
+    let mut bytes = 0;
+
+    let tracked = crate::HEAP_ALLOCATOR.for_each_allocation(&mut |allocation| {
+        count += 1;
+        bytes += allocation.size;
+
+        let _ = write!(
+            out,
+            "#{:<8} {:#x} {:>8} bytes{} at",
+            allocation.sequence,
+            allocation.address,
+            allocation.size,
+            if allocation.redzones_intact {
+                ""
+            } else {
+                " CORRUPTED"
+            }
+        );
+        for call_site in allocation.call_sites.iter().take_while(|&&site| site != 0) {
+            let _ = write!(out, " {:#x}", call_site);
+        }
+        let _ = writeln!(out);
+    });
+
+    if tracked {
+        let _ = writeln!(out, "{} live allocations, {} bytes", count, bytes);
+    }
+    tracked
+}
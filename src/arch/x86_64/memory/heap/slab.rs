@@ -0,0 +1,374 @@
+//! Slab caches for small allocations.
+//!
+//! Every size class from 8 bytes to 4 KiB has a cache with a free list of
+//! objects. An empty cache takes a new slab from the backing allocator and
+//! carves it into objects. Slabs are aligned to their object size, so an
+//! object is aligned to its own size as well.
+//!
+//! Freed objects go back on the free list of their cache, slabs are never
+//! returned to the backing allocator.
+//!
+//! `SlabBackend` is the heap backend which puts the caches in front of the
+//! linked list heap.
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use super::linked_list::LinkedListBackend;
+use super::Backend;
+
+/// Object sizes of the caches.
+pub const SIZE_CLASSES: [usize; 10] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+/// Number of caches.
+pub const CACHE_COUNT: usize = SIZE_CLASSES.len();
+
+/// Smallest slab size, a slab holds at least `MIN_OBJECTS_PER_SLAB`
+/// objects so large classes do not take a slab per object.
+const MIN_SLAB_SIZE: usize = 4096;
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// Returns the index of the cache for `layout`, or `None` if the
+/// allocation is too large for the caches.
+pub fn size_class(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&class| size <= class)
+}
+
+/// Statistics of a single cache.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub object_size: usize,
+    /// Number of slabs taken from the backing allocator.
+    pub slabs: usize,
+    /// Number of objects in all slabs.
+    pub objects: usize,
+    /// Number of objects which are allocated.
+    pub in_use: usize,
+    /// Number of allocations served by the cache since boot.
+    pub allocations: usize,
+}
+
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+struct Cache {
+    free: Option<NonNull<FreeObject>>,
+    stats: CacheStats,
+}
+
+impl Cache {
+    const fn new(object_size: usize) -> Cache {
+        Cache {
+            free: None,
+            stats: CacheStats {
+                object_size,
+                slabs: 0,
+                objects: 0,
+                in_use: 0,
+                allocations: 0,
+            },
+        }
+    }
+
+    fn slab_layout(&self) -> Layout {
+        let object_size = self.stats.object_size;
+        let size = (object_size * MIN_OBJECTS_PER_SLAB).max(MIN_SLAB_SIZE);
+        Layout::from_size_align(size, object_size.max(MIN_SLAB_SIZE).min(size))
+            .expect("invalid slab layout")
+    }
+
+    /// Takes a new slab from `backing` and puts its objects on the free
+    /// list.
+    fn refill(&mut self, backing: &mut dyn FnMut(Layout) -> Option<NonNull<u8>>) -> bool {
+        let layout = self.slab_layout();
+        let slab = match backing(layout) {
+            Some(slab) => slab.as_ptr(),
+            None => return false,
+        };
+
+        let object_size = self.stats.object_size;
+        let count = layout.size() / object_size;
+
+        // Push in reverse, so objects are handed out in address order
+        for i in (0..count).rev() {
+            unsafe {
+                self.push(NonNull::new_unchecked(slab.add(i * object_size)));
+            }
+        }
+
+        self.stats.slabs += 1;
+        self.stats.objects += count;
+        true
+    }
+
+    unsafe fn push(&mut self, object: NonNull<u8>) {
+        let object = object.cast::<FreeObject>();
+        object.as_ptr().write(FreeObject { next: self.free });
+        self.free = Some(object);
+    }
+
+    fn pop(&mut self) -> Option<NonNull<u8>> {
+        let object = self.free?;
+        self.free = unsafe { object.as_ref().next };
+        Some(object.cast())
+    }
+}
+
+pub struct Slabs {
+    caches: [Cache; CACHE_COUNT],
+}
+
+// The free lists point into memory owned by the slabs.
+unsafe impl Send for Slabs {}
+
+impl Slabs {
+    pub const fn new() -> Slabs {
+        Slabs {
+            caches: [
+                Cache::new(SIZE_CLASSES[0]),
+                Cache::new(SIZE_CLASSES[1]),
+                Cache::new(SIZE_CLASSES[2]),
+                Cache::new(SIZE_CLASSES[3]),
+                Cache::new(SIZE_CLASSES[4]),
+                Cache::new(SIZE_CLASSES[5]),
+                Cache::new(SIZE_CLASSES[6]),
+                Cache::new(SIZE_CLASSES[7]),
+                Cache::new(SIZE_CLASSES[8]),
+                Cache::new(SIZE_CLASSES[9]),
+            ],
+        }
+    }
+
+    /// Allocates an object from cache `class`. `backing` is called for a
+    /// new slab when the cache is empty.
+    pub fn allocate(
+        &mut self,
+        class: usize,
+        backing: &mut dyn FnMut(Layout) -> Option<NonNull<u8>>,
+    ) -> Option<NonNull<u8>> {
+        let cache = &mut self.caches[class];
+
+        let object = match cache.pop() {
+            Some(object) => object,
+            None if cache.refill(backing) => cache.pop()?,
+            None => return None,
+        };
+
+        cache.stats.in_use += 1;
+        cache.stats.allocations += 1;
+        Some(object)
+    }
+
+    /// Returns an object to cache `class`.
+    ///
+    /// # Unsafety
+    ///
+    /// `object` must have been allocated from the same cache.
+    pub unsafe fn deallocate(&mut self, class: usize, object: NonNull<u8>) {
+        let cache = &mut self.caches[class];
+        cache.push(object);
+        cache.stats.in_use -= 1;
+    }
+
+    pub fn stats(&self) -> [CacheStats; CACHE_COUNT] {
+        let mut stats = [CacheStats::default(); CACHE_COUNT];
+        for (stats, cache) in stats.iter_mut().zip(self.caches.iter()) {
+            *stats = cache.stats;
+        }
+        stats
+    }
+
+    /// Bytes in slabs which are not allocated.
+    pub fn free_bytes(&self) -> usize {
+        self.caches
+            .iter()
+            .map(|cache| (cache.stats.objects - cache.stats.in_use) * cache.stats.object_size)
+            .sum()
+    }
+}
+
+/// The fixed block heap backend. Allocations up to 4 KiB come from the
+/// slab caches, larger allocations and the slabs themselves from the
+/// linked list heap.
+pub struct SlabBackend {
+    heap: LinkedListBackend,
+    slabs: Slabs,
+}
+
+impl SlabBackend {
+    pub const fn new() -> SlabBackend {
+        SlabBackend {
+            heap: LinkedListBackend::new(),
+            slabs: Slabs::new(),
+        }
+    }
+}
+
+impl Backend for SlabBackend {
+    const NAME: &'static str = "slab";
+
+    unsafe fn init(&mut self, bottom: usize, size: usize) {
+        self.heap.init(bottom, size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let SlabBackend { heap, slabs } = self;
+
+        match size_class(&layout) {
+            Some(class) => slabs.allocate(class, &mut |slab| heap.allocate(slab)),
+            None => heap.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        match size_class(&layout) {
+            Some(class) => self.slabs.deallocate(class, ptr),
+            None => self.heap.deallocate(ptr, layout),
+        }
+    }
+
+    unsafe fn extend(&mut self, by: usize) {
+        self.heap.extend(by);
+    }
+
+    fn top(&self) -> usize {
+        self.heap.top()
+    }
+
+    fn size(&self) -> usize {
+        self.heap.size()
+    }
+
+    /// Includes the free objects in the slabs.
+    fn used(&self) -> usize {
+        self.heap.used()
+    }
+
+    fn cache_stats(&self) -> Option<[CacheStats; CACHE_COUNT]> {
+        Some(self.slabs.stats())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::alloc::{alloc, dealloc};
+    use std::vec::Vec;
+
+    /// Allocates slabs from the host allocator and frees them on drop.
+    struct Backing(Vec<(NonNull<u8>, Layout)>);
+
+    impl Backing {
+        fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+            let slab = NonNull::new(unsafe { alloc(layout) })?;
+            self.0.push((slab, layout));
+            Some(slab)
+        }
+    }
+
+    impl Drop for Backing {
+        fn drop(&mut self) {
+            for (slab, layout) in self.0.drain(..) {
+                unsafe { dealloc(slab.as_ptr(), layout) };
+            }
+        }
+    }
+
+    #[test]
+    fn size_classes() {
+        let class = |size, align| size_class(&Layout::from_size_align(size, align).unwrap());
+
+        assert_eq!(class(1, 1), Some(0));
+        assert_eq!(class(8, 8), Some(0));
+        assert_eq!(class(9, 1), Some(1));
+        assert_eq!(class(24, 64), Some(3));
+        assert_eq!(class(4096, 4096), Some(9));
+        assert_eq!(class(4097, 8), None);
+    }
+
+    #[test]
+    fn objects_are_aligned_and_distinct() {
+        let mut backing = Backing(Vec::new());
+        let mut slabs = Slabs::new();
+        let mut objects = Vec::new();
+
+        for _ in 0..100 {
+            let object = slabs.allocate(4, &mut |layout| backing.allocate(layout)).unwrap();
+            assert_eq!(object.as_ptr() as usize % 128, 0);
+            objects.push(object.as_ptr() as usize);
+        }
+
+        objects.sort();
+        objects.dedup();
+        assert_eq!(objects.len(), 100);
+
+        let stats = slabs.stats()[4];
+        assert_eq!(stats.in_use, 100);
+        assert_eq!(stats.slabs, 4);
+        assert_eq!(stats.objects, 128);
+    }
+
+    #[test]
+    fn freed_objects_are_reused() {
+        let mut backing = Backing(Vec::new());
+        let mut slabs = Slabs::new();
+
+        let first = slabs.allocate(0, &mut |layout| backing.allocate(layout)).unwrap();
+        unsafe { slabs.deallocate(0, first) };
+        let second = slabs.allocate(0, &mut |layout| backing.allocate(layout)).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(slabs.stats()[0].slabs, 1);
+        assert_eq!(slabs.stats()[0].allocations, 2);
+        assert_eq!(slabs.free_bytes(), (4096 / 8 - 1) * 8);
+    }
+
+    #[test]
+    fn large_classes_get_several_objects_per_slab() {
+        let mut backing = Backing(Vec::new());
+        let mut slabs = Slabs::new();
+
+        slabs.allocate(9, &mut |layout| backing.allocate(layout)).unwrap();
+
+        assert_eq!(slabs.stats()[9].objects, MIN_OBJECTS_PER_SLAB);
+    }
+
+    #[test]
+    fn backend_uses_slabs_for_small_objects() {
+        let region_layout = Layout::from_size_align(16 * 4096, 4096).unwrap();
+        let region = unsafe { alloc(region_layout) };
+
+        let mut backend = SlabBackend::new();
+        unsafe { backend.init(region as usize, 16 * 4096) };
+
+        let small = Layout::from_size_align(12, 4).unwrap();
+        let large = Layout::from_size_align(8192, 8).unwrap();
+        let first = backend.allocate(small).unwrap();
+        let second = backend.allocate(small).unwrap();
+        let third = backend.allocate(large).unwrap();
+
+        assert_eq!(second.as_ptr() as usize - first.as_ptr() as usize, 16);
+        assert_eq!(backend.used(), 4096 + 8192);
+        assert_eq!(backend.cache_stats().unwrap()[1].in_use, 2);
+
+        unsafe {
+            backend.deallocate(first, small);
+            backend.deallocate(second, small);
+            backend.deallocate(third, large);
+        }
+        assert_eq!(backend.cache_stats().unwrap()[1].in_use, 0);
+        // The slab stays with the cache
+        assert_eq!(backend.used(), 4096);
+
+        unsafe { dealloc(region, region_layout) };
+    }
+
+    #[test]
+    fn backing_failure() {
+        let mut slabs = Slabs::new();
+
+        assert!(slabs.allocate(2, &mut |_| None).is_none());
+        assert_eq!(slabs.stats()[2].in_use, 0);
+    }
+}
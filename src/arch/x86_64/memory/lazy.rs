@@ -0,0 +1,102 @@
+//! # Demand paging
+//!
+//! Anonymous memory can be reserved without backing it with frames. The
+//! P1 entries of its pages are not present, but are marked with `LAZY`
+//! and hold the flags the page gets. The first access to such a page
+//! faults, and `handle_fault` maps it to a zeroed frame.
+//!
+//! The markers live in the page tables, so the same works for kernel
+//! regions and for the user part of any address space.
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    page_table::PageTableEntry, FrameAllocator, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::paging;
+use super::MemoryError;
+
+/// Marks a page which is mapped on its first access.
+pub const LAZY: PageTableFlags = PageTableFlags::BIT_10;
+
+/// Whether `entry` is a page which was not touched yet.
+pub fn is_lazy(entry: &PageTableEntry) -> bool {
+    let flags = entry.flags();
+    flags.contains(LAZY) && !flags.contains(PageTableFlags::PRESENT)
+}
+
+/// Whether a fault on `entry` may be handled, user code must not get
+/// kernel pages backed.
+pub fn allows(entry: &PageTableEntry, user: bool) -> bool {
+    !user || entry.flags().contains(PageTableFlags::USER_ACCESSIBLE)
+}
+
+/// Marks `pages` in the page tables under `p4_frame`, so they are mapped
+/// with `flags` on their first access. Only the page tables are allocated.
+pub fn reserve_pages<A, I>(
+    p4_frame: PhysFrame,
+    pages: I,
+    flags: PageTableFlags,
+    frame_allocator: &mut A,
+) -> Result<(), MemoryError>
+where
+    A: FrameAllocator<Size4KiB>,
+    I: Iterator<Item = Page<Size4KiB>>,
+{
+    let table_flags = flags & PageTableFlags::USER_ACCESSIBLE;
+    let marker = (flags | LAZY) - PageTableFlags::PRESENT;
+
+    for page in pages {
+        let entry =
+            unsafe { paging::create_p1_entry(p4_frame, page, table_flags, frame_allocator)? };
+        if !entry.is_unused() {
+            return Err(MemoryError::AlreadyMapped);
+        }
+        entry.set_addr(PhysAddr::new(0), marker);
+    }
+
+    Ok(())
+}
+
+/// Removes the markers of the `pages` which were never touched. The pages
+/// which were touched are normal mappings and have to be unmapped.
+pub fn release_pages<I>(p4_frame: PhysFrame, pages: I)
+where
+    I: Iterator<Item = Page<Size4KiB>>,
+{
+    for page in pages {
+        if let Some(entry) = unsafe { paging::find_p1_entry(p4_frame, page) } {
+            if is_lazy(entry) {
+                entry.set_unused();
+            }
+        }
+    }
+}
+
+/// Maps the page containing `address` if it is a lazy page of the active
+/// address space, which user code may access if the fault is from `user`
+/// mode. Returns false if it is not.
+pub fn handle_fault<A>(
+    address: VirtAddr,
+    user: bool,
+    frame_allocator: &mut A,
+) -> Result<bool, MemoryError>
+where
+    A: FrameAllocator<Size4KiB>,
+{
+    let (p4_frame, _) = Cr3::read();
+    let page = Page::containing_address(address);
+
+    let entry = match unsafe { paging::find_p1_entry(p4_frame, page) } {
+        Some(entry) if is_lazy(entry) && allows(entry, user) => entry,
+        _ => return Ok(false),
+    };
+
+    let frame = paging::zeroed_frame(frame_allocator).ok_or(MemoryError::OutOfFrames)?;
+    entry.set_frame(frame, (entry.flags() - LAZY) | PageTableFlags::PRESENT);
+    tlb::flush(address);
+
+    trace!("Demand paged {:?} to {:?}", address, frame);
+    Ok(true)
+}
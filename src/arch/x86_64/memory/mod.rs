@@ -1,89 +1,450 @@
-use bootloader::bootinfo::BootInfo;
+//! # Memory management
+//!
+//! The memory controller owns the page table, the frame allocator and the
+//! virtual memory manager. It lives in a global after `init`, the
+//! functions of this module lock it with interrupts disabled:
+//!
+//! - `map` and `unmap` reserve and map, or unmap and release, regions of
+//!   the kernel address space, `map_huge` maps large regions with 2 MiB
+//!   or 1 GiB pages, `map_physical` maps memory the kernel does not
+//!   allocate, like a framebuffer, with huge pages where it can, `map_lazy`
+//!   reserves a region which gets its frames on first access (see `lazy`)
+//! - `translate` looks up the physical address of a virtual address
+//! - `alloc_stack` and `free_stack` manage kernel stacks, `guard_page_hit`
+//!   tells the page fault handlers which stack overflowed, and
+//!   `stack_containing` which stack an address is on
+//! - `alloc_frames` allocates physically contiguous frames, which can be
+//!   reached with `paging::phys_to_virt`
+//!
+//! The page table is used through the mapping picked by `paging`, and
+//! `init` maps the physical memory window with huge pages.
+//! `AddressSpace` adds page tables of their own for user processes.
+use bootloader::bootinfo::{BootInfo, MemoryMap};
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::PageFaultErrorCode;
 use x86_64::structures::paging::{
-    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, RecursivePageTable, Size4KiB,
+    frame::PhysFrameRange,
+    mapper::{MapToError, TranslateError, UnmapError},
+    FrameAllocator, Mapper, Page, PageSize, PageTableFlags, Size1GiB, Size2MiB, Size4KiB,
 };
-use x86_64::VirtAddr;
+use x86_64::{PhysAddr, VirtAddr};
 
 use self::area_frame_allocator::AreaFrameAllocator;
-use self::heap::{HEAP_SIZE, HEAP_START};
-use self::stack_allocator::Stack;
+use self::heap::{HEAP_MAX_SIZE, HEAP_SIZE, HEAP_START};
+use self::paging::{HugePageSize, KernelPageTable};
+use self::vmm::{Region, RegionKind, Vmm, VmmError, KERNEL_SPACE_END, KERNEL_SPACE_START};
 
+#[macro_use]
+pub mod paging;
+pub mod address_space;
 mod area_frame_allocator;
+pub mod cow;
 pub mod heap;
+pub mod lazy;
 mod stack_allocator;
+pub mod vmm;
 
-/// Initializes the memory controller.
+pub use self::address_space::AddressSpace;
+pub use self::stack_allocator::Stack;
+
+static MEMORY_CONTROLLER: Mutex<Option<MemoryController<'static>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryError {
+    /// `memory::init` has not run yet.
+    Uninitialized,
+    /// No physical memory left.
+    OutOfFrames,
+    /// The virtual memory manager could not reserve or find a region.
+    Vmm(VmmError),
+    AlreadyMapped,
+    NotMapped,
+    /// The page is part of a huge page mapping.
+    HugePage,
+    /// A page table entry points to an invalid frame.
+    InvalidFrame,
+    /// The address is not in the user part of an address space.
+    NotUserAddress,
+    /// A frame has as many references as can be counted.
+    TooManyReferences,
+    /// A write to a page which is not writable.
+    ReadOnly,
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryError::Uninitialized => write!(f, "memory is not initialized"),
+            MemoryError::OutOfFrames => write!(f, "out of physical memory"),
+            MemoryError::Vmm(error) => write!(f, "virtual memory: {:?}", error),
+            MemoryError::AlreadyMapped => write!(f, "page already mapped"),
+            MemoryError::NotMapped => write!(f, "page not mapped"),
+            MemoryError::HugePage => write!(f, "page is part of a huge page"),
+            MemoryError::InvalidFrame => write!(f, "invalid frame address"),
+            MemoryError::NotUserAddress => write!(f, "not a user space address"),
+            MemoryError::TooManyReferences => write!(f, "frame shared too often"),
+            MemoryError::ReadOnly => write!(f, "page is read-only"),
+        }
+    }
+}
+
+impl From<VmmError> for MemoryError {
+    fn from(error: VmmError) -> MemoryError {
+        MemoryError::Vmm(error)
+    }
+}
+
+impl<S: PageSize> From<MapToError<S>> for MemoryError {
+    fn from(error: MapToError<S>) -> MemoryError {
+        match error {
+            MapToError::FrameAllocationFailed => MemoryError::OutOfFrames,
+            MapToError::ParentEntryHugePage => MemoryError::HugePage,
+            MapToError::PageAlreadyMapped(_) => MemoryError::AlreadyMapped,
+        }
+    }
+}
+
+impl From<UnmapError> for MemoryError {
+    fn from(error: UnmapError) -> MemoryError {
+        match error {
+            UnmapError::PageNotMapped => MemoryError::NotMapped,
+            UnmapError::ParentEntryHugePage => MemoryError::HugePage,
+            UnmapError::InvalidFrameAddress(_) => MemoryError::InvalidFrame,
+        }
+    }
+}
+
+/// Runs `f` with the memory controller locked and interrupts disabled, so
+/// an interrupt handler can not deadlock on the lock.
+fn with_controller<R, F>(f: F) -> Result<R, MemoryError>
+where
+    F: FnOnce(&mut MemoryController<'static>) -> Result<R, MemoryError>,
+{
+    interrupts::without_interrupts(|| match MEMORY_CONTROLLER.lock().as_mut() {
+        Some(controller) => f(controller),
+        None => Err(MemoryError::Uninitialized),
+    })
+}
+
+/// Reserves a region of `size` bytes in the kernel address space and maps
+/// it to new frames.
+pub fn map(size: u64, flags: PageTableFlags, name: &'static str) -> Result<Region, MemoryError> {
+    with_controller(|controller| controller.map(size, flags, name))
+}
+
+/// Reserves a region of `size` bytes and maps it with the largest huge
+/// pages the CPU supports and `size` is a multiple of. The size must be a
+/// multiple of 2 MiB.
+pub fn map_huge(
+    size: u64,
+    flags: PageTableFlags,
+    name: &'static str,
+) -> Result<Region, MemoryError> {
+    with_controller(|controller| controller.map_huge(size, flags, name))
+}
+
+/// Reserves a region of `size` bytes and maps it to the physical memory at
+/// `start`, which both must be page aligned. The region is aligned like
+/// `start`, so huge pages fit where the physical memory allows.
+pub fn map_physical(
+    start: PhysAddr,
+    size: u64,
+    flags: PageTableFlags,
+    name: &'static str,
+) -> Result<Region, MemoryError> {
+    with_controller(|controller| controller.map_physical(start, size, flags, name))
+}
+
+/// Reserves a region of `size` bytes whose pages are mapped to zeroed
+/// frames when they are first touched.
+pub fn map_lazy(
+    size: u64,
+    flags: PageTableFlags,
+    name: &'static str,
+) -> Result<Region, MemoryError> {
+    with_controller(|controller| controller.map_lazy(size, flags, name))
+}
+
+/// Unmaps the region starting at `start`, frees its frames and releases
+/// the addresses.
+pub fn unmap(start: VirtAddr) -> Result<(), MemoryError> {
+    with_controller(|controller| controller.unmap(start))
+}
+
+/// Returns the physical address `address` is mapped to.
+pub fn translate(address: VirtAddr) -> Result<PhysAddr, MemoryError> {
+    with_controller(|controller| controller.translate(address))
+}
+
+/// Allocates a kernel stack with a guard page. `name` identifies the
+/// stack in the region list and in overflow reports.
+pub fn alloc_stack(size_in_pages: usize, name: &'static str) -> Result<Stack, MemoryError> {
+    with_controller(|controller| controller.alloc_stack(size_in_pages, name))
+}
+
+/// Unmaps a stack from `alloc_stack` and frees its frames. The addresses
+/// are reused by the next stacks.
+pub fn free_stack(stack: Stack) -> Result<(), MemoryError> {
+    with_controller(|controller| controller.free_stack(stack))
+}
+
+/// Allocates `count` physically contiguous frames.
+pub fn alloc_frames(count: u64) -> Result<PhysFrameRange<Size4KiB>, MemoryError> {
+    with_controller(|controller| controller.alloc_frames(count))
+}
+
+/// Returns the stack region if `address` is in the guard page of a stack.
+///
+/// Meant for the fault handlers: it does not wait for the memory
+/// controller, and returns `None` if the lock is held.
+pub fn guard_page_hit(address: VirtAddr) -> Option<Region> {
+    let controller = MEMORY_CONTROLLER.try_lock()?;
+    let region = *controller.as_ref()?.vmm.find(address)?;
+
+    let guard_page_end = region.start + Size4KiB::SIZE;
+    if region.kind == RegionKind::Stack && address < guard_page_end {
+        Some(region)
+    } else {
+        None
+    }
+}
+
+/// Returns the stack region `address` is in, guard page included.
+///
+/// Does not wait for the memory controller either, the heap calls it
+/// while it allocates. Returns `None` if the lock is held.
+pub fn stack_containing(address: VirtAddr) -> Option<Region> {
+    let controller = MEMORY_CONTROLLER.try_lock()?;
+    let region = *controller.as_ref()?.vmm.find(address)?;
+
+    if region.kind == RegionKind::Stack {
+        Some(region)
+    } else {
+        None
+    }
+}
+
+/// Handles a page fault at `address` if it is the first access to a lazy
+/// page or a write to a copy-on-write page. Returns false if the fault is
+/// an error, which it always is for user code touching kernel pages.
 ///
-/// We want the MemoryController to have the same lifetime as the RecursivePageTable
-pub fn init<'a>(
-    _boot_info: &BootInfo,
-    mut recursive_page_table: RecursivePageTable<'a>,
-) -> MemoryController<'a> {
+/// Code which holds the memory controller must not touch lazy or
+/// copy-on-write pages, the fault can not be handled then.
+pub fn handle_page_fault(address: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    let protection = error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION);
+    if protection && !error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        return false;
+    }
+
+    let mut controller = match MEMORY_CONTROLLER.try_lock() {
+        Some(controller) => controller,
+        None => return false,
+    };
+    let controller = match controller.as_mut() {
+        Some(controller) => controller,
+        None => return false,
+    };
+
+    let user = error_code.contains(PageFaultErrorCode::USER_MODE);
+    let result = if protection {
+        cow::handle_fault(address, user, &mut controller.frame_allocator)
+    } else {
+        lazy::handle_fault(address, user, &mut controller.frame_allocator)
+    };
+
+    match result {
+        Ok(handled) => handled,
+        Err(error) => {
+            error!("Handling the page fault at {:?} failed: {}", address, error);
+            false
+        }
+    }
+}
+
+/// Sets up paging and initializes the memory controller and the kernel
+/// heap.
+pub fn init(_boot_info: &BootInfo) {
     assert_has_not_been_called!("Memory should only be initialized once!");
 
-    kprintln!("HEAP START = 0x{:X}", HEAP_START);
-    kprintln!("HEAP END = 0x{:X}", HEAP_START + HEAP_SIZE);
+    let mut page_table = unsafe { paging::init(_boot_info) };
+    info!("Using {:?} paging", page_table.mode());
 
     let mut frame_allocator = AreaFrameAllocator::new(&_boot_info.memory_map);
+    address_space::init(&mut frame_allocator);
+    let mut vmm = Vmm::new(
+        VirtAddr::new(KERNEL_SPACE_START),
+        VirtAddr::new(KERNEL_SPACE_END),
+    );
 
-    let heap_start_page = Page::containing_address(VirtAddr::new(HEAP_START));
-    // Subtract one to get the last frame.
-    let heap_end_page = Page::containing_address(VirtAddr::new(HEAP_START + HEAP_SIZE - 1));
+    // Reserve the address space the heap can grow into
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let heap = vmm
+        .reserve_at(
+            VirtAddr::new(HEAP_START),
+            HEAP_MAX_SIZE,
+            RegionKind::Heap,
+            flags,
+            "kernel heap",
+        )
+        .expect("Heap region reservation failed");
 
-    kprintln!("Mapping kernel heap");
+    debug!("HEAP START = 0x{:X}", HEAP_START);
+    debug!("HEAP END = 0x{:X}", HEAP_START + HEAP_SIZE);
 
-    // Map the heap
-    for page in Page::range_inclusive(heap_start_page, heap_end_page) {
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-        map_page(page, flags, &mut recursive_page_table, &mut frame_allocator)
+    info!("Mapping kernel heap");
+
+    let initial_pages = heap.pages().take((HEAP_SIZE / Size4KiB::SIZE) as usize);
+    with_mapper!(&mut page_table, mapper => {
+        vmm::map_pages(&heap, initial_pages, mapper, &mut frame_allocator)
             .expect("Heap page mapping failed");
-    }
 
-    kprintln!(
-        "HEAP start, page start virt addr: {:?}",
-        heap_start_page.start_address()
-    );
-    kprintln!(
-        "HEAP start, page start phys frame: {:?}",
-        recursive_page_table.translate_page(heap_start_page)
-    );
-    kprintln!(
-        "HEAP end, page start virt addr: {:?}",
-        heap_end_page.start_address()
-    );
-    kprintln!(
-        "HEAP end, page start phys frame: {:?}",
-        recursive_page_table.translate_page(heap_end_page)
-    );
+        debug!(
+            "HEAP start, page start phys frame: {:?}",
+            mapper.translate_page(Page::<Size4KiB>::containing_address(heap.start))
+        );
+    });
 
-    // Map the stack
-    let stack_allocator = {
-        let stack_alloc_start = heap_end_page + 1;
-        let stack_alloc_end = stack_alloc_start + 100; // 100 pages = 400KB
-        let stack_alloc_range = Page::range_inclusive(stack_alloc_start, stack_alloc_end);
-        stack_allocator::StackAllocator::new(stack_alloc_range)
-    };
+    // The window of the bootloader uses 4 KiB pages, the kernel switches to
+    // one with huge pages. The old one stays mapped, for what points there.
+    info!("Mapping the physical memory window");
+    let window = vmm
+        .reserve_aligned(
+            physical_memory_size(&_boot_info.memory_map),
+            HugePageSize::largest().size(),
+            RegionKind::Physical,
+            flags,
+            "physical memory",
+        )
+        .expect("Physical memory window reservation failed");
+    with_mapper!(&mut page_table, mapper => {
+        vmm::map_physical(&window, PhysAddr::new(0), mapper, &mut frame_allocator)
+            .expect("Physical memory window mapping failed");
+    });
+    unsafe { paging::move_physical_memory_window(&mut page_table, window.start) };
+    debug!("Physical memory window at {:?}", window.start);
 
-    MemoryController {
-        page_table: recursive_page_table,
+    *MEMORY_CONTROLLER.lock() = Some(MemoryController {
+        page_table: page_table,
         frame_allocator: frame_allocator,
-        stack_allocator: stack_allocator,
+        vmm: vmm,
+        heap: heap,
+    });
+
+    let limit = heap::limit();
+    debug!("HEAP LIMIT = 0x{:X}", limit);
+
+    unsafe {
+        crate::HEAP_ALLOCATOR.init(
+            HEAP_START as usize,
+            HEAP_SIZE as usize,
+            limit as usize,
+            grow_heap,
+        );
+    }
+}
+
+/// Returns the size of the physical address space in the memory map,
+/// rounded up to 2 MiB pages.
+fn physical_memory_size(memory_map: &MemoryMap) -> u64 {
+    let end = memory_map
+        .iter()
+        .map(|region| region.range.end_frame_number * Size4KiB::SIZE)
+        .max()
+        .unwrap_or(0);
+
+    let align = Size2MiB::SIZE;
+    (end + align - 1) & !(align - 1)
+}
+
+/// Maps `size` bytes at `start` for the kernel heap.
+///
+/// Called by the heap with its lock held, so this must not allocate.
+fn grow_heap(start: usize, size: usize) -> bool {
+    let result = with_controller(|controller| {
+        let heap = controller.heap;
+        let start = VirtAddr::new(start as u64);
+        debug_assert!(heap.contains(start) && heap.contains(start + (size - 1)));
+
+        let frame_allocator = &mut controller.frame_allocator;
+        with_mapper!(&mut controller.page_table, mapper => {
+            map_heap(&heap, start, start + size, mapper, frame_allocator)
+        })
+    });
+
+    match result {
+        Ok(()) => {
+            trace!("Heap grown by {} bytes at 0x{:X}", size, start);
+            true
+        }
+        Err(error) => {
+            warn!("Growing the heap failed: {}", error);
+            false
+        }
+    }
+}
+
+/// Maps the heap from `start` to `end`. Whole, aligned 2 MiB chunks get a
+/// 2 MiB page if there is a free huge frame, the rest gets 4 KiB pages.
+fn map_heap<M, A>(
+    heap: &Region,
+    start: VirtAddr,
+    end: VirtAddr,
+    mapper: &mut M,
+    frame_allocator: &mut A,
+) -> Result<(), MemoryError>
+where
+    M: Mapper<Size4KiB> + Mapper<Size2MiB>,
+    A: FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>,
+{
+    let mut address = start;
+
+    while address < end {
+        if address.is_aligned(Size2MiB::SIZE) && end - address >= Size2MiB::SIZE {
+            if let Some(frame) = FrameAllocator::<Size2MiB>::allocate_frame(frame_allocator) {
+                let page = Page::<Size2MiB>::containing_address(address);
+                unsafe {
+                    mapper
+                        .map_to(page, frame, heap.flags, frame_allocator)?
+                        .flush();
+                }
+                address += Size2MiB::SIZE;
+                continue;
+            }
+        }
+
+        let page = Page::<Size4KiB>::containing_address(address);
+        map_page(page, heap.flags, mapper, frame_allocator)?;
+        address += Size4KiB::SIZE;
     }
+
+    Ok(())
+}
+
+/// Calls `f` for every region of the kernel address space. The memory
+/// controller is locked, so `f` must not allocate.
+pub fn for_each_region<F: FnMut(&Region)>(mut f: F) {
+    let _ = with_controller(|controller| {
+        controller.vmm.regions().for_each(|region| f(region));
+        Ok(())
+    });
 }
 
-/// Wrapper for the AreaFrameAllocator
-pub fn map_page<'a, A>(
+/// Maps `page` to a new frame.
+pub fn map_page<M, A>(
     page: Page<Size4KiB>,
     flags: PageTableFlags,
-    page_table: &mut RecursivePageTable<'a>,
+    page_table: &mut M,
     frame_allocator: &mut A,
-) -> Result<(), MapToError<Size4KiB>>
+) -> Result<(), MemoryError>
 where
+    M: Mapper<Size4KiB>,
     A: FrameAllocator<Size4KiB>,
 {
     let frame = frame_allocator
         .allocate_frame()
-        .expect("OOM - Cannot allocate frame");
+        .ok_or(MemoryError::OutOfFrames)?;
 
     unsafe {
         page_table
@@ -95,20 +456,209 @@ where
 }
 
 pub struct MemoryController<'a> {
-    page_table: RecursivePageTable<'a>,
+    page_table: KernelPageTable<'a>,
     frame_allocator: AreaFrameAllocator,
-    stack_allocator: stack_allocator::StackAllocator,
+    vmm: Vmm,
+    /// The region reserved for the heap.
+    heap: Region,
 }
 
 impl<'a> MemoryController<'a> {
-    pub fn alloc_stack(&mut self, size_in_pages: usize) -> Option<Stack> {
+    pub fn map(
+        &mut self,
+        size: u64,
+        flags: PageTableFlags,
+        name: &'static str,
+    ) -> Result<Region, MemoryError> {
+        let region = self.vmm.reserve(size, RegionKind::Other, flags, name)?;
+
+        let frame_allocator = &mut self.frame_allocator;
+        let mapped = with_mapper!(&mut self.page_table, mapper => {
+            vmm::map_pages(&region, region.pages(), mapper, frame_allocator)
+        });
+
+        if let Err(error) = mapped {
+            // Undo the pages which were mapped
+            let _ = self.unmap(region.start);
+            return Err(error.into());
+        }
+
+        Ok(region)
+    }
+
+    pub fn map_huge(
+        &mut self,
+        size: u64,
+        flags: PageTableFlags,
+        name: &'static str,
+    ) -> Result<Region, MemoryError> {
+        let page_size = HugePageSize::largest_for(size).ok_or(VmmError::Unaligned)?;
+        let region = self
+            .vmm
+            .reserve_huge(size, page_size.size(), RegionKind::Other, flags, name)?;
+
+        let frame_allocator = &mut self.frame_allocator;
+        let mapped: Result<(), MemoryError> = with_mapper!(&mut self.page_table, mapper => {
+            match page_size {
+                HugePageSize::Size2MiB => {
+                    vmm::map_huge_pages::<Size2MiB, _, _>(&region, mapper, frame_allocator)
+                        .map_err(MemoryError::from)
+                }
+                HugePageSize::Size1GiB => {
+                    vmm::map_huge_pages::<Size1GiB, _, _>(&region, mapper, frame_allocator)
+                        .map_err(MemoryError::from)
+                }
+            }
+        });
+
+        if let Err(error) = mapped {
+            let _ = self.unmap(region.start);
+            return Err(error);
+        }
+
+        Ok(region)
+    }
+
+    pub fn map_physical(
+        &mut self,
+        start: PhysAddr,
+        size: u64,
+        flags: PageTableFlags,
+        name: &'static str,
+    ) -> Result<Region, MemoryError> {
+        if !start.is_aligned(Size4KiB::SIZE) {
+            return Err(VmmError::Unaligned.into());
+        }
+        // The largest page size `start` is aligned to
+        let align = 1u64 << start.as_u64().trailing_zeros().min(63);
+        let align = align.min(HugePageSize::largest().size());
+        let region = self
+            .vmm
+            .reserve_aligned(size, align, RegionKind::Physical, flags, name)?;
+
+        let frame_allocator = &mut self.frame_allocator;
+        let mapped = with_mapper!(&mut self.page_table, mapper => {
+            vmm::map_physical(&region, start, mapper, frame_allocator)
+        });
+
+        if let Err(error) = mapped {
+            let _ = self.unmap(region.start);
+            return Err(error);
+        }
+
+        Ok(region)
+    }
+
+    pub fn map_lazy(
+        &mut self,
+        size: u64,
+        flags: PageTableFlags,
+        name: &'static str,
+    ) -> Result<Region, MemoryError> {
+        let p4_frame = address_space::kernel_p4().ok_or(MemoryError::Uninitialized)?;
+        let region = self.vmm.reserve(size, RegionKind::Lazy, flags, name)?;
+
+        if let Err(error) =
+            lazy::reserve_pages(p4_frame, region.pages(), flags, &mut self.frame_allocator)
+        {
+            lazy::release_pages(p4_frame, region.pages());
+            self.vmm.release(region.start)?;
+            return Err(error);
+        }
+
+        Ok(region)
+    }
+
+    pub fn unmap(&mut self, start: VirtAddr) -> Result<(), MemoryError> {
+        let region = *self
+            .vmm
+            .find(start)
+            .filter(|region| region.start == start)
+            .ok_or(MemoryError::Vmm(VmmError::NotFound))?;
+
+        let frame_allocator = &mut self.frame_allocator;
+        with_mapper!(&mut self.page_table, mapper => {
+            if region.kind == RegionKind::Physical {
+                vmm::unmap_physical(&region, mapper)
+            } else if region.page_size == Size2MiB::SIZE {
+                vmm::unmap_huge_pages::<Size2MiB, _, _>(&region, mapper, frame_allocator)
+            } else if region.page_size == Size1GiB::SIZE {
+                vmm::unmap_huge_pages::<Size1GiB, _, _>(&region, mapper, frame_allocator)
+            } else {
+                vmm::unmap_region(&region, mapper, frame_allocator)
+            }
+        })?;
+
+        // The pages of a lazy region which were never touched
+        if region.kind == RegionKind::Lazy {
+            let p4_frame = address_space::kernel_p4().ok_or(MemoryError::Uninitialized)?;
+            lazy::release_pages(p4_frame, region.pages());
+        }
+
+        self.vmm.release(start)?;
+        Ok(())
+    }
+
+    pub fn translate(&self, address: VirtAddr) -> Result<PhysAddr, MemoryError> {
+        // Walk down the page sizes until the page which maps the address
+        let result = with_mapper!(&self.page_table, mapper => {
+            match translate_in::<Size4KiB, _>(mapper, address) {
+                Err(TranslateError::ParentEntryHugePage) => {
+                    match translate_in::<Size2MiB, _>(mapper, address) {
+                        Err(TranslateError::ParentEntryHugePage) => {
+                            translate_in::<Size1GiB, _>(mapper, address)
+                        }
+                        result => result,
+                    }
+                }
+                result => result,
+            }
+        });
+
+        match result {
+            Ok(address) => Ok(address),
+            Err(TranslateError::PageNotMapped) => Err(MemoryError::NotMapped),
+            Err(TranslateError::ParentEntryHugePage) => Err(MemoryError::HugePage),
+            Err(TranslateError::InvalidFrameAddress(_)) => Err(MemoryError::InvalidFrame),
+        }
+    }
+
+    pub fn alloc_stack(
+        &mut self,
+        size_in_pages: usize,
+        name: &'static str,
+    ) -> Result<Stack, MemoryError> {
         let &mut MemoryController {
             ref mut page_table,
             ref mut frame_allocator,
-            ref mut stack_allocator,
+            ref mut vmm,
+            ..
         } = self;
-        stack_allocator.alloc_stack(page_table, frame_allocator, size_in_pages)
+        with_mapper!(page_table, mapper => {
+            stack_allocator::alloc_stack(vmm, mapper, frame_allocator, size_in_pages, name)
+        })
     }
+
+    pub fn free_stack(&mut self, stack: Stack) -> Result<(), MemoryError> {
+        self.unmap(stack.region_start())
+    }
+
+    pub fn alloc_frames(&mut self, count: u64) -> Result<PhysFrameRange<Size4KiB>, MemoryError> {
+        self.frame_allocator
+            .allocate_contiguous(count)
+            .ok_or(MemoryError::OutOfFrames)
+    }
+}
+
+/// Translates `address` as part of a page of size `S`.
+fn translate_in<S, M>(mapper: &M, address: VirtAddr) -> Result<PhysAddr, TranslateError>
+where
+    S: PageSize,
+    M: Mapper<S>,
+{
+    let page = Page::<S>::containing_address(address);
+    let frame = mapper.translate_page(page)?;
+    Ok(frame.start_address() + (address - page.start_address()))
 }
 
 #[cfg(test)]
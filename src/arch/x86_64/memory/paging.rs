@@ -0,0 +1,276 @@
+//! # Paging
+//!
+//! The bootloader maps the page tables recursively, and maps all physical
+//! memory with 4 KiB pages. `memory::init` maps a window of its own with
+//! huge pages and moves `physical_memory_offset` there. The kernel can
+//! edit its page tables through either mapping, chosen with the `paging`
+//! command line option:
+//!
+//! - `paging=offset` (default) uses `OffsetPageTable`
+//! - `paging=recursive` uses `RecursivePageTable`
+//!
+//! Everything that needs to touch physical memory, like the frame
+//! allocator, ACPI tables or DMA buffers, goes through `phys_to_virt`.
+//!
+//! Besides 4 KiB pages, large regions can be mapped with 2 MiB pages,
+//! which every x86_64 CPU supports, or 1 GiB pages if `HugePageSize`
+//! says the CPU has them.
+use bootloader::bootinfo::BootInfo;
+use core::arch::x86_64::__cpuid;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    page_table::PageTableEntry, FrameAllocator, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags, PhysFrame, RecursivePageTable, Size1GiB, Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::MemoryError;
+
+/// Where physical memory is mapped, 0 before `init`.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagingMode {
+    Recursive,
+    Offset,
+}
+
+/// The page table of the kernel, in one of the paging modes.
+pub enum KernelPageTable<'a> {
+    Recursive(RecursivePageTable<'a>),
+    Offset(OffsetPageTable<'a>),
+}
+
+/// Runs `$body` with `$mapper` bound to the `Mapper` of a
+/// `KernelPageTable`, whichever mode it is in.
+macro_rules! with_mapper {
+    ($page_table:expr, $mapper:ident => $body:expr) => {
+        match $page_table {
+            $crate::arch::memory::paging::KernelPageTable::Recursive($mapper) => $body,
+            $crate::arch::memory::paging::KernelPageTable::Offset($mapper) => $body,
+        }
+    };
+}
+
+impl<'a> KernelPageTable<'a> {
+    pub fn mode(&self) -> PagingMode {
+        match self {
+            KernelPageTable::Recursive(_) => PagingMode::Recursive,
+            KernelPageTable::Offset(_) => PagingMode::Offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HugePageSize {
+    Size2MiB,
+    Size1GiB,
+}
+
+impl HugePageSize {
+    pub fn size(self) -> u64 {
+        match self {
+            HugePageSize::Size2MiB => Size2MiB::SIZE,
+            HugePageSize::Size1GiB => Size1GiB::SIZE,
+        }
+    }
+
+    /// Whether the CPU can map pages of this size.
+    pub fn supported(self) -> bool {
+        match self {
+            HugePageSize::Size2MiB => true,
+            HugePageSize::Size1GiB => supports_1gib_pages(),
+        }
+    }
+
+    /// Returns the largest page size the CPU supports.
+    pub fn largest() -> HugePageSize {
+        if HugePageSize::Size1GiB.supported() {
+            HugePageSize::Size1GiB
+        } else {
+            HugePageSize::Size2MiB
+        }
+    }
+
+    /// Returns the largest supported page size `size` is a multiple of.
+    pub fn largest_for(size: u64) -> Option<HugePageSize> {
+        [HugePageSize::Size1GiB, HugePageSize::Size2MiB]
+            .iter()
+            .copied()
+            .find(|page_size| page_size.supported() && size % page_size.size() == 0)
+    }
+}
+
+/// Checks the pdpe1gb bit of the extended CPUID features.
+fn supports_1gib_pages() -> bool {
+    const PDPE1GB: u32 = 1 << 26;
+
+    let highest_extended = unsafe { __cpuid(0x8000_0000) }.eax;
+    highest_extended >= 0x8000_0001 && unsafe { __cpuid(0x8000_0001) }.edx & PDPE1GB != 0
+}
+
+/// Returns the paging mode from the command line.
+pub fn mode() -> PagingMode {
+    match crate::cmdline::get("paging") {
+        None | Some("offset") => PagingMode::Offset,
+        Some("recursive") => PagingMode::Recursive,
+        Some(other) => {
+            warn!("Unknown paging mode {:?}, using offset paging", other);
+            PagingMode::Offset
+        }
+    }
+}
+
+/// Stores the physical memory offset and creates the kernel page table in
+/// the mode from the command line.
+///
+/// # Unsafety
+///
+/// Must be called once, the page table must not be used through another
+/// reference.
+pub unsafe fn init(boot_info: &BootInfo) -> KernelPageTable<'static> {
+    let offset = VirtAddr::new(boot_info.physical_memory_offset);
+    PHYSICAL_MEMORY_OFFSET.store(offset.as_u64(), Ordering::SeqCst);
+
+    match mode() {
+        PagingMode::Recursive => {
+            // Use the p4 page table address found in the boot info and
+            // cast it to the page table struct.
+            // For more info see: https://github.com/rust-osdev/x86_64/blob/master/src/structures/paging/page_table.rs
+            let p4 = &mut *(boot_info.recursive_page_table_addr as *mut PageTable);
+            KernelPageTable::Recursive(
+                RecursivePageTable::new(p4).expect("recursive page table creation failed"),
+            )
+        }
+        PagingMode::Offset => {
+            let (p4_frame, _) = Cr3::read();
+            let p4 = &mut *phys_to_virt(p4_frame.start_address()).as_mut_ptr::<PageTable>();
+            KernelPageTable::Offset(OffsetPageTable::new(p4, offset))
+        }
+    }
+}
+
+/// Returns where the physical memory is mapped.
+///
+/// # Panics
+///
+/// Panics if called before `init`.
+pub fn physical_memory_offset() -> VirtAddr {
+    match PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst) {
+        0 => panic!("physical memory offset used before paging::init"),
+        offset => VirtAddr::new(offset),
+    }
+}
+
+/// Makes `offset` the physical memory window, and recreates `page_table`
+/// if it uses the window.
+///
+/// # Unsafety
+///
+/// All physical memory must be mapped at `offset`. References into the
+/// old window stay valid as long as it is mapped.
+pub unsafe fn move_physical_memory_window(
+    page_table: &mut KernelPageTable<'static>,
+    offset: VirtAddr,
+) {
+    PHYSICAL_MEMORY_OFFSET.store(offset.as_u64(), Ordering::SeqCst);
+
+    if let KernelPageTable::Offset(_) = page_table {
+        let (p4_frame, _) = Cr3::read();
+        let p4 = &mut *phys_to_virt(p4_frame.start_address()).as_mut_ptr::<PageTable>();
+        *page_table = KernelPageTable::Offset(OffsetPageTable::new(p4, offset));
+    }
+}
+
+/// Returns the virtual address through which the kernel reaches the
+/// physical address `address`.
+///
+/// # Panics
+///
+/// Panics if called before `init`.
+pub fn phys_to_virt(address: PhysAddr) -> VirtAddr {
+    physical_memory_offset() + address.as_u64()
+}
+
+/// Returns the page table in `frame`, through the physical memory mapping.
+///
+/// # Unsafety
+///
+/// `frame` must hold a page table, which is not changed through another
+/// reference while the returned one is used.
+pub unsafe fn page_table<'a>(frame: PhysFrame) -> &'a mut PageTable {
+    &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>()
+}
+
+/// Allocates a frame and fills it with zeros.
+pub fn zeroed_frame<A: FrameAllocator<Size4KiB>>(frame_allocator: &mut A) -> Option<PhysFrame> {
+    let frame = frame_allocator.allocate_frame()?;
+    unsafe {
+        ptr::write_bytes(
+            phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+            0,
+            Size4KiB::SIZE as usize,
+        );
+    }
+    Some(frame)
+}
+
+/// Returns the P1 entry of `page` in the page tables under the P4 table in
+/// `p4_frame`, or `None` if a table on the way is missing or the page is
+/// part of a huge page.
+///
+/// # Unsafety
+///
+/// The tables must not be changed through another reference while the
+/// entry is used.
+pub unsafe fn find_p1_entry<'a>(p4_frame: PhysFrame, page: Page) -> Option<&'a mut PageTableEntry> {
+    let mut table = page_table(p4_frame);
+
+    for &index in [page.p4_index(), page.p3_index(), page.p2_index()].iter() {
+        let flags = table[index].flags();
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        table = page_table(PhysFrame::containing_address(table[index].addr()));
+    }
+
+    Some(&mut table[page.p1_index()])
+}
+
+/// Like `find_p1_entry`, but creates the missing tables. `table_flags` are
+/// added to the entries on the way, like `Mapper::map_to` does.
+///
+/// # Unsafety
+///
+/// See `find_p1_entry`.
+pub unsafe fn create_p1_entry<'a, A>(
+    p4_frame: PhysFrame,
+    page: Page,
+    table_flags: PageTableFlags,
+    frame_allocator: &mut A,
+) -> Result<&'a mut PageTableEntry, MemoryError>
+where
+    A: FrameAllocator<Size4KiB>,
+{
+    let table_flags = table_flags | PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let mut table = page_table(p4_frame);
+
+    for &index in [page.p4_index(), page.p3_index(), page.p2_index()].iter() {
+        let entry = &mut table[index];
+
+        if entry.is_unused() {
+            let frame = zeroed_frame(frame_allocator).ok_or(MemoryError::OutOfFrames)?;
+            entry.set_frame(frame, table_flags);
+        } else if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(MemoryError::HugePage);
+        } else {
+            entry.set_flags(entry.flags() | table_flags);
+        }
+
+        table = page_table(PhysFrame::containing_address(entry.addr()));
+    }
+
+    Ok(&mut table[page.p1_index()])
+}
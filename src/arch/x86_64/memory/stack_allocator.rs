@@ -1,71 +1,53 @@
-use crate::arch::x86_64::memory::map_page;
+use super::vmm::{self, RegionKind, Vmm, VmmError};
+use super::MemoryError;
 use x86_64::structures::paging::{
-    FrameAllocator, Page, page::PageRangeInclusive, PageSize, PageTableFlags, RecursivePageTable,
-    Size4KiB,
+    FrameAllocator, FrameDeallocator, Mapper, PageSize, PageTableFlags, Size4KiB,
 };
+use x86_64::VirtAddr;
 
-pub struct StackAllocator {
-    range: PageRangeInclusive,
-}
-
-impl StackAllocator {
-    pub fn new(page_range: PageRangeInclusive) -> StackAllocator {
-        StackAllocator { range: page_range }
+/// Allocates a stack of `size_in_pages` pages in a new region of the
+/// VMM. The region starts with an unmapped guard page, so an overflow
+/// causes a page fault instead of overwriting other memory. The region is
+/// named `name`, so the fault handlers can tell which stack overflowed.
+pub fn alloc_stack<M, FA>(
+    vmm: &mut Vmm,
+    page_table: &mut M,
+    frame_allocator: &mut FA,
+    size_in_pages: usize,
+    name: &'static str,
+) -> Result<Stack, MemoryError>
+where
+    M: Mapper<Size4KiB>,
+    FA: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    if size_in_pages == 0 {
+        // a zero sized stack makes no sense
+        return Err(MemoryError::Vmm(VmmError::Unaligned));
     }
-}
-
-impl StackAllocator {
-    pub fn alloc_stack<FA: FrameAllocator<Size4KiB>>(
-        &mut self,
-        page_table: &mut RecursivePageTable,
-        frame_allocator: &mut FA,
-        size_in_pages: usize,
-    ) -> Option<Stack> {
-        if size_in_pages == 0 {
-            return None; // a zero sized stack makes no sense
-        }
-
-        // clone the range, since we only want to change it on success
-        // TODO: can do without clone because PageRangenclusive is copy type?
-        let mut range = self.range.clone();
-        //let mut range = self.range;
-
-        // try to allocate the stack pages and a guard page
-        let guard_page = range.next();
-        let stack_start = range.next();
-        let stack_end = if size_in_pages == 1 {
-            stack_start
-        } else {
-            // choose the (size_in_pages-2)th element, since index
-            // starts at 0 and we already allocated the start page
-            range.nth(size_in_pages - 2)
-        };
-
-        match (guard_page, stack_start, stack_end) {
-            (Some(_), Some(start), Some(end)) => {
-                // success! write back updated range
-                self.range = range;
 
-                // map stack pages to physical frames
-                for page in Page::range_inclusive(start, end) {
-                    let flags = PageTableFlags::PRESENT
-                        | PageTableFlags::WRITABLE
-                        | PageTableFlags::NO_EXECUTE;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let region = vmm.reserve(
+        (size_in_pages as u64 + 1) * Size4KiB::SIZE,
+        RegionKind::Stack,
+        flags,
+        name,
+    )?;
 
-                    map_page(page, flags, page_table, frame_allocator)
-                        .expect("Stack page mapping failed");
-                }
-
-                // create a new stack
-                let top_of_stack = end.start_address() + Size4KiB::SIZE;
-                Some(Stack::new(
-                    top_of_stack.as_u64() as usize,
-                    start.start_address().as_u64() as usize,
-                ))
-            }
-            _ => None, /* not enough pages */
-        }
+    // map stack pages to physical frames, skipping the guard page
+    if let Err(error) = vmm::map_pages(&region, region.pages().skip(1), page_table, frame_allocator)
+    {
+        warn!("Stack page mapping failed: {:?}", error);
+        // Give back the pages which were mapped
+        let _ = vmm::unmap_region(&region, page_table, frame_allocator);
+        let _ = vmm.release(region.start);
+        return Err(error.into());
     }
+
+    // create a new stack
+    Ok(Stack::new(
+        region.end().as_u64() as usize,
+        (region.start.as_u64() + Size4KiB::SIZE) as usize,
+    ))
 }
 
 #[derive(Debug)]
@@ -85,7 +67,6 @@ impl Stack {
 }
 
 impl Stack {
-    #[allow(dead_code)]
     pub fn top(&self) -> usize {
         self.top
     }
@@ -94,4 +75,9 @@ impl Stack {
     pub fn bottom(&self) -> usize {
         self.bottom
     }
-}
\ No newline at end of file
+
+    /// Start of the VMM region of the stack, the guard page.
+    pub fn region_start(&self) -> VirtAddr {
+        VirtAddr::new(self.bottom as u64 - Size4KiB::SIZE)
+    }
+}
@@ -0,0 +1,637 @@
+//! # Virtual memory manager
+//!
+//! Keeps track of the regions in the kernel part of the virtual address
+//! space, and hands out free ranges for new ones. Everything the kernel
+//! maps after boot, like the heap and the stacks, lives in a region.
+//!
+//! Kernel virtual address space layout:
+//!
+//! ```text
+//! 0x0000_0000_0000 - 0x0400_0000_0000  kernel image, boot info and the
+//!                                      physical memory map of the
+//!                                      bootloader
+//! 0x0400_0000_0000 - 0x0400_4000_0000  kernel heap (HEAP_MAX_SIZE)
+//! 0x0400_4000_0000 - 0x0500_0000_0000  the physical memory window, stacks
+//!                                      and other regions
+//! ```
+//!
+//! The regions are kept in a fixed size table, the heap grows through the
+//! memory manager so it can not allocate.
+//!
+//! A region is mapped with 4 KiB pages, or with huge pages if it was
+//! reserved with `reserve_huge`. Two kinds mix page sizes: the heap gets a
+//! 2 MiB page for every whole 2 MiB chunk it grows by, and physical regions
+//! get huge pages wherever the virtual and physical addresses line up.
+use core::fmt;
+use x86_64::structures::paging::{
+    mapper::{MapToError, UnmapError},
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size1GiB,
+    Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::paging::HugePageSize;
+use super::MemoryError;
+
+/// Start of the address space managed by the VMM.
+pub const KERNEL_SPACE_START: u64 = 0x_0400_0000_0000;
+/// End (exclusive) of the address space managed by the VMM.
+pub const KERNEL_SPACE_END: u64 = 0x_0500_0000_0000;
+
+/// The heap is the first region of the kernel space.
+pub const HEAP_START: u64 = KERNEL_SPACE_START;
+
+/// Maximum number of regions.
+pub const MAX_REGIONS: usize = 256;
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Heap,
+    /// A stack, the first page is an unmapped guard page.
+    Stack,
+    /// Anonymous memory, the pages are mapped on their first access.
+    Lazy,
+    /// Physical memory the kernel does not allocate, like a framebuffer or
+    /// the physical memory window. Its frames are not freed on unmap.
+    Physical,
+    Other,
+}
+
+/// A reserved range of virtual addresses.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub start: VirtAddr,
+    /// Size in bytes, a multiple of the page size.
+    pub size: u64,
+    pub kind: RegionKind,
+    /// Flags used to map the pages of the region.
+    pub flags: PageTableFlags,
+    pub name: &'static str,
+    /// Size of the pages the region is mapped with.
+    pub page_size: u64,
+}
+
+impl Region {
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        address >= self.start && address < self.end()
+    }
+
+    /// All pages of the region, as 4 KiB pages.
+    pub fn pages(&self) -> impl Iterator<Item = Page<Size4KiB>> {
+        self.pages_of_size()
+    }
+
+    /// All pages of the region, as pages of size `S`.
+    pub fn pages_of_size<S: PageSize>(&self) -> impl Iterator<Item = Page<S>> {
+        let start = Page::containing_address(self.start);
+        (0..self.size / S::SIZE).map(move |i| start + i)
+    }
+
+    pub fn is_huge(&self) -> bool {
+        self.page_size != PAGE_SIZE
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#014x}-{:#014x} {:>8}K {:<6} {}",
+            self.start.as_u64(),
+            self.end().as_u64(),
+            self.size / 1024,
+            match self.kind {
+                RegionKind::Heap => "heap",
+                RegionKind::Stack => "stack",
+                RegionKind::Lazy => "lazy",
+                RegionKind::Physical => "phys",
+                RegionKind::Other => "other",
+            },
+            self.name
+        )?;
+        if self.is_huge() {
+            write!(f, " ({}K pages)", self.page_size / 1024)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmmError {
+    /// No free range is large enough.
+    OutOfSpace,
+    /// The range overlaps another region or is outside the kernel space.
+    Overlap,
+    /// The region table is full.
+    TooManyRegions,
+    /// There is no region at the address.
+    NotFound,
+    /// The address or size is not page aligned, or the size is zero.
+    Unaligned,
+}
+
+pub struct Vmm {
+    start: VirtAddr,
+    end: VirtAddr,
+    /// Sorted by start address, the used entries come first.
+    regions: [Option<Region>; MAX_REGIONS],
+    count: usize,
+}
+
+impl Vmm {
+    pub const fn new(start: VirtAddr, end: VirtAddr) -> Vmm {
+        Vmm {
+            start,
+            end,
+            regions: [None; MAX_REGIONS],
+            count: 0,
+        }
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.regions[..self.count].iter().flatten()
+    }
+
+    /// Returns the region containing `address`.
+    pub fn find(&self, address: VirtAddr) -> Option<&Region> {
+        self.regions().find(|region| region.contains(address))
+    }
+
+    /// Reserves the range from `start` to `start + size`.
+    pub fn reserve_at(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        kind: RegionKind,
+        flags: PageTableFlags,
+        name: &'static str,
+    ) -> Result<Region, VmmError> {
+        self.reserve_at_with_page_size(start, size, PAGE_SIZE, kind, flags, name)
+    }
+
+    fn reserve_at_with_page_size(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        page_size: u64,
+        kind: RegionKind,
+        flags: PageTableFlags,
+        name: &'static str,
+    ) -> Result<Region, VmmError> {
+        if start.as_u64() % page_size != 0 || size % page_size != 0 || size == 0 {
+            return Err(VmmError::Unaligned);
+        }
+        let end = start.as_u64().checked_add(size).ok_or(VmmError::Overlap)?;
+        if start < self.start || end > self.end.as_u64() {
+            return Err(VmmError::Overlap);
+        }
+        if self
+            .regions()
+            .any(|region| start < region.end() && end > region.start.as_u64())
+        {
+            return Err(VmmError::Overlap);
+        }
+
+        self.insert(Region {
+            start,
+            size,
+            kind,
+            flags,
+            name,
+            page_size,
+        })
+    }
+
+    /// Reserves `size` bytes at the lowest free address.
+    pub fn reserve(
+        &mut self,
+        size: u64,
+        kind: RegionKind,
+        flags: PageTableFlags,
+        name: &'static str,
+    ) -> Result<Region, VmmError> {
+        self.reserve_huge(size, PAGE_SIZE, kind, flags, name)
+    }
+
+    /// Reserves `size` bytes at the lowest free address which is aligned
+    /// to `page_size`, for a region mapped with pages of that size.
+    pub fn reserve_huge(
+        &mut self,
+        size: u64,
+        page_size: u64,
+        kind: RegionKind,
+        flags: PageTableFlags,
+        name: &'static str,
+    ) -> Result<Region, VmmError> {
+        if size % page_size != 0 || size == 0 {
+            return Err(VmmError::Unaligned);
+        }
+
+        let start = self.find_free(size, page_size)?;
+        self.reserve_at_with_page_size(start, size, page_size, kind, flags, name)
+    }
+
+    /// Reserves `size` bytes of 4 KiB pages at the lowest free address
+    /// which is aligned to `align`.
+    pub fn reserve_aligned(
+        &mut self,
+        size: u64,
+        align: u64,
+        kind: RegionKind,
+        flags: PageTableFlags,
+        name: &'static str,
+    ) -> Result<Region, VmmError> {
+        if size % PAGE_SIZE != 0 || size == 0 || !align.is_power_of_two() {
+            return Err(VmmError::Unaligned);
+        }
+
+        let start = self.find_free(size, align.max(PAGE_SIZE))?;
+        self.reserve_at_with_page_size(start, size, PAGE_SIZE, kind, flags, name)
+    }
+
+    /// Returns the lowest address aligned to `align` with `size` free
+    /// bytes after it.
+    fn find_free(&self, size: u64, align: u64) -> Result<VirtAddr, VmmError> {
+        let mut candidate = self.start.align_up(align);
+        for region in self.regions() {
+            if region.start.as_u64() >= candidate.as_u64().saturating_add(size) {
+                break;
+            }
+            candidate = candidate.max(region.end().align_up(align));
+        }
+
+        match candidate.as_u64().checked_add(size) {
+            Some(end) if end <= self.end.as_u64() => Ok(candidate),
+            _ => Err(VmmError::OutOfSpace),
+        }
+    }
+
+    /// Removes the region starting at `start`. The pages must have been
+    /// unmapped.
+    pub fn release(&mut self, start: VirtAddr) -> Result<Region, VmmError> {
+        let index = self.regions[..self.count]
+            .iter()
+            .position(|region| region.map_or(false, |region| region.start == start))
+            .ok_or(VmmError::NotFound)?;
+
+        let region = self.regions[index].take();
+        self.regions[index..self.count].rotate_left(1);
+        self.count -= 1;
+
+        region.ok_or(VmmError::NotFound)
+    }
+
+    fn insert(&mut self, region: Region) -> Result<Region, VmmError> {
+        if self.count == MAX_REGIONS {
+            return Err(VmmError::TooManyRegions);
+        }
+
+        let index = self
+            .regions()
+            .position(|existing| existing.start > region.start)
+            .unwrap_or(self.count);
+
+        self.regions[index..=self.count].rotate_right(1);
+        self.regions[index] = Some(region);
+        self.count += 1;
+
+        Ok(region)
+    }
+}
+
+/// Maps `pages` of `region` to new frames, with the flags of the region.
+pub fn map_pages<M, A, I>(
+    region: &Region,
+    pages: I,
+    mapper: &mut M,
+    frame_allocator: &mut A,
+) -> Result<(), MapToError<Size4KiB>>
+where
+    M: Mapper<Size4KiB>,
+    A: FrameAllocator<Size4KiB>,
+    I: Iterator<Item = Page<Size4KiB>>,
+{
+    for page in pages {
+        debug_assert!(region.contains(page.start_address()));
+
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        unsafe {
+            mapper
+                .map_to(page, frame, region.flags, frame_allocator)?
+                .flush();
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps all pages of `region` to new frames, with pages of size `S`.
+pub fn map_huge_pages<S, M, A>(
+    region: &Region,
+    mapper: &mut M,
+    frame_allocator: &mut A,
+) -> Result<(), MapToError<S>>
+where
+    S: PageSize,
+    M: Mapper<S>,
+    A: FrameAllocator<S> + FrameAllocator<Size4KiB>,
+{
+    debug_assert_eq!(region.page_size, S::SIZE);
+
+    for page in region.pages_of_size::<S>() {
+        let frame: PhysFrame<S> = FrameAllocator::<S>::allocate_frame(frame_allocator)
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        unsafe {
+            mapper
+                .map_to(page, frame, region.flags, frame_allocator)?
+                .flush();
+        }
+    }
+
+    Ok(())
+}
+
+/// Unmaps the mapped pages of `region`, which is mapped with pages of size
+/// `S`, and returns their frames to the frame allocator.
+pub fn unmap_huge_pages<S, M, D>(
+    region: &Region,
+    mapper: &mut M,
+    frame_deallocator: &mut D,
+) -> Result<(), UnmapError>
+where
+    S: PageSize,
+    M: Mapper<S>,
+    D: FrameDeallocator<S>,
+{
+    for page in region.pages_of_size::<S>() {
+        match mapper.unmap(page) {
+            Ok((frame, flush)) => {
+                flush.flush();
+                unsafe { frame_deallocator.deallocate_frame(frame) };
+            }
+            // Guard pages and lazily mapped pages
+            Err(UnmapError::PageNotMapped) => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps `region` to the physical memory at `start`. Where both addresses
+/// are aligned to a huge page and the rest of the region covers it, it
+/// gets one, everything else gets 4 KiB pages.
+pub fn map_physical<M, A>(
+    region: &Region,
+    start: PhysAddr,
+    mapper: &mut M,
+    frame_allocator: &mut A,
+) -> Result<(), MemoryError>
+where
+    M: Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB>,
+    A: FrameAllocator<Size4KiB>,
+{
+    let mut address = region.start;
+
+    while address < region.end() {
+        let frame = start + (address - region.start);
+        let fits = |page_size: HugePageSize| {
+            page_size.supported()
+                && address.is_aligned(page_size.size())
+                && frame.is_aligned(page_size.size())
+                && region.end() - address >= page_size.size()
+        };
+
+        address += unsafe {
+            if fits(HugePageSize::Size1GiB) {
+                map_frame::<Size1GiB, _, _>(address, frame, region.flags, mapper, frame_allocator)?
+            } else if fits(HugePageSize::Size2MiB) {
+                map_frame::<Size2MiB, _, _>(address, frame, region.flags, mapper, frame_allocator)?
+            } else {
+                map_frame::<Size4KiB, _, _>(address, frame, region.flags, mapper, frame_allocator)?
+            }
+        };
+    }
+
+    Ok(())
+}
+
+/// Maps the page of size `S` at `address` to the frame at `frame`, and
+/// returns the page size.
+unsafe fn map_frame<S, M, A>(
+    address: VirtAddr,
+    frame: PhysAddr,
+    flags: PageTableFlags,
+    mapper: &mut M,
+    frame_allocator: &mut A,
+) -> Result<u64, MapToError<S>>
+where
+    S: PageSize,
+    M: Mapper<S>,
+    A: FrameAllocator<Size4KiB>,
+{
+    let page = Page::<S>::containing_address(address);
+    let frame = PhysFrame::<S>::containing_address(frame);
+    mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    Ok(S::SIZE)
+}
+
+/// Unmaps the pages of `region` whatever their size, like the ones of
+/// `map_physical`. The frames are not freed.
+pub fn unmap_physical<M>(region: &Region, mapper: &mut M) -> Result<(), UnmapError>
+where
+    M: Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB>,
+{
+    let mut address = region.start;
+    while address < region.end() {
+        address += unmap_any_size(address, mapper)?;
+    }
+    Ok(())
+}
+
+/// Unmaps the page at `address`, trying the smaller page sizes first as
+/// their walk fails on a huge parent entry. Returns the page size.
+fn unmap_any_size<M>(address: VirtAddr, mapper: &mut M) -> Result<u64, UnmapError>
+where
+    M: Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB>,
+{
+    match Mapper::<Size4KiB>::unmap(mapper, Page::containing_address(address)) {
+        Ok((_, flush)) => {
+            flush.flush();
+            return Ok(Size4KiB::SIZE);
+        }
+        Err(UnmapError::PageNotMapped) => return Ok(Size4KiB::SIZE),
+        Err(UnmapError::ParentEntryHugePage) => {}
+        Err(error) => return Err(error),
+    }
+
+    match Mapper::<Size2MiB>::unmap(mapper, Page::containing_address(address)) {
+        Ok((_, flush)) => {
+            flush.flush();
+            return Ok(Size2MiB::SIZE);
+        }
+        Err(UnmapError::ParentEntryHugePage) => {}
+        Err(error) => return Err(error),
+    }
+
+    let (_, flush) = Mapper::<Size1GiB>::unmap(mapper, Page::containing_address(address))?;
+    flush.flush();
+    Ok(Size1GiB::SIZE)
+}
+
+/// Unmaps the mapped pages of `region` and returns their frames to the
+/// frame allocator.
+pub fn unmap_region<M, D>(
+    region: &Region,
+    mapper: &mut M,
+    frame_deallocator: &mut D,
+) -> Result<(), UnmapError>
+where
+    M: Mapper<Size4KiB>,
+    D: FrameDeallocator<Size4KiB>,
+{
+    unmap_huge_pages::<Size4KiB, _, _>(region, mapper, frame_deallocator)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const START: u64 = 0x10_0000;
+
+    fn vmm() -> Vmm {
+        Vmm::new(VirtAddr::new(START), VirtAddr::new(START + 64 * PAGE_SIZE))
+    }
+
+    fn reserve(vmm: &mut Vmm, pages: u64) -> Result<Region, VmmError> {
+        vmm.reserve(
+            pages * PAGE_SIZE,
+            RegionKind::Other,
+            PageTableFlags::PRESENT,
+            "test",
+        )
+    }
+
+    #[test]
+    fn first_fit() {
+        let mut vmm = vmm();
+
+        let first = reserve(&mut vmm, 2).unwrap();
+        let second = reserve(&mut vmm, 4).unwrap();
+        let third = reserve(&mut vmm, 1).unwrap();
+
+        assert_eq!(first.start.as_u64(), START);
+        assert_eq!(second.start.as_u64(), START + 2 * PAGE_SIZE);
+        assert_eq!(third.start.as_u64(), START + 6 * PAGE_SIZE);
+
+        // The hole of the second region is reused
+        vmm.release(second.start).unwrap();
+        let small = reserve(&mut vmm, 3).unwrap();
+        assert_eq!(small.start, second.start);
+
+        let large = reserve(&mut vmm, 8).unwrap();
+        assert_eq!(large.start, third.end());
+    }
+
+    #[test]
+    fn fixed_regions() {
+        let mut vmm = vmm();
+        let flags = PageTableFlags::PRESENT;
+        let at = |pages: u64| VirtAddr::new(START + pages * PAGE_SIZE);
+
+        vmm.reserve_at(at(4), 4 * PAGE_SIZE, RegionKind::Heap, flags, "heap")
+            .unwrap();
+
+        assert_eq!(
+            vmm.reserve_at(at(6), PAGE_SIZE, RegionKind::Other, flags, "x")
+                .unwrap_err(),
+            VmmError::Overlap
+        );
+        assert_eq!(
+            vmm.reserve_at(at(63), 2 * PAGE_SIZE, RegionKind::Other, flags, "x")
+                .unwrap_err(),
+            VmmError::Overlap
+        );
+        assert_eq!(
+            vmm.reserve_at(at(1) + 1u64, PAGE_SIZE, RegionKind::Other, flags, "x")
+                .unwrap_err(),
+            VmmError::Unaligned
+        );
+
+        // Allocations go around the fixed region
+        assert_eq!(reserve(&mut vmm, 4).unwrap().start, at(0));
+        assert_eq!(reserve(&mut vmm, 1).unwrap().start, at(8));
+
+        let starts: std::vec::Vec<u64> = vmm.regions().map(|r| r.start.as_u64()).collect();
+        assert_eq!(starts, [at(0).as_u64(), at(4).as_u64(), at(8).as_u64()]);
+    }
+
+    #[test]
+    fn find_and_release() {
+        let mut vmm = vmm();
+        let region = reserve(&mut vmm, 2).unwrap();
+
+        assert!(vmm.find(region.start + PAGE_SIZE).is_some());
+        assert!(vmm.find(region.end()).is_none());
+
+        assert_eq!(vmm.release(region.end()).unwrap_err(), VmmError::NotFound);
+        vmm.release(region.start).unwrap();
+        assert!(vmm.find(region.start).is_none());
+    }
+
+    #[test]
+    fn huge_regions_are_aligned() {
+        let huge = 16 * PAGE_SIZE;
+        let mut vmm = Vmm::new(VirtAddr::new(START), VirtAddr::new(START + 4 * huge));
+
+        reserve(&mut vmm, 1).unwrap();
+        let flags = PageTableFlags::PRESENT;
+        let region = vmm.reserve_huge(2 * huge, huge, RegionKind::Other, flags, "huge").unwrap();
+
+        assert_eq!(region.start.as_u64(), (START + huge) & !(huge - 1));
+        assert!(region.is_huge());
+        assert_eq!(region.pages_of_size::<Size4KiB>().count(), 32);
+
+        // The space before the huge region is still used for small ones
+        assert!(reserve(&mut vmm, 1).unwrap().start < region.start);
+        let unaligned = vmm.reserve_huge(huge + PAGE_SIZE, huge, RegionKind::Other, flags, "x");
+        assert_eq!(unaligned.unwrap_err(), VmmError::Unaligned);
+    }
+
+    #[test]
+    fn aligned_regions_use_small_pages() {
+        let align = 16 * PAGE_SIZE;
+        let flags = PageTableFlags::PRESENT;
+        let mut vmm = Vmm::new(VirtAddr::new(START), VirtAddr::new(START + 4 * align));
+
+        reserve(&mut vmm, 1).unwrap();
+        let region = vmm
+            .reserve_aligned(3 * PAGE_SIZE, align, RegionKind::Physical, flags, "fb")
+            .unwrap();
+
+        assert_eq!(region.start.as_u64(), (START + align) & !(align - 1));
+        assert!(!region.is_huge());
+        let unaligned =
+            vmm.reserve_aligned(PAGE_SIZE, 3 * PAGE_SIZE, RegionKind::Other, flags, "x");
+        assert_eq!(unaligned.unwrap_err(), VmmError::Unaligned);
+    }
+
+    #[test]
+    fn out_of_space() {
+        let mut vmm = vmm();
+
+        reserve(&mut vmm, 60).unwrap();
+        assert_eq!(reserve(&mut vmm, 5).unwrap_err(), VmmError::OutOfSpace);
+        assert!(reserve(&mut vmm, 4).is_ok());
+    }
+}
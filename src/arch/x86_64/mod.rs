@@ -1,11 +1,14 @@
 use bootloader::bootinfo::BootInfo;
-use x86_64::structures::paging::{PageTable, RecursivePageTable};
 
+pub mod acpi;
 pub mod gdt;
 pub mod idt;
 pub mod interrupts;
 pub mod memory;
 pub mod context;
+pub mod power;
+pub mod syscall;
+pub mod user;
 mod device;
 
 /// Initialize for the x86_64 architecture
@@ -20,27 +23,14 @@ pub fn init(boot_info_address: usize) {
     //     panic!("os_bootinfo version passed by bootloader does not match crate version!");
     // }
 
-    // Use the p4 page table address found in the boot info and
-    // cast it to the page table struct.
-    // For more info see: https://github.com/rust-osdev/x86_64/blob/master/src/structures/paging/page_table.rs
-    let mut page_table: &mut PageTable =
-        unsafe { &mut *(_boot_info.recursive_page_table_addr as *mut PageTable) };
+    // Sets up paging and also initializes the heap
+    memory::init(_boot_info);
 
-    let rec_page_table =
-        RecursivePageTable::new(&mut page_table).expect("recursive page table creation failed");
-
-    let _memory_controller = memory::init(_boot_info, rec_page_table);
-
-    /*  This piece of unsafe code uses the static declared in /lib.rs and initializes
-        the heap. 
-    */
-    unsafe {
-        use self::memory::heap::{HEAP_SIZE, HEAP_START};
-        crate::HEAP_ALLOCATOR.lock().init(HEAP_START as usize, HEAP_SIZE as usize);
-    }
+    acpi::init();
 
     gdt::init();
     idt::init();
+    syscall::init();
 
     // We need allocation enabled before initialzing the devices
     // For example the timer uses allocation
@@ -0,0 +1,176 @@
+//! Power management: rebooting, powering off and halting the machine.
+//!
+//! Every operation tries the available backends from most to least
+//! graceful:
+//!
+//! - reboot: ACPI reset register, 8042 reset line, triple fault
+//! - shutdown: ACPI S5 sleep state, QEMU `isa-debug-exit` device
+use x86_64::instructions::port::Port;
+
+use super::acpi::{self, ADDRESS_SPACE_IO, ADDRESS_SPACE_MEMORY};
+use super::interrupts;
+#[cfg(feature = "heap-debug")]
+use super::memory::heap;
+#[cfg(feature = "heap-debug")]
+use crate::console::ConsoleWriter;
+use crate::device::serial;
+
+/// Port of the QEMU `isa-debug-exit` device, see the Makefile.
+const QEMU_EXIT_PORT: u16 = 0xf4;
+
+/// Exit codes for the QEMU `isa-debug-exit` device. QEMU exits with
+/// `(code << 1) | 1`, so a code can not be confused with QEMU's own exit
+/// codes 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+// PM1 control register bits
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_EN: u16 = 1 << 13;
+
+/// Exits QEMU with the given code. Returns if the kernel does not run in
+/// QEMU or QEMU was started without the `isa-debug-exit` device.
+pub fn exit_qemu(code: QemuExitCode) {
+    serial::flush();
+
+    let mut port: Port<u32> = Port::new(QEMU_EXIT_PORT);
+    unsafe {
+        port.write(code as u32);
+    }
+}
+
+/// Resets the machine.
+pub fn reboot() -> ! {
+    interrupts::interrupts_disable();
+    serial::flush();
+
+    acpi_reset();
+    keyboard_controller_reset();
+    triple_fault()
+}
+
+/// Powers the machine off.
+pub fn shutdown() -> ! {
+    interrupts::interrupts_disable();
+
+    #[cfg(feature = "heap-debug")]
+    {
+        serial_println!("Live heap allocations at shutdown:");
+        heap::dump_allocations(&mut ConsoleWriter(&serial::SERIAL_CONSOLE));
+    }
+    serial::flush();
+
+    acpi_shutdown();
+    exit_qemu(QemuExitCode::Success);
+
+    warn!("Shutdown failed, halting");
+    halt()
+}
+
+/// Stops the CPU until the machine is reset or powered off.
+pub fn halt() -> ! {
+    interrupts::interrupts_disable();
+    serial::flush();
+
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Writes the reset value to the ACPI reset register, if the FADT has one.
+fn acpi_reset() {
+    let (register, value) = match acpi::get().and_then(|acpi| acpi.reset_register) {
+        Some(reset) => reset,
+        None => return,
+    };
+
+    match register.address_space {
+        ADDRESS_SPACE_IO => unsafe {
+            Port::new(register.address as u16).write(value);
+        },
+        ADDRESS_SPACE_MEMORY => {
+            // The reset register is not mapped, and this is the last thing
+            // the kernel does, so skip it and use the other methods.
+            debug!("Skipping memory mapped ACPI reset register");
+        }
+        _ => {}
+    }
+}
+
+/// Pulses the reset line of the 8042 keyboard controller.
+fn keyboard_controller_reset() {
+    let mut status: Port<u8> = Port::new(0x64);
+    unsafe {
+        // Wait until the controller input buffer is empty. Without a
+        // controller the port reads 0xFF, so give up after a while.
+        for _ in 0..100_000 {
+            if status.read() & 0x02 == 0 {
+                break;
+            }
+            interrupts::pause();
+        }
+        status.write(0xFE);
+    }
+
+    // Give the controller some time to reset the machine
+    for _ in 0..100_000 {
+        interrupts::pause();
+    }
+}
+
+/// Loads an empty IDT and raises an exception. The CPU can not deliver it,
+/// and shuts down after the resulting double fault, which resets the
+/// machine.
+fn triple_fault() -> ! {
+    use x86_64::instructions::tables::{lidt, DescriptorTablePointer};
+
+    let empty = DescriptorTablePointer { limit: 0, base: 0 };
+    unsafe {
+        lidt(&empty);
+    }
+    x86_64::instructions::interrupts::int3();
+
+    halt()
+}
+
+/// Enters the S5 (soft off) sleep state through the PM1 control blocks.
+fn acpi_shutdown() {
+    let acpi = match acpi::get() {
+        Some(acpi) => acpi,
+        None => return,
+    };
+
+    let (sleep_type_a, sleep_type_b) = match acpi.s5_sleep_types {
+        Some(types) => types,
+        None => {
+            warn!("No ACPI S5 sleep state");
+            return;
+        }
+    };
+
+    let mut pm1a: Port<u16> = Port::new(acpi.pm1a_control);
+
+    unsafe {
+        // Switch to ACPI mode if the firmware has not done so yet
+        if pm1a.read() & SCI_EN == 0 && acpi.smi_command != 0 && acpi.acpi_enable != 0 {
+            Port::new(acpi.smi_command as u16).write(acpi.acpi_enable);
+            for _ in 0..100_000 {
+                if pm1a.read() & SCI_EN != 0 {
+                    break;
+                }
+                interrupts::pause();
+            }
+        }
+
+        pm1a.write((sleep_type_a as u16) << SLP_TYP_SHIFT | SLP_EN);
+
+        if acpi.pm1b_control != 0 {
+            Port::new(acpi.pm1b_control).write((sleep_type_b as u16) << SLP_TYP_SHIFT | SLP_EN);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+.intel_syntax noprefix
+.global x86_64_syscall_entry
+.global x86_64_int80_entry
+
+# Entered by the syscall instruction. rcx holds the user rip, r11 the user
+# rflags, SFMASK cleared the interrupt flag and rsp is still the user
+# stack. Builds the user::Registers on the kernel stack, and returns to
+# the state the handler left in them.
+x86_64_syscall_entry:
+    mov [rip + SYSCALL_USER_RSP], rsp
+    mov rsp, [rip + SYSCALL_KERNEL_RSP]
+
+    push qword ptr [rip + SYSCALL_USER_RSP]
+    push r11
+    push rcx
+    push r15
+    push r14
+    push r13
+    push r12
+    push rbp
+    push rbx
+    push r11
+    push rcx
+    push r9
+    push r8
+    push r10
+    push rdx
+    push rsi
+    push rdi
+    push rax
+
+    mov rdi, rsp
+    call x86_64_syscall_handler
+
+    pop rax
+    pop rdi
+    pop rsi
+    pop rdx
+    pop r10
+    pop r8
+    pop r9
+    # sysretq takes rip from rcx and rflags from r11
+    add rsp, 16
+    pop rbx
+    pop rbp
+    pop r12
+    pop r13
+    pop r14
+    pop r15
+    pop rcx
+    pop r11
+    pop rsp
+    sysretq
+
+# The int 0x80 gate. The CPU switched to the kernel stack of the TSS and
+# pushed the interrupt frame: rip, cs, rflags, rsp and ss. The stub copies
+# rip, rflags and rsp into the user::Registers and back, the padding keeps
+# the stack aligned.
+x86_64_int80_entry:
+    push 0
+    push qword ptr [rsp + 32]
+    push qword ptr [rsp + 32]
+    push qword ptr [rsp + 24]
+    push r15
+    push r14
+    push r13
+    push r12
+    push rbp
+    push rbx
+    push r11
+    push rcx
+    push r9
+    push r8
+    push r10
+    push rdx
+    push rsi
+    push rdi
+    push rax
+
+    mov rdi, rsp
+    call x86_64_syscall_handler
+
+    pop rax
+    pop rdi
+    pop rsi
+    pop rdx
+    pop r10
+    pop r8
+    pop r9
+    pop rcx
+    pop r11
+    pop rbx
+    pop rbp
+    pop r12
+    pop r13
+    pop r14
+    pop r15
+    # pop computes the address after moving rsp
+    pop qword ptr [rsp + 24]
+    pop qword ptr [rsp + 32]
+    pop qword ptr [rsp + 32]
+    add rsp, 8
+    iretq
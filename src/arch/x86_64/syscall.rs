@@ -0,0 +1,95 @@
+//! # System call entry
+//!
+//! `init` enables the `syscall` instruction: STAR holds the segments,
+//! LSTAR the entry stub and SFMASK the flags cleared on entry. `int 0x80`
+//! is a fallback gate into the same handler. The stubs in `syscall.asm`
+//! save every register and call `x86_64_syscall_handler`, which passes
+//! the call on to `crate::syscall::dispatch`. The stubs return to user
+//! code with the registers the system call left, so `execve` can replace
+//! them.
+//!
+//! `syscall` does not switch stacks, the stub switches to the stack set
+//! with `gdt::set_kernel_stack`, the same one interrupts from ring 3 use.
+use core::mem;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::{Efer, EferFlags, Msr};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::HandlerFunc;
+use x86_64::VirtAddr;
+
+use super::gdt;
+use super::user::{self, Registers, UserExit};
+use crate::syscall;
+
+pub const SYSCALL_INTERRUPT_ID: u8 = 0x80;
+
+const STAR: u32 = 0xc000_0081;
+const LSTAR: u32 = 0xc000_0082;
+const SFMASK: u32 = 0xc000_0084;
+
+global_asm!(include_str!("syscall.asm"));
+
+extern "C" {
+    fn x86_64_syscall_entry();
+    fn x86_64_int80_entry();
+}
+
+/// The kernel stack `x86_64_syscall_entry` switches to.
+#[no_mangle]
+static mut SYSCALL_KERNEL_RSP: u64 = 0;
+/// The user stack while a system call runs.
+#[no_mangle]
+static mut SYSCALL_USER_RSP: u64 = 0;
+
+/// Enables the `syscall` instruction. The GDT must be loaded.
+pub fn init() {
+    let selectors = gdt::selectors();
+
+    // sysret loads the user data segment from STAR[63:48] + 8 and the
+    // user code segment from STAR[63:48] + 16, syscall the kernel code
+    // segment from STAR[47:32] and the kernel data segment after it.
+    let sysret_base = u64::from(selectors.user_data.0 - 8);
+    let syscall_base = u64::from(selectors.kernel_code.0);
+    let masked = RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG;
+
+    unsafe {
+        SYSCALL_KERNEL_RSP = gdt::kernel_stack().as_u64();
+
+        Msr::new(STAR).write(sysret_base << 48 | syscall_base << 32);
+        Msr::new(LSTAR).write(x86_64_syscall_entry as u64);
+        Msr::new(SFMASK).write(masked.bits());
+        Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+    }
+}
+
+/// Sets the stack system calls run on, called by `gdt::set_kernel_stack`.
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe { SYSCALL_KERNEL_RSP = top.as_u64() };
+}
+
+/// Returns the handler for the `int 0x80` gate. It is not an
+/// `extern "x86-interrupt"` function, but the IDT only needs its address.
+pub fn int80_handler() -> HandlerFunc {
+    unsafe { mem::transmute(x86_64_int80_entry as unsafe extern "C" fn()) }
+}
+
+/// Called by the entry stubs with interrupts disabled. Interrupts are
+/// enabled while the system call runs, it may block.
+#[no_mangle]
+extern "C" fn x86_64_syscall_handler(registers: &mut Registers) {
+    interrupts::enable();
+    syscall::dispatch(registers);
+    interrupts::disable();
+
+    // Only user code can be returned to, sysretq to a non-canonical
+    // address faults in ring 0
+    if !registers.is_valid() {
+        unsafe {
+            user::exit_user_mode(UserExit::Fault {
+                exception: "general protection fault",
+                instruction: VirtAddr::try_new(registers.rip).unwrap_or(VirtAddr::new(0)),
+            })
+        }
+    }
+    registers.sanitize_flags();
+}
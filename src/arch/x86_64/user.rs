@@ -0,0 +1,169 @@
+//! # User mode
+//!
+//! `enter_user_mode` runs code at CPL 3, with the user code and data
+//! segments from the GDT. The code and its stack must be mapped
+//! `USER_ACCESSIBLE`, the kernel pages are not and stay out of its reach.
+//!
+//! Interrupts in user mode switch to the stack in the TSS, see
+//! `gdt::set_kernel_stack`, and return to the user code. A fault in user
+//! mode or the `exit` system call ends the run: they call
+//! `exit_user_mode`, which returns to the caller of `enter_user_mode` on
+//! the kernel stack it left.
+//!
+//! `resume_user_mode` starts user code with every register given, like
+//! the state a system call returns to. The system call stubs save the
+//! same `Registers`.
+//!
+//! Runs can nest: a system call can run other user code, on another
+//! kernel stack. The inner run ends first, and the outer one goes on.
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+use super::gdt;
+use super::memory::address_space::USER_SPACE_END;
+
+global_asm!(include_str!("user_mode.asm"));
+
+extern "C" {
+    fn x86_64_enter_user_mode(
+        registers: *const Registers,
+        code_selector: u64,
+        data_selector: u64,
+        kernel_rsp: *mut u64,
+    );
+    fn x86_64_exit_user_mode(kernel_rsp: u64) -> !;
+}
+
+/// Why user code stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserExit {
+    /// The instruction at `instruction` caused `exception`.
+    Fault {
+        exception: &'static str,
+        instruction: VirtAddr,
+    },
+    /// The code made the `exit` system call.
+    Exit(i32),
+    /// A signal ended the code.
+    Killed(u8),
+    /// A signal stopped the code, its registers were saved to go on.
+    Stopped(u8),
+}
+
+/// The registers of user code, in the order the system call stubs save
+/// them.
+#[repr(C)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Registers {
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub rcx: u64,
+    pub r11: u64,
+    pub rbx: u64,
+    pub rbp: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+}
+
+impl Registers {
+    /// The flags user code may change: the arithmetic flags, the trap and
+    /// the direction flag.
+    const USER_FLAGS: u64 = 0xdd5;
+
+    /// The state to start the code at `entry` on `stack` with, the other
+    /// registers are zero.
+    pub fn entry(entry: VirtAddr, stack: VirtAddr) -> Registers {
+        Registers {
+            rip: entry.as_u64(),
+            rsp: stack.as_u64(),
+            rflags: RFlags::INTERRUPT_FLAG.bits(),
+            ..Registers::default()
+        }
+    }
+
+    /// Whether the state can be returned to: `rip` must be a user
+    /// address. `sysretq` to a non-canonical address faults in ring 0.
+    pub fn is_valid(&self) -> bool {
+        self.rip < USER_SPACE_END
+    }
+
+    /// Keeps the flags user code may set and enables interrupts.
+    pub fn sanitize_flags(&mut self) {
+        self.rflags = self.rflags & Registers::USER_FLAGS | RFlags::INTERRUPT_FLAG.bits();
+    }
+}
+
+/// The kernel stack the innermost `enter_user_mode` left, 0 outside user
+/// mode.
+static mut KERNEL_RSP: u64 = 0;
+static mut EXIT: Option<UserExit> = None;
+
+/// Runs the code at `entry` in ring 3 on `user_stack`, until it exits or
+/// faults.
+///
+/// # Unsafety
+///
+/// `entry` and `user_stack` must be mapped user accessible in the active
+/// address space. The kernel stack this is called on must not be the
+/// stack set with `gdt::set_kernel_stack`.
+pub unsafe fn enter_user_mode(entry: VirtAddr, user_stack: VirtAddr) -> UserExit {
+    resume_user_mode(&Registers::entry(entry, user_stack))
+}
+
+/// Runs user code with `registers`, until it exits or faults. Interrupts
+/// are enabled, see `Registers::sanitize_flags`.
+///
+/// # Unsafety
+///
+/// See `enter_user_mode`, `rip` and `rsp` must be mapped user accessible.
+pub unsafe fn resume_user_mode(registers: &Registers) -> UserExit {
+    assert!(
+        registers.is_valid(),
+        "resuming user mode at {:#x}",
+        registers.rip
+    );
+
+    let mut registers = registers.clone();
+    registers.sanitize_flags();
+    let outer_rsp = KERNEL_RSP;
+
+    let selectors = gdt::selectors();
+    x86_64_enter_user_mode(
+        &registers,
+        u64::from(selectors.user_code.0),
+        u64::from(selectors.user_data.0),
+        &mut KERNEL_RSP,
+    );
+
+    KERNEL_RSP = outer_rsp;
+    EXIT.take().expect("left user mode without a reason")
+}
+
+/// Whether the interrupt or exception of `stack_frame` arrived in ring 3.
+pub fn from_user_mode(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 3 == 3
+}
+
+/// Returns from `enter_user_mode` with `exit`.
+///
+/// # Unsafety
+///
+/// Must only be called by an interrupt handler or a system call which
+/// interrupted user code, the kernel stack it runs on is abandoned.
+pub unsafe fn exit_user_mode(exit: UserExit) -> ! {
+    assert_ne!(KERNEL_RSP, 0, "exit_user_mode called outside user mode");
+
+    EXIT = Some(exit);
+    x86_64_exit_user_mode(KERNEL_RSP)
+}
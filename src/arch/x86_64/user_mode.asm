@@ -0,0 +1,61 @@
+.intel_syntax noprefix
+.global x86_64_enter_user_mode
+.global x86_64_exit_user_mode
+
+# Jumps to ring 3 with the user::Registers at rdi. Saves the kernel
+# context on the stack and its address in *kernel_rsp, so
+# x86_64_exit_user_mode can return to the caller.
+#
+# rdi: registers, rsi: user code selector, rdx: user data selector,
+# rcx: kernel_rsp
+x86_64_enter_user_mode:
+    pushfq
+    push rbx
+    push rbp
+    push r12
+    push r13
+    push r14
+    push r15
+    mov [rcx], rsp
+
+    mov ds, dx
+    mov es, dx
+
+    # The frame iretq pops: ss, rsp, rflags, cs, rip
+    push rdx
+    push qword ptr [rdi + 136]
+    push qword ptr [rdi + 128]
+    push rsi
+    push qword ptr [rdi + 120]
+
+    # Every register comes from user::Registers, no kernel value leaks
+    mov rax, [rdi + 0]
+    mov rsi, [rdi + 16]
+    mov rdx, [rdi + 24]
+    mov r10, [rdi + 32]
+    mov r8, [rdi + 40]
+    mov r9, [rdi + 48]
+    mov rcx, [rdi + 56]
+    mov r11, [rdi + 64]
+    mov rbx, [rdi + 72]
+    mov rbp, [rdi + 80]
+    mov r12, [rdi + 88]
+    mov r13, [rdi + 96]
+    mov r14, [rdi + 104]
+    mov r15, [rdi + 112]
+    mov rdi, [rdi + 8]
+    iretq
+
+# Returns from x86_64_enter_user_mode with the kernel context saved there.
+#
+# rdi: the saved kernel rsp
+x86_64_exit_user_mode:
+    mov rsp, rdi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbp
+    pop rbx
+    popfq
+    ret
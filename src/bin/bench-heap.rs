@@ -0,0 +1,47 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+
+use core::panic::PanicInfo;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::heap::bench;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+
+/// Compares the slab caches with the linked list heap, run it with
+/// `make bench`.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    serial_println!("{:>6} {:>12} {:>12}", "size", "slab", "linked list");
+    bench::run(|result| {
+        serial_println!(
+            "{:>6} {:>12} {:>12}",
+            result.size,
+            result.slab_cycles,
+            result.linked_list_cycles
+        );
+    });
+    serial_println!("(cycles per allocation and free)");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
@@ -0,0 +1,73 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::panic::PanicInfo;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::address_space::{self, USER_SPACE_START};
+use rust_kernel::arch::memory::AddressSpace;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// Maps the same user address in two address spaces and checks they do
+/// not see each other's memory, but both see the kernel heap.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    let address = VirtAddr::new(USER_SPACE_START);
+    let page = Page::containing_address(address);
+    let flags = PageTableFlags::WRITABLE;
+    let shared = Box::new(42u64);
+
+    let mut first = AddressSpace::new().expect("creating an address space failed");
+    let mut second = AddressSpace::new().expect("creating an address space failed");
+    first.map(page, flags).unwrap();
+    second.map(page, flags).unwrap();
+
+    let value = address.as_mut_ptr::<u64>();
+    unsafe {
+        first.switch();
+        *value = 1;
+        assert_eq!(*shared, 42);
+
+        second.switch();
+        assert_eq!(*value, 0, "second address space sees the first one");
+        *value = 2;
+
+        first.switch();
+        assert_eq!(*value, 1);
+    }
+
+    address_space::switch_to_kernel();
+    drop(first);
+    second.unmap(page).unwrap();
+    assert!(second.translate(address).is_err());
+    drop(second);
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
@@ -7,7 +7,7 @@
 extern crate rust_kernel;
 
 use core::panic::PanicInfo;
-use rust_kernel::exit_qemu;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
 
 /// This function is the entry point, since the linker looks for a function
 /// named `_start` by default.
@@ -17,7 +17,7 @@ pub extern "C" fn _start() -> ! {
     kprintln!("Test");
     serial_println!("ok");
 
-    unsafe { exit_qemu(); }
+    exit_qemu(QemuExitCode::Success);
     loop {}
 }
 
@@ -31,6 +31,6 @@ pub fn panic(info: &PanicInfo) -> ! {
 
     serial_println!("{}", info);
 
-    unsafe { exit_qemu(); }
+    exit_qemu(QemuExitCode::Failed);
     loop {}
 }
\ No newline at end of file
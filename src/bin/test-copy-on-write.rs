@@ -0,0 +1,81 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+
+use core::panic::PanicInfo;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::address_space::{self, USER_SPACE_START};
+use rust_kernel::arch::memory::AddressSpace;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// Duplicates an address space and checks both share the frame until one
+/// of them writes to it, and then see their own copy.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    let address = VirtAddr::new(USER_SPACE_START);
+    let page = Page::containing_address(address);
+    let value = address.as_mut_ptr::<u64>();
+
+    let mut first = AddressSpace::new().expect("creating an address space failed");
+    first.map(page, PageTableFlags::WRITABLE).unwrap();
+    unsafe {
+        first.switch();
+        *value = 1;
+    }
+
+    let second = first
+        .duplicate()
+        .expect("duplicating the address space failed");
+    assert_eq!(first.translate(address), second.translate(address));
+
+    unsafe {
+        second.switch();
+        assert_eq!(*value, 1);
+        *value = 2;
+        assert_ne!(first.translate(address), second.translate(address));
+
+        first.switch();
+        assert_eq!(
+            *value, 1,
+            "first address space sees the write of the second"
+        );
+        // The last reference, the frame is kept
+        let frame = first.translate(address);
+        *value = 3;
+        assert_eq!(first.translate(address), frame);
+
+        second.switch();
+        assert_eq!(*value, 2);
+    }
+
+    address_space::switch_to_kernel();
+    drop(first);
+    drop(second);
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
@@ -0,0 +1,58 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+
+use core::panic::PanicInfo;
+use rust_kernel::arch;
+use rust_kernel::arch::memory;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use x86_64::structures::paging::PageTableFlags;
+
+/// Reserves a lazy region and checks that only the touched page gets a
+/// frame, and that it is zeroed.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let region = memory::map_lazy(64 * 4096, flags, "lazy test").unwrap();
+    let touched = region.start + 3 * 4096u64;
+
+    assert!(memory::translate(touched).is_err());
+
+    unsafe {
+        let value = touched.as_mut_ptr::<u64>();
+        assert_eq!(*value, 0);
+        *value = 42;
+        assert_eq!(*value, 42);
+    }
+
+    assert!(memory::translate(touched).is_ok());
+    assert!(memory::translate(region.start).is_err());
+
+    memory::unmap(region.start).unwrap();
+    assert!(memory::translate(touched).is_err());
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
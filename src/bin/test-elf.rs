@@ -0,0 +1,62 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+
+use core::panic::PanicInfo;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::address_space::USER_SPACE_START;
+use rust_kernel::arch::memory::paging::phys_to_virt;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use rust_kernel::arch::user::UserExit;
+use rust_kernel::elf::loader;
+use rust_kernel::elf::testing::executable;
+
+/// mov rdi, [rsp]; mov eax, EXIT; syscall
+const CODE: [u8; 11] = [
+    0x48, 0x8b, 0x3c, 0x24, 0xb8, 0x02, 0x00, 0x00, 0x00, 0x0f, 0x05,
+];
+
+/// Loads an executable and runs it. It exits with argc, which it reads
+/// from the stack the loader built.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    // The segment gets 0x3000 bytes of .bss after the code
+    let file = executable(USER_SPACE_START + 0x1000, &CODE, CODE.len() as u64 + 0x3000);
+    let program = loader::load(&file, &["test", "a", "b"], &["HOME=/"])
+        .unwrap_or_else(|error| panic!("loading failed: {}", error));
+
+    // The .bss is mapped and zeroed
+    let bss = program
+        .address_space
+        .translate(program.entry + 0x2000u64)
+        .unwrap();
+    assert_eq!(unsafe { *phys_to_virt(bss).as_ptr::<u64>() }, 0);
+
+    assert_eq!(program.run(), UserExit::Exit(3));
+    drop(program);
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
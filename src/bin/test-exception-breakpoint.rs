@@ -12,7 +12,7 @@ extern crate lazy_static;
 
 use core::panic::PanicInfo;
 use core::sync::atomic::{AtomicUsize, Ordering};
-use rust_kernel::exit_qemu;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
 use x86_64::structures::idt::{ExceptionStackFrame, InterruptDescriptorTable};
 
 static BREAKPOINT_HANDLER_CALLED: AtomicUsize = AtomicUsize::new(0);
@@ -28,21 +28,24 @@ pub extern "C" fn _start(boot_info_address: usize) -> ! {
     x86_64::instructions::interrupts::int3();
 
     // Load the breakpoint called static integer and check if equal to 1
-    match BREAKPOINT_HANDLER_CALLED.load(Ordering::SeqCst) {
-        1 => serial_println!("ok"),
+    let exit_code = match BREAKPOINT_HANDLER_CALLED.load(Ordering::SeqCst) {
+        1 => {
+            serial_println!("ok");
+            QemuExitCode::Success
+        }
         0 => {
             serial_println!("failed");
             serial_println!("Breakpoint handler was not called.");
+            QemuExitCode::Failed
         }
         other => {
             serial_println!("failed");
             serial_println!("Breakpoint handler was called {} times", other);
+            QemuExitCode::Failed
         }
-    }
+    };
 
-    unsafe {
-        exit_qemu();
-    }
+    exit_qemu(exit_code);
     loop {}
 }
 
@@ -54,9 +57,7 @@ pub fn panic(info: &PanicInfo) -> ! {
     serial_println!("failed");
     serial_println!("{}", info);
 
-    unsafe {
-        exit_qemu();
-    }
+    exit_qemu(QemuExitCode::Failed);
 
     loop {}
 }
@@ -0,0 +1,108 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::panic::PanicInfo;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::address_space::USER_SPACE_START;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use rust_kernel::elf::testing::executable;
+use rust_kernel::process::{self, programs, ExitStatus, ProcessError};
+
+/// Makes stdout close-on-exec, forks and waits for the child, then exits
+/// with the exit code of the child. The child builds `argv` on its stack
+/// and executes "writer".
+///
+/// ```text
+///     mov eax, FCNTL; mov edi, 1; mov esi, F_SETFD; mov edx, FD_CLOEXEC
+///     syscall
+///     mov eax, FORK; syscall
+///     test rax, rax; jnz 2f
+///     lea rdi, [rip + path]; push 0; push rdi; mov rsi, rsp
+///     xor edx, edx; mov eax, EXECVE; syscall
+///     mov edi, 100; mov eax, EXIT; syscall
+/// 2:  mov rbx, rax; sub rsp, 16
+///     mov rdi, rax; mov rsi, rsp; mov eax, WAIT; syscall
+///     cmp rax, rbx; jne 3f
+///     mov edi, [rsp]; shr edi, 8; mov eax, EXIT; syscall
+/// 3:  mov edi, 101; mov eax, EXIT; syscall
+/// path: .asciz "writer"
+/// ```
+const PARENT: [u8; 125] = [
+    0xb8, 0x09, 0x00, 0x00, 0x00, 0xbf, 0x01, 0x00, 0x00, 0x00, 0xbe, 0x02, 0x00, 0x00, 0x00, 0xba,
+    0x01, 0x00, 0x00, 0x00, 0x0f, 0x05, 0xb8, 0x07, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x85, 0xc0,
+    0x75, 0x22, 0x48, 0x8d, 0x3d, 0x4d, 0x00, 0x00, 0x00, 0x6a, 0x00, 0x57, 0x48, 0x89, 0xe6, 0x31,
+    0xd2, 0xb8, 0x08, 0x00, 0x00, 0x00, 0x0f, 0x05, 0xbf, 0x64, 0x00, 0x00, 0x00, 0xb8, 0x02, 0x00,
+    0x00, 0x00, 0x0f, 0x05, 0x48, 0x89, 0xc3, 0x48, 0x83, 0xec, 0x10, 0x48, 0x89, 0xc7, 0x48, 0x89,
+    0xe6, 0xb8, 0x06, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x39, 0xd8, 0x75, 0x0d, 0x8b, 0x3c, 0x24,
+    0xc1, 0xef, 0x08, 0xb8, 0x02, 0x00, 0x00, 0x00, 0x0f, 0x05, 0xbf, 0x65, 0x00, 0x00, 0x00, 0xb8,
+    0x02, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x77, 0x72, 0x69, 0x74, 0x65, 0x72, 0x00,
+];
+
+/// Writes "ok\n" to stdout, which must be closed, and to stderr, which
+/// must not be. Exits with 3 if both went as expected.
+///
+/// ```text
+///     mov eax, WRITE; mov edi, 1; lea rsi, [rip + msg]; mov edx, 3
+///     syscall
+///     mov rbx, rax
+///     mov eax, WRITE; mov edi, 2; lea rsi, [rip + msg]; mov edx, 3
+///     syscall
+///     xor edi, edi; cmp rbx, -EBADF; sete dil
+///     cmp rax, 3; jne 1f; or edi, 2
+/// 1:  mov eax, EXIT; syscall
+/// msg: .ascii "ok\n"
+/// ```
+const WRITER: [u8; 80] = [
+    0xb8, 0x01, 0x00, 0x00, 0x00, 0xbf, 0x01, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x35, 0x3c, 0x00, 0x00,
+    0x00, 0xba, 0x03, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x89, 0xc3, 0xb8, 0x01, 0x00, 0x00, 0x00,
+    0xbf, 0x02, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x35, 0x21, 0x00, 0x00, 0x00, 0xba, 0x03, 0x00, 0x00,
+    0x00, 0x0f, 0x05, 0x31, 0xff, 0x48, 0x83, 0xfb, 0xf7, 0x40, 0x0f, 0x94, 0xc7, 0x48, 0x83, 0xf8,
+    0x03, 0x75, 0x03, 0x83, 0xcf, 0x02, 0xb8, 0x02, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x6f, 0x6b, 0x0a,
+];
+
+/// Runs a process which forks a child. The child shares the stack of its
+/// parent copy-on-write, executes another program, and finds stdout
+/// closed and stderr open.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    let address = USER_SPACE_START + 0x1000;
+    let writer = executable(address, &WRITER, WRITER.len() as u64);
+    programs::register("writer", Box::leak(writer.into_boxed_slice()));
+
+    let file = executable(address, &PARENT, PARENT.len() as u64);
+    let pid = process::spawn("parent", &file, &["parent"], &[], None)
+        .unwrap_or_else(|error| panic!("spawning failed: {}", error));
+
+    assert_eq!(process::run(pid), Ok(ExitStatus::Exited(3)));
+    assert_eq!(process::wait(None, None), Ok((pid, ExitStatus::Exited(3))));
+    // The parent reaped the child
+    assert_eq!(process::wait(Some(pid), None), Err(ProcessError::NoChild));
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
@@ -0,0 +1,63 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+
+use core::panic::PanicInfo;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::{self, paging, MemoryError};
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use x86_64::structures::paging::{PageSize, PageTableFlags, Size2MiB};
+
+/// Maps a region with a 2 MiB page, checks that an address inside it
+/// translates into one aligned huge frame and is reachable through the
+/// physical memory window, then unmaps it again.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let region = memory::map_huge(Size2MiB::SIZE, flags, "huge test").unwrap();
+    assert_eq!(region.page_size, Size2MiB::SIZE);
+    assert!(region.start.is_aligned(Size2MiB::SIZE));
+
+    let offset = 0x12_3458;
+    let interior = region.start + offset;
+    let physical = memory::translate(interior).unwrap();
+    assert!((physical - offset).is_aligned(Size2MiB::SIZE));
+    assert_eq!(memory::translate(region.start).unwrap(), physical - offset);
+
+    // The window maps the same frame, with huge pages too
+    let window = paging::phys_to_virt(physical);
+    assert_eq!(memory::translate(window), Ok(physical));
+    unsafe {
+        interior.as_mut_ptr::<u64>().write_volatile(0x1234_5678);
+        assert_eq!(window.as_ptr::<u64>().read_volatile(), 0x1234_5678);
+    }
+
+    memory::unmap(region.start).unwrap();
+    assert_eq!(memory::translate(interior), Err(MemoryError::NotMapped));
+    assert_eq!(memory::translate(region.start), Err(MemoryError::NotMapped));
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
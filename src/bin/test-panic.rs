@@ -6,7 +6,7 @@
 extern crate rust_kernel;
 
 use core::panic::PanicInfo;
-use rust_kernel::exit_qemu;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
 
 #[cfg(not(test))]
 #[no_mangle]
@@ -20,6 +20,6 @@ pub extern "C" fn _start() -> ! {
 pub fn panic(_info: &PanicInfo) -> ! {
     serial_println!("ok");
 
-    unsafe { exit_qemu(); }
+    exit_qemu(QemuExitCode::Success);
     loop {}
 }
\ No newline at end of file
@@ -0,0 +1,58 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+
+use core::panic::PanicInfo;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::address_space::USER_SPACE_START;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use rust_kernel::elf::testing::executable;
+use rust_kernel::process::{self, ExitStatus, ProcessError};
+
+/// mov eax, GETPID; syscall; mov edi, eax; mov eax, EXIT; syscall
+const CODE: [u8; 16] = [
+    0xb8, 0x05, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x89, 0xc7, 0xb8, 0x02, 0x00, 0x00, 0x00, 0x0f, 0x05,
+];
+
+/// Runs a process which exits with its PID, and reaps it.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    let file = executable(USER_SPACE_START + 0x1000, &CODE, CODE.len() as u64);
+    let pid = process::spawn("getpid", &file, &["getpid"], &[], None)
+        .unwrap_or_else(|error| panic!("spawning failed: {}", error));
+
+    assert_eq!(process::run(pid), Ok(ExitStatus::Exited(pid.0 as i32)));
+    assert_eq!(process::run(pid), Err(ProcessError::NotReady));
+    assert_eq!(process::current(), None);
+
+    assert_eq!(
+        process::wait(None, None),
+        Ok((pid, ExitStatus::Exited(pid.0 as i32)))
+    );
+    assert_eq!(process::wait(None, None), Err(ProcessError::NoChild));
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
@@ -0,0 +1,126 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+
+use core::panic::PanicInfo;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::address_space::USER_SPACE_START;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use rust_kernel::elf::testing::executable;
+use rust_kernel::process::signal::{self, SIGCONT, SIGKILL, SIGSTOP};
+use rust_kernel::process::{self, ExitStatus, State};
+
+/// Catches SIGUSR1 and sends it to itself. The handler stores the
+/// signal number where `rbx` points, which `sigreturn` must not undo, and
+/// `kill` must still return 0 after it. Exits with the stored number.
+///
+/// ```text
+///     sub rsp, 40
+///     lea rax, [rip + handler]; mov [rsp], rax
+///     mov qword ptr [rsp + 8], 0
+///     lea rax, [rip + restorer]; mov [rsp + 16], rax
+///     mov qword ptr [rsp + 24], 0
+///     mov qword ptr [rsp + 32], 0
+///     mov eax, SIGACTION; mov edi, SIGUSR1; mov rsi, rsp; xor edx, edx
+///     syscall
+///     lea rbx, [rsp + 32]
+///     mov eax, GETPID; syscall
+///     mov rdi, rax; mov esi, SIGUSR1; mov eax, KILL; syscall
+///     mov edi, [rbx]; add edi, eax
+///     mov eax, EXIT; syscall
+/// handler:
+///     mov [rbx], edi
+///     ret
+/// restorer:
+///     mov eax, SIGRETURN; syscall
+///     ud2
+/// ```
+const HANDLER: [u8; 121] = [
+    0x48, 0x83, 0xec, 0x28, 0x48, 0x8d, 0x05, 0x62, 0x00, 0x00, 0x00, 0x48, 0x89, 0x04, 0x24, 0x48,
+    0xc7, 0x44, 0x24, 0x08, 0x00, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x05, 0x51, 0x00, 0x00, 0x00, 0x48,
+    0x89, 0x44, 0x24, 0x10, 0x48, 0xc7, 0x44, 0x24, 0x18, 0x00, 0x00, 0x00, 0x00, 0x48, 0xc7, 0x44,
+    0x24, 0x20, 0x00, 0x00, 0x00, 0x00, 0xb8, 0x0b, 0x00, 0x00, 0x00, 0xbf, 0x0a, 0x00, 0x00, 0x00,
+    0x48, 0x89, 0xe6, 0x31, 0xd2, 0x0f, 0x05, 0x48, 0x8d, 0x5c, 0x24, 0x20, 0xb8, 0x05, 0x00, 0x00,
+    0x00, 0x0f, 0x05, 0x48, 0x89, 0xc7, 0xbe, 0x0a, 0x00, 0x00, 0x00, 0xb8, 0x0a, 0x00, 0x00, 0x00,
+    0x0f, 0x05, 0x8b, 0x3b, 0x01, 0xc7, 0xb8, 0x02, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x89, 0x3b, 0xc3,
+    0xb8, 0x0d, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x0f, 0x0b,
+];
+
+/// Stops itself with SIGSTOP, then exits with 7 if `kill` returned 0.
+///
+/// ```text
+///     mov eax, GETPID; syscall
+///     mov rdi, rax; mov esi, SIGSTOP; mov eax, KILL; syscall
+///     lea edi, [rax + 7]
+///     mov eax, EXIT; syscall
+/// ```
+const STOPPER: [u8; 32] = [
+    0xb8, 0x05, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x89, 0xc7, 0xbe, 0x13, 0x00, 0x00, 0x00, 0xb8,
+    0x0a, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x8d, 0x78, 0x07, 0xb8, 0x02, 0x00, 0x00, 0x00, 0x0f, 0x05,
+];
+
+/// Runs a process with a signal handler, and one which stops until it
+/// is continued. A stopped process only ends on SIGKILL.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    let address = USER_SPACE_START + 0x1000;
+    let spawn = |name, code: &[u8]| {
+        let file = executable(address, code, code.len() as u64);
+        process::spawn(name, &file, &[name], &[], None)
+            .unwrap_or_else(|error| panic!("spawning failed: {}", error))
+    };
+
+    let pid = spawn("handler", &HANDLER);
+    assert_eq!(process::run(pid), Ok(ExitStatus::Exited(10)));
+    assert_eq!(
+        process::wait(None, Some(pid)),
+        Ok((pid, ExitStatus::Exited(10)))
+    );
+
+    let pid = spawn("stopper", &STOPPER);
+    assert_eq!(process::run(pid), Ok(ExitStatus::Stopped(SIGSTOP)));
+    process::for_each(|info| {
+        if info.pid == pid {
+            assert_eq!(info.state, State::Stopped(SIGSTOP));
+        }
+    });
+    signal::send(pid, SIGCONT).unwrap();
+    assert_eq!(process::run(pid), Ok(ExitStatus::Exited(7)));
+    assert_eq!(
+        process::wait(None, Some(pid)),
+        Ok((pid, ExitStatus::Exited(7)))
+    );
+
+    let pid = spawn("stopper", &STOPPER);
+    assert_eq!(process::run(pid), Ok(ExitStatus::Stopped(SIGSTOP)));
+    signal::send(pid, SIGKILL).unwrap();
+    assert_eq!(
+        process::wait(None, Some(pid)),
+        Ok((pid, ExitStatus::Killed(SIGKILL)))
+    );
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
@@ -56,14 +56,12 @@ lazy_static! {
 }
 
 
-use rust_kernel::exit_qemu;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
 extern "x86-interrupt" fn double_fault_handler(
     _stack_frame: &mut ExceptionStackFrame,
     _error_code: u64,
 ) -> ! {
     serial_println!("ok");
-    unsafe {
-        exit_qemu();
-    }
+    exit_qemu(QemuExitCode::Success);
     loop {}
 }
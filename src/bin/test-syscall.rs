@@ -0,0 +1,101 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+
+use core::panic::PanicInfo;
+use core::ptr;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::address_space::{self, USER_SPACE_START};
+use rust_kernel::arch::memory::paging::phys_to_virt;
+use rust_kernel::arch::memory::AddressSpace;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use rust_kernel::arch::user::{enter_user_mode, UserExit};
+use rust_kernel::syscall;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+const MESSAGE: &[u8] = b"hello from user mode\n";
+const MESSAGE_OFFSET: u64 = 0x100;
+
+/// Writes `MESSAGE` with `syscall` and exits through `int 0x80` with the
+/// result of the write as exit code:
+///
+/// mov eax, WRITE; mov edi, 1; mov rsi, message; mov edx, len; syscall;
+/// mov edi, eax; mov eax, EXIT; int 0x80
+fn code(message: u64) -> [u8; 36] {
+    let mut code = [0; 36];
+    code[0] = 0xb8;
+    code[1] = syscall::WRITE as u8;
+    code[5] = 0xbf;
+    code[6] = syscall::STDOUT as u8;
+    code[10..12].copy_from_slice(&[0x48, 0xbe]);
+    code[12..20].copy_from_slice(&message.to_le_bytes());
+    code[20] = 0xba;
+    code[21] = MESSAGE.len() as u8;
+    code[25..29].copy_from_slice(&[0x0f, 0x05, 0x89, 0xc7]);
+    code[29] = 0xb8;
+    code[30] = syscall::EXIT as u8;
+    code[34..36].copy_from_slice(&[0xcd, 0x80]);
+    code
+}
+
+/// Runs code in ring 3 which makes system calls through both entries.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    let entry = VirtAddr::new(USER_SPACE_START);
+    let stack_page = Page::containing_address(entry + 0x10000u64);
+
+    let mut space = AddressSpace::new().expect("creating an address space failed");
+    let code_frame = space
+        .map(Page::containing_address(entry), PageTableFlags::empty())
+        .unwrap();
+    space
+        .map(
+            stack_page,
+            PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )
+        .unwrap();
+
+    let code = code((entry + MESSAGE_OFFSET).as_u64());
+    unsafe {
+        let page = phys_to_virt(code_frame.start_address()).as_mut_ptr::<u8>();
+        ptr::copy_nonoverlapping(code.as_ptr(), page, code.len());
+        ptr::copy_nonoverlapping(
+            MESSAGE.as_ptr(),
+            page.add(MESSAGE_OFFSET as usize),
+            MESSAGE.len(),
+        );
+    }
+
+    space.switch();
+    let exit = unsafe { enter_user_mode(entry, stack_page.start_address() + 4096u64) };
+    assert_eq!(exit, UserExit::Exit(MESSAGE.len() as i32));
+
+    address_space::switch_to_kernel();
+    drop(space);
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
@@ -0,0 +1,83 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(not(test), no_main)] // disable all Rust-level entry points
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
+
+// add the library as dependency (same crate name as executable)
+#[macro_use]
+extern crate rust_kernel;
+
+use core::panic::PanicInfo;
+use core::ptr;
+use rust_kernel::arch;
+use rust_kernel::arch::memory::address_space::{self, USER_SPACE_START};
+use rust_kernel::arch::memory::paging::phys_to_virt;
+use rust_kernel::arch::memory::AddressSpace;
+use rust_kernel::arch::power::{exit_qemu, QemuExitCode};
+use rust_kernel::arch::user::{enter_user_mode, UserExit};
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// push rax; mov ecx, 0x100000; 1: dec ecx; jnz 1b; hlt
+const CODE: [u8; 11] = [
+    0x50, 0xb9, 0x00, 0x00, 0x10, 0x00, 0xff, 0xc9, 0x75, 0xfc, 0xf4,
+];
+const HLT_OFFSET: u64 = 10;
+
+/// Runs code in ring 3 which uses its stack, loops while interrupts arrive
+/// and then executes the privileged `hlt`. The general protection fault
+/// has to bring us back here.
+#[cfg(not(test))]
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    arch::init(boot_info_address);
+
+    let entry = VirtAddr::new(USER_SPACE_START);
+    let stack_page = Page::containing_address(entry + 0x10000u64);
+
+    let mut space = AddressSpace::new().expect("creating an address space failed");
+    let code_frame = space
+        .map(Page::containing_address(entry), PageTableFlags::empty())
+        .unwrap();
+    space
+        .map(
+            stack_page,
+            PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )
+        .unwrap();
+
+    unsafe {
+        let code = phys_to_virt(code_frame.start_address()).as_mut_ptr::<u8>();
+        ptr::copy_nonoverlapping(CODE.as_ptr(), code, CODE.len());
+    }
+
+    space.switch();
+    let exit = unsafe { enter_user_mode(entry, stack_page.start_address() + 4096u64) };
+    assert_eq!(
+        exit,
+        UserExit::Fault {
+            exception: "general protection fault",
+            instruction: entry + HLT_OFFSET,
+        }
+    );
+
+    address_space::switch_to_kernel();
+    drop(space);
+
+    serial_println!("ok");
+
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+/// This function is called on panic.
+#[cfg(not(test))]
+#[panic_handler]
+#[no_mangle]
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("failed");
+
+    serial_println!("{}", info);
+
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
@@ -0,0 +1,69 @@
+//! # Kernel command line
+//!
+//! The bootloader has no way to pass a command line, so it is baked into
+//! the image at build time through the `KERNEL_CMDLINE` environment
+//! variable, for example:
+//!
+//! ```text
+//! KERNEL_CMDLINE="log=info,rust_kernel::arch=debug" make
+//! ```
+//!
+//! Options are separated by whitespace and have the form `key=value`.
+
+pub fn cmdline() -> &'static str {
+    option_env!("KERNEL_CMDLINE").unwrap_or("")
+}
+
+/// Returns the value of the option `key`, the last occurrence wins.
+pub fn get(key: &str) -> Option<&'static str> {
+    find(cmdline(), key)
+}
+
+fn find<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+    cmdline
+        .split_whitespace()
+        .filter_map(|option| {
+            let mut parts = option.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name == key => Some(value),
+                _ => None,
+            }
+        })
+        .last()
+}
+
+/// Parses a size with an optional `K`, `M` or `G` suffix, like `64M`.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let (number, shift) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 10),
+        b'M' | b'm' => (&value[..value.len() - 1], 20),
+        b'G' | b'g' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_option() {
+        let cmdline = "log=debug  heap_max=64M log=trace";
+
+        assert_eq!(find(cmdline, "log"), Some("trace"));
+        assert_eq!(find(cmdline, "heap_max"), Some("64M"));
+        assert_eq!(find(cmdline, "heap"), None);
+    }
+
+    #[test]
+    fn size_suffixes() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size("16m"), Some(16 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size(""), None);
+    }
+}
@@ -0,0 +1,26 @@
+//! Console input.
+//!
+//! Input devices (serial ports, the keyboard) push bytes into one queue
+//! which console programs read from, so they work the same on every
+//! console.
+use crate::console::ring_buffer::ByteQueue;
+use crate::process::signal;
+use crate::sync::irq_lock::IrqLock;
+
+static INPUT: IrqLock<ByteQueue> = IrqLock::new(ByteQueue::new());
+
+/// The byte Ctrl+C sends.
+const INTERRUPT: u8 = 0x03;
+
+/// Queues a byte of input. Input is dropped when nobody reads it. Ctrl+C
+/// sends `SIGINT` to the foreground process instead, if there is one.
+pub fn push(byte: u8) {
+    if byte == INTERRUPT && signal::interrupt_foreground() {
+        return;
+    }
+    INPUT.lock().push(byte);
+}
+
+pub fn read() -> Option<u8> {
+    INPUT.lock().pop()
+}
@@ -0,0 +1,206 @@
+//! # Console
+//!
+//! Every piece of kernel output goes through this module. Output devices
+//! implement the `Console` trait and are registered as sinks, each sink
+//! has its own level filter. A single `kprintln!` is written to every sink
+//! whose filter allows the level of the message.
+//!
+//! The default sinks (VGA, COM1 and the kernel message ring buffer) are
+//! part of the static sink table, so printing works before anything has
+//! been initialized.
+use core::fmt;
+
+use crate::device::serial::SERIAL_CONSOLE;
+use crate::device::vga_buffer::VGA_CONSOLE;
+use crate::sync::irq_lock::IrqLock;
+
+use self::ring_buffer::RingBufferConsole;
+
+pub mod input;
+pub mod ring_buffer;
+
+/// Maximum number of sinks which can be registered at the same time.
+const MAX_SINKS: usize = 8;
+
+/// Importance of a message, lower is more important.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// An output device for kernel messages.
+///
+/// Implementations lock their device internally, so `write_str` takes
+/// `&self` and sinks can be stored as `&'static dyn Console`.
+pub trait Console: Sync {
+    /// Short name used to address the sink, for example "vga".
+    fn name(&self) -> &'static str;
+
+    fn write_str(&self, s: &str);
+}
+
+#[derive(Debug)]
+pub enum ConsoleError {
+    /// All sink slots are in use.
+    Full,
+    /// A sink with the same name is already registered.
+    Duplicate,
+    /// No sink with the given name is registered.
+    NotFound,
+}
+
+#[derive(Clone, Copy)]
+struct Sink {
+    console: &'static dyn Console,
+    max_level: Level,
+}
+
+/// In-memory copy of the console output.
+pub static KMSG: RingBufferConsole = RingBufferConsole::new("kmsg");
+
+static SINKS: IrqLock<[Option<Sink>; MAX_SINKS]> = IrqLock::new([
+    Some(Sink {
+        console: &VGA_CONSOLE,
+        max_level: Level::Info,
+    }),
+    Some(Sink {
+        console: &SERIAL_CONSOLE,
+        max_level: Level::Trace,
+    }),
+    Some(Sink {
+        console: &KMSG,
+        max_level: Level::Trace,
+    }),
+    None,
+    None,
+    None,
+    None,
+    None,
+]);
+
+/// Registers a new sink which receives all messages up to `max_level`.
+pub fn register(console: &'static dyn Console, max_level: Level) -> Result<(), ConsoleError> {
+    let mut sinks = SINKS.lock();
+
+    if sinks
+        .iter()
+        .flatten()
+        .any(|sink| sink.console.name() == console.name())
+    {
+        return Err(ConsoleError::Duplicate);
+    }
+
+    let slot = sinks
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(ConsoleError::Full)?;
+    *slot = Some(Sink { console, max_level });
+
+    Ok(())
+}
+
+/// Removes the sink with the given name.
+pub fn unregister(name: &str) -> Result<(), ConsoleError> {
+    let mut sinks = SINKS.lock();
+
+    let slot = sinks
+        .iter_mut()
+        .find(|slot| slot.map_or(false, |sink| sink.console.name() == name))
+        .ok_or(ConsoleError::NotFound)?;
+    *slot = None;
+
+    Ok(())
+}
+
+/// Changes the level filter of the sink with the given name.
+pub fn set_level(name: &str, max_level: Level) -> Result<(), ConsoleError> {
+    let mut sinks = SINKS.lock();
+
+    let sink = sinks
+        .iter_mut()
+        .flatten()
+        .find(|sink| sink.console.name() == name)
+        .ok_or(ConsoleError::NotFound)?;
+    sink.max_level = max_level;
+
+    Ok(())
+}
+
+/// Calls `f` with the name and level filter of every registered sink.
+pub fn for_each_sink<F: FnMut(&'static str, Level)>(mut f: F) {
+    // Copy the table so `f` is free to print.
+    let sinks = *SINKS.lock();
+
+    for sink in sinks.iter().flatten() {
+        f(sink.console.name(), sink.max_level);
+    }
+}
+
+/// Forwards the formatted pieces of a message to every sink that
+/// accepts the message level. This way the message is formatted once,
+/// without needing a heap allocated buffer.
+struct Broadcast<'a> {
+    sinks: &'a [Option<Sink>],
+    level: Level,
+}
+
+impl<'a> fmt::Write for Broadcast<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for sink in self.sinks.iter().flatten() {
+            if self.level <= sink.max_level {
+                sink.console.write_str(s);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adapter to use `write!` on a single sink.
+pub struct ConsoleWriter<'a>(pub &'a dyn Console);
+
+impl<'a> fmt::Write for ConsoleWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
+
+pub fn print(level: Level, args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let sinks = SINKS.lock();
+    let mut broadcast = Broadcast {
+        sinks: &*sinks,
+        level,
+    };
+
+    broadcast.write_fmt(args).unwrap();
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::print($crate::console::Level::Info, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => (print!("\n"));
+    ($fmt:expr) => (print!(concat!( $fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (print!(concat!( $fmt, "\n"), $($arg)*));
+}
+
+#[macro_export]
+macro_rules! kprintln {
+    () => (print!("\n"));
+    ($fmt:expr) => {
+        println!(concat!("[ {:>4.4} ] ", $fmt), $crate::time::TIME.get_seconds())
+    };
+    ($fmt:expr, $($arg:tt)*) => {
+        println!(concat!("[ {:>4.4} ] ", $fmt), $crate::time::TIME.get_seconds(), $($arg)*)
+    };
+}
@@ -0,0 +1,206 @@
+//! Ring buffers.
+//!
+//! `RingBufferConsole` is an in-memory sink which keeps the last
+//! `RING_BUFFER_SIZE` bytes of output, when the buffer is full the oldest
+//! bytes are overwritten. `ByteQueue` is a small fixed size FIFO for
+//! device buffers, it refuses new bytes when it is full.
+use crate::console::Console;
+use crate::sync::irq_lock::IrqLock;
+
+pub const RING_BUFFER_SIZE: usize = 16 * 1024; // 16kb
+
+pub struct RingBuffer {
+    data: [u8; RING_BUFFER_SIZE],
+    /// Index of the oldest byte.
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    pub const fn new() -> Self {
+        RingBuffer {
+            data: [0; RING_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            let tail = (self.head + self.len) % RING_BUFFER_SIZE;
+            self.data[tail] = byte;
+
+            if self.len == RING_BUFFER_SIZE {
+                // Overwrote the oldest byte
+                self.head = (self.head + 1) % RING_BUFFER_SIZE;
+            } else {
+                self.len += 1;
+            }
+        }
+    }
+
+    /// Returns the contents, oldest first. Like `VecDeque::as_slices` the
+    /// contents are split in two parts when they wrap around the end.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let end = self.head + self.len;
+
+        if end <= RING_BUFFER_SIZE {
+            (&self.data[self.head..end], &[])
+        } else {
+            (
+                &self.data[self.head..],
+                &self.data[..end - RING_BUFFER_SIZE],
+            )
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+pub struct RingBufferConsole {
+    name: &'static str,
+    buffer: IrqLock<RingBuffer>,
+}
+
+impl RingBufferConsole {
+    pub const fn new(name: &'static str) -> Self {
+        RingBufferConsole {
+            name,
+            buffer: IrqLock::new(RingBuffer::new()),
+        }
+    }
+
+    /// Calls `f` with the buffer contents, oldest first. Interrupts are
+    /// disabled while `f` runs.
+    pub fn read<F: FnOnce(&[u8], &[u8])>(&self, f: F) {
+        let buffer = self.buffer.lock();
+        let (first, second) = buffer.as_slices();
+        f(first, second);
+    }
+
+    pub fn clear(&self) {
+        self.buffer.lock().clear();
+    }
+}
+
+impl Console for RingBufferConsole {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn write_str(&self, s: &str) {
+        self.buffer.lock().write(s.as_bytes());
+    }
+}
+
+pub const BYTE_QUEUE_SIZE: usize = 256;
+
+/// Fixed size FIFO which does not need the heap, so it can be used
+/// from interrupt handlers and before memory is initialized.
+pub struct ByteQueue {
+    data: [u8; BYTE_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl ByteQueue {
+    pub const fn new() -> Self {
+        ByteQueue {
+            data: [0; BYTE_QUEUE_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns false if the queue is full and the byte was dropped.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+
+        self.data[(self.head + self.len) % BYTE_QUEUE_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let byte = self.data[self.head];
+        self.head = (self.head + 1) % BYTE_QUEUE_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == BYTE_QUEUE_SIZE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::vec::Vec;
+
+    fn contents(buffer: &RingBuffer) -> Vec<u8> {
+        let (first, second) = buffer.as_slices();
+        let mut contents = Vec::from(first);
+        contents.extend_from_slice(second);
+        contents
+    }
+
+    #[test]
+    fn write_and_read() {
+        let mut buffer = RingBuffer::new();
+        buffer.write(b"hello ");
+        buffer.write(b"world");
+
+        assert_eq!(buffer.len(), 11);
+        assert_eq!(contents(&buffer), b"hello world");
+    }
+
+    #[test]
+    fn overwrites_oldest() {
+        let mut buffer = RingBuffer::new();
+        let data: Vec<u8> = (0..RING_BUFFER_SIZE + 10).map(|i| i as u8).collect();
+        buffer.write(&data);
+
+        assert_eq!(buffer.len(), RING_BUFFER_SIZE);
+        assert_eq!(contents(&buffer), &data[10..]);
+    }
+
+    #[test]
+    fn byte_queue_fifo() {
+        let mut queue = ByteQueue::new();
+        for i in 0..BYTE_QUEUE_SIZE {
+            assert!(queue.push(i as u8));
+        }
+        assert!(!queue.push(0xff));
+
+        for i in 0..BYTE_QUEUE_SIZE {
+            assert_eq!(queue.pop(), Some(i as u8));
+        }
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -0,0 +1,182 @@
+//! # Linear framebuffer text console
+//!
+//! Renders text to a 32 bits per pixel linear framebuffer using the 8x8
+//! font from the font8x8 crate. The bootloader currently leaves the machine
+//! in VGA text mode, so the framebuffer console is only registered once a
+//! driver has a framebuffer to hand over.
+use crate::arch::memory::{self, MemoryError};
+use crate::console::Console;
+use font8x8::legacy::BASIC_LEGACY;
+use spin::Mutex;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::PhysAddr;
+
+const GLYPH_SIZE: usize = 8;
+
+const FOREGROUND: u32 = 0x00ff_ff00; // yellow, same as the VGA console
+const BACKGROUND: u32 = 0x0000_0000;
+
+/// A linear framebuffer with 32 bit pixels.
+pub struct Framebuffer {
+    pixels: &'static mut [u32],
+    width: usize,
+    height: usize,
+    /// Number of pixels between the start of two rows.
+    stride: usize,
+}
+
+impl Framebuffer {
+    /// # Unsafety
+    ///
+    /// `base` must point to a mapped framebuffer of at least
+    /// `stride * height` pixels which is not used by anything else.
+    pub unsafe fn new(base: *mut u32, width: usize, height: usize, stride: usize) -> Self {
+        assert!(stride >= width);
+
+        Framebuffer {
+            pixels: core::slice::from_raw_parts_mut(base, stride * height),
+            width,
+            height,
+            stride,
+        }
+    }
+
+    /// Maps the framebuffer at physical address `address`, with huge pages
+    /// where it is aligned for them. The mapping is uncached.
+    ///
+    /// # Unsafety
+    ///
+    /// `address` must be a framebuffer of at least `stride * height`
+    /// pixels which is not used by anything else.
+    pub unsafe fn map(
+        address: PhysAddr,
+        width: usize,
+        height: usize,
+        stride: usize,
+    ) -> Result<Self, MemoryError> {
+        let size = (stride * height * 4) as u64;
+        let size = (size + 0xfff) & !0xfff;
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE
+            | PageTableFlags::NO_CACHE;
+
+        let region = memory::map_physical(address, size, flags, "framebuffer")?;
+        Ok(Framebuffer::new(
+            region.start.as_mut_ptr(),
+            width,
+            height,
+            stride,
+        ))
+    }
+}
+
+struct TextWriter {
+    framebuffer: Framebuffer,
+    column: usize,
+    row: usize,
+}
+
+impl TextWriter {
+    fn columns(&self) -> usize {
+        self.framebuffer.width / GLYPH_SIZE
+    }
+
+    fn rows(&self) -> usize {
+        self.framebuffer.height / GLYPH_SIZE
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.column = 0,
+            0x8 => self.column = self.column.saturating_sub(1),
+            byte => {
+                if self.column >= self.columns() {
+                    self.new_line();
+                }
+
+                let glyph = match byte {
+                    0x20..=0x7e => BASIC_LEGACY[byte as usize],
+                    _ => BASIC_LEGACY[b'?' as usize],
+                };
+                self.draw_glyph(glyph);
+                self.column += 1;
+            }
+        }
+    }
+
+    fn draw_glyph(&mut self, glyph: [u8; 8]) {
+        let x = self.column * GLYPH_SIZE;
+        let y = self.row * GLYPH_SIZE;
+        let stride = self.framebuffer.stride;
+
+        for (dy, bits) in glyph.iter().enumerate() {
+            let line = (y + dy) * stride + x;
+            for dx in 0..GLYPH_SIZE {
+                // Bit 0 is the leftmost pixel
+                let set = bits & (1 << dx) != 0;
+                self.framebuffer.pixels[line + dx] = if set { FOREGROUND } else { BACKGROUND };
+            }
+        }
+    }
+
+    /// Moves to the next text row, scrolling the screen up one text row
+    /// when the cursor is on the last row.
+    fn new_line(&mut self) {
+        self.column = 0;
+
+        if self.row + 1 < self.rows() {
+            self.row += 1;
+            return;
+        }
+
+        let stride = self.framebuffer.stride;
+        let text_rows = self.rows() * GLYPH_SIZE;
+        let pixels = &mut self.framebuffer.pixels;
+
+        pixels.copy_within(GLYPH_SIZE * stride..text_rows * stride, 0);
+        for pixel in &mut pixels[(text_rows - GLYPH_SIZE) * stride..text_rows * stride] {
+            *pixel = BACKGROUND;
+        }
+    }
+}
+
+/// Console sink which renders to a framebuffer once one is attached.
+pub struct FramebufferConsole {
+    writer: Mutex<Option<TextWriter>>,
+}
+
+pub static FRAMEBUFFER_CONSOLE: FramebufferConsole = FramebufferConsole {
+    writer: Mutex::new(None),
+};
+
+impl FramebufferConsole {
+    /// Attaches a framebuffer, clearing it. Register the console afterwards
+    /// to have it receive output.
+    pub fn attach(&self, mut framebuffer: Framebuffer) {
+        for pixel in framebuffer.pixels.iter_mut() {
+            *pixel = BACKGROUND;
+        }
+
+        *self.writer.lock() = Some(TextWriter {
+            framebuffer,
+            column: 0,
+            row: 0,
+        });
+    }
+}
+
+impl Console for FramebufferConsole {
+    fn name(&self) -> &'static str {
+        "framebuffer"
+    }
+
+    fn write_str(&self, s: &str) {
+        if let Some(writer) = self.writer.lock().as_mut() {
+            for byte in s.bytes() {
+                writer.write_byte(byte);
+            }
+        }
+    }
+}
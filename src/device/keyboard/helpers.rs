@@ -84,9 +84,12 @@ impl ModifierState {
         self.shift.is_pressed() ^ self.caps_lock
     }
 
-    /// Apply all of our modifiers to character and convert to String
+    /// Apply all of our modifiers to character and convert to String.
+    /// Control with a letter gives the control character, Ctrl+C is 0x03.
     pub fn apply_to(&self, ascii: u8) -> u8 {
-        if self.is_uppercase() {
+        if self.control.is_pressed() && ascii.is_ascii_alphabetic() {
+            ascii.to_ascii_uppercase() & 0x1f
+        } else if self.is_uppercase() {
             map_to_upper(ascii)
         } else {
             ascii
@@ -1,5 +1,6 @@
 use alloc::collections::VecDeque;
 
+use crate::console;
 use crate::device::keyboard::helpers::{Key, KeyEvent, STATE};
 use crate::device::keyboard::helpers::Key::*;
 use crate::device::keyboard::helpers::Modifier::*;
@@ -37,7 +38,22 @@ pub struct KeyPackage {
 }
 
 impl Keyboard {
+    /// Returns true if the buffer holds at least one complete scancode.
+    /// Multibyte scancodes arrive in separate interrupts, so the prefix
+    /// can be buffered without the second byte.
+    pub fn has_scancode(&self) -> bool {
+        match self.scancode_buffer.front() {
+            Some(0xE0) | Some(0xE1) => self.scancode_buffer.len() >= 2,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
     pub fn process_scancode(&mut self) -> Option<KeyPackage> {
+        if !self.has_scancode() {
+            return None;
+        }
+
         let scancode = match self.scancode_buffer.pop_front() {
             Some(scancode) => scancode,
             None => return None,
@@ -94,6 +110,29 @@ impl Keyboard {
     }
 }
 
+impl KeyPackage {
+    /// Queues the bytes a terminal would send for this key as console
+    /// input. Arrow keys become ANSI escape sequences.
+    pub fn push_console_input(&self) {
+        if let Some(character) = self.character {
+            console::input::push(character as u8);
+            return;
+        }
+
+        let sequence: &[u8] = match self.key {
+            Special(ArrowUp(true)) => b"\x1b[A",
+            Special(ArrowDown(true)) => b"\x1b[B",
+            Special(ArrowRight(true)) => b"\x1b[C",
+            Special(ArrowLeft(true)) => b"\x1b[D",
+            _ => b"",
+        };
+
+        for &byte in sequence {
+            console::input::push(byte);
+        }
+    }
+}
+
 lazy_static! {
     pub static ref KEYBOARD: IrqLock<Keyboard> = IrqLock::new(Keyboard {
         scancode_buffer: VecDeque::new(),
@@ -2,6 +2,8 @@
 pub mod serial;
 #[macro_use]
 pub mod vga_buffer;
+pub mod framebuffer;
 pub mod keyboard;
+pub mod pci;
 pub mod pic8259;
 
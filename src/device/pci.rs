@@ -0,0 +1,133 @@
+//! # PCI
+//!
+//! Enumerates PCI functions through configuration space access
+//! mechanism #1 (the 0xCF8 address and 0xCFC data ports).
+use x86_64::instructions::port::Port;
+
+use crate::sync::irq_lock::IrqLock;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// Serializes access to the address/data port pair.
+static CONFIG_LOCK: IrqLock<()> = IrqLock::new(());
+
+/// Location of a function on the PCI bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Address {
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        let address = 1 << 31
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xFC) as u32;
+
+        let _guard = CONFIG_LOCK.lock();
+        unsafe {
+            Port::new(CONFIG_ADDRESS).write(address);
+            Port::new(CONFIG_DATA).read()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Function {
+    pub address: Address,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+}
+
+impl Function {
+    fn probe(address: Address) -> Option<Function> {
+        let id = address.read_u32(0x00);
+        let vendor_id = id as u16;
+
+        if vendor_id == 0xFFFF {
+            return None;
+        }
+
+        let class = address.read_u32(0x08);
+        let header = address.read_u32(0x0C);
+
+        Some(Function {
+            address,
+            vendor_id,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type: (header >> 16) as u8,
+        })
+    }
+
+    fn is_multifunction(&self) -> bool {
+        self.header_type & 0x80 != 0
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        match (self.class, self.subclass) {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "Mass storage controller",
+            (0x02, 0x00) => "Ethernet controller",
+            (0x02, _) => "Network controller",
+            (0x03, 0x00) => "VGA compatible controller",
+            (0x03, _) => "Display controller",
+            (0x04, _) => "Multimedia controller",
+            (0x05, _) => "Memory controller",
+            (0x06, 0x00) => "Host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "Bridge",
+            (0x07, _) => "Communication controller",
+            (0x08, _) => "System peripheral",
+            (0x0C, 0x03) => "USB controller",
+            (0x0C, 0x05) => "SMBus controller",
+            (0x0C, _) => "Serial bus controller",
+            _ => "Unknown device",
+        }
+    }
+}
+
+/// Calls `f` for every function on every bus.
+pub fn for_each_function<F: FnMut(&Function)>(mut f: F) {
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            let address = Address {
+                bus,
+                device,
+                function: 0,
+            };
+
+            let first = match Function::probe(address) {
+                Some(function) => function,
+                None => continue,
+            };
+            f(&first);
+
+            if !first.is_multifunction() {
+                continue;
+            }
+
+            for function in 1..8u8 {
+                let address = Address { function, ..address };
+                if let Some(function) = Function::probe(address) {
+                    f(&function);
+                }
+            }
+        }
+    }
+}
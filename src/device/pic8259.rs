@@ -9,8 +9,16 @@ use spin;
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
-pub const TIMER_INTERRUPT_ID: u8 = PIC_1_OFFSET;
-pub const KEYBOARD_INTERRUPT_ID: u8 = PIC_1_OFFSET + 1;
+// IRQ lines
+pub const TIMER_IRQ: u8 = 0;
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const COM2_IRQ: u8 = 3; // shared with COM4
+pub const COM1_IRQ: u8 = 4; // shared with COM3
+
+pub const TIMER_INTERRUPT_ID: u8 = PIC_1_OFFSET + TIMER_IRQ;
+pub const KEYBOARD_INTERRUPT_ID: u8 = PIC_1_OFFSET + KEYBOARD_IRQ;
+pub const COM2_INTERRUPT_ID: u8 = PIC_1_OFFSET + COM2_IRQ;
+pub const COM1_INTERRUPT_ID: u8 = PIC_1_OFFSET + COM1_IRQ;
 
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
@@ -18,3 +26,22 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub fn init() {
     unsafe { PICS.lock().initialize() };
 }
+
+/// Unmasks an IRQ line. Lines 8 to 15 are on the second PIC, which is
+/// chained to line 2 of the first PIC.
+pub fn unmask(irq: u8) {
+    use x86_64::instructions::port::Port;
+
+    let (port, line) = if irq < 8 {
+        (0x21, irq)
+    } else {
+        unmask(2);
+        (0xA1, irq - 8)
+    };
+
+    let mut data: Port<u8> = Port::new(port);
+    unsafe {
+        let mask = data.read();
+        data.write(mask & !(1 << line));
+    }
+}
@@ -1,20 +1,413 @@
-use spin::Mutex;
-use uart_16550::SerialPort;
+//! # 16550 UART driver
+//!
+//! Interrupt driven driver for the four legacy serial ports. Received
+//! bytes are buffered in the RX queue by the interrupt handler, bytes to
+//! send are queued in the TX queue and sent whenever the transmitter is
+//! empty. When the TX queue is full the driver falls back to polling, so
+//! output is never lost, even with interrupts disabled.
+//!
+//! COM1 is the serial console, its input is forwarded to the console input.
+use core::fmt;
 
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        unsafe {
-        let mut serial_port = SerialPort::new(0x3F8);
+use x86_64::instructions::port::Port;
 
-        serial_port.init();
-        Mutex::new(serial_port)
+use crate::arch::interrupts;
+use crate::console::ring_buffer::ByteQueue;
+use crate::console::{self, Console};
+use crate::sync::irq_lock::IrqLock;
+
+pub const COM1_BASE: u16 = 0x3F8;
+pub const COM2_BASE: u16 = 0x2F8;
+pub const COM3_BASE: u16 = 0x3E8;
+pub const COM4_BASE: u16 = 0x2E8;
+
+/// Base frequency of the UART divided by 16, the divisor for a baud rate
+/// is `UART_CLOCK / baud_rate`.
+const UART_CLOCK: u32 = 115_200;
+
+/// Size of the transmitter FIFO of a 16550.
+const TX_FIFO_SIZE: usize = 16;
+
+// Register offsets from the base port
+const DATA: u16 = 0; // DLL when DLAB is set
+const INTERRUPT_ENABLE: u16 = 1; // DLM when DLAB is set
+const FIFO_CONTROL: u16 = 2; // interrupt identification on read
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+// Interrupt enable bits
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+
+// Line control bits
+const LCR_DLAB: u8 = 1 << 7;
+
+// Modem control bits
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT2: u8 = 1 << 3; // connects the interrupt line to the PIC
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+// Line status bits
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TX_EMPTY: u8 = 1 << 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Five = 0b00,
+    Six = 0b01,
+    Seven = 0b10,
+    Eight = 0b11,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None = 0b000,
+    Odd = 0b001,
+    Even = 0b011,
+    Mark = 0b101,
+    Space = 0b111,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One = 0,
+    Two = 1,
+}
+
+/// Baud rate and frame format of a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl LineConfig {
+    /// 115200 baud, 8 data bits, no parity and 1 stop bit.
+    pub const fn default() -> Self {
+        LineConfig {
+            baud_rate: 115_200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
         }
-    };
+    }
+
+    /// Parses settings in the usual `<baud>,<data bits><parity><stop bits>`
+    /// notation, for example `9600,7e1`. The frame format is optional.
+    pub fn parse(settings: &str) -> Option<Self> {
+        let mut parts = settings.splitn(2, ',');
+        let baud_rate: u32 = parts.next()?.parse().ok()?;
+
+        if baud_rate == 0 || UART_CLOCK % baud_rate != 0 {
+            return None;
+        }
+
+        let mut config = LineConfig {
+            baud_rate,
+            ..LineConfig::default()
+        };
+
+        if let Some(format) = parts.next() {
+            let format = format.as_bytes();
+            if format.len() != 3 {
+                return None;
+            }
+
+            config.data_bits = match format[0] {
+                b'5' => DataBits::Five,
+                b'6' => DataBits::Six,
+                b'7' => DataBits::Seven,
+                b'8' => DataBits::Eight,
+                _ => return None,
+            };
+            config.parity = match format[1].to_ascii_lowercase() {
+                b'n' => Parity::None,
+                b'o' => Parity::Odd,
+                b'e' => Parity::Even,
+                b'm' => Parity::Mark,
+                b's' => Parity::Space,
+                _ => return None,
+            };
+            config.stop_bits = match format[2] {
+                b'1' => StopBits::One,
+                b'2' => StopBits::Two,
+                _ => return None,
+            };
+        }
+
+        Some(config)
+    }
+
+    fn divisor(&self) -> u16 {
+        (UART_CLOCK / self.baud_rate) as u16
+    }
+
+    fn line_control(&self) -> u8 {
+        self.data_bits as u8 | (self.stop_bits as u8) << 2 | (self.parity as u8) << 3
+    }
+}
+
+pub struct Uart {
+    base: u16,
+    config: LineConfig,
+    initialized: bool,
+    /// Set when the port passed the loopback test.
+    present: bool,
+    /// Set when the port is interrupt driven.
+    interrupts: bool,
+    /// Forward received bytes to the console input.
+    console_input: bool,
+    rx: ByteQueue,
+    tx: ByteQueue,
+}
+
+pub static COM1: IrqLock<Uart> = IrqLock::new(Uart::new(COM1_BASE, true));
+pub static COM2: IrqLock<Uart> = IrqLock::new(Uart::new(COM2_BASE, false));
+pub static COM3: IrqLock<Uart> = IrqLock::new(Uart::new(COM3_BASE, false));
+pub static COM4: IrqLock<Uart> = IrqLock::new(Uart::new(COM4_BASE, false));
+
+impl Uart {
+    const fn new(base: u16, console_input: bool) -> Self {
+        Uart {
+            base,
+            config: LineConfig::default(),
+            initialized: false,
+            present: false,
+            interrupts: false,
+            console_input,
+            rx: ByteQueue::new(),
+            tx: ByteQueue::new(),
+        }
+    }
+
+    fn read_register(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.base + register).read() }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        unsafe { Port::new(self.base + register).write(value) }
+    }
+
+    /// Programs the line settings and checks if the port exists. Returns
+    /// whether the port is present. Interrupts stay disabled.
+    pub fn init(&mut self, config: LineConfig) -> bool {
+        self.initialized = true;
+        self.interrupts = false;
+        self.write_register(INTERRUPT_ENABLE, 0);
+        self.set_config(config);
+
+        // Enable and clear the FIFOs, interrupt when 14 bytes are received.
+        self.write_register(FIFO_CONTROL, 0xC7);
+
+        // Check the port in loopback mode, a missing port reads back 0xFF.
+        self.write_register(MODEM_CONTROL, MCR_RTS | MCR_OUT2 | MCR_LOOPBACK);
+        self.write_register(DATA, 0xAE);
+        self.present = self.read_register(DATA) == 0xAE;
+
+        self.write_register(MODEM_CONTROL, MCR_DTR | MCR_RTS | MCR_OUT2);
+
+        self.present
+    }
+
+    pub fn set_config(&mut self, config: LineConfig) {
+        let divisor = config.divisor();
+
+        self.write_register(LINE_CONTROL, LCR_DLAB);
+        self.write_register(DATA, divisor as u8);
+        self.write_register(INTERRUPT_ENABLE, (divisor >> 8) as u8);
+        self.write_register(LINE_CONTROL, config.line_control());
+
+        self.config = config;
+    }
+
+    pub fn config(&self) -> LineConfig {
+        self.config
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Switches the port to interrupt driven mode. The IRQ line of the
+    /// port must be unmasked at the PIC.
+    pub fn enable_interrupts(&mut self) {
+        self.interrupts = true;
+        self.update_interrupt_enable();
+    }
+
+    fn update_interrupt_enable(&mut self) {
+        let mut enable = IER_RX_AVAILABLE;
+        if !self.tx.is_empty() {
+            enable |= IER_TX_EMPTY;
+        }
+        self.write_register(INTERRUPT_ENABLE, enable);
+    }
+
+    fn ensure_initialized(&mut self) {
+        if !self.initialized {
+            self.init(LineConfig::default());
+        }
+    }
+
+    fn tx_empty(&self) -> bool {
+        self.read_register(LINE_STATUS) & LSR_TX_EMPTY != 0
+    }
+
+    fn send_polling(&mut self, byte: u8) {
+        while !self.tx_empty() {
+            interrupts::pause();
+        }
+        self.write_register(DATA, byte);
+    }
+
+    /// Moves queued bytes to the transmitter FIFO if it is empty.
+    fn fill_tx_fifo(&mut self) {
+        if !self.tx_empty() {
+            return;
+        }
+
+        for _ in 0..TX_FIFO_SIZE {
+            match self.tx.pop() {
+                Some(byte) => self.write_register(DATA, byte),
+                None => break,
+            }
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        self.ensure_initialized();
+
+        if !self.interrupts {
+            self.send_polling(byte);
+            return;
+        }
+
+        if self.tx.is_full() {
+            // Make room by sending the oldest byte ourselves.
+            let oldest = self.tx.pop().unwrap();
+            self.send_polling(oldest);
+        }
+
+        self.tx.push(byte);
+        self.fill_tx_fifo();
+        self.update_interrupt_enable();
+    }
+
+    /// Waits until all queued bytes have been handed to the transmitter.
+    pub fn flush(&mut self) {
+        while let Some(byte) = self.tx.pop() {
+            self.send_polling(byte);
+        }
+
+        if self.interrupts {
+            self.update_interrupt_enable();
+        }
+    }
+
+    /// Returns the next received byte, if any.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        self.ensure_initialized();
+
+        if self.interrupts {
+            return self.rx.pop();
+        }
+
+        if self.read_register(LINE_STATUS) & LSR_DATA_READY != 0 {
+            Some(self.read_register(DATA))
+        } else {
+            None
+        }
+    }
+
+    /// Services the port, called from the interrupt handler.
+    pub fn handle_interrupt(&mut self) {
+        if !self.interrupts {
+            return;
+        }
+
+        while self.read_register(LINE_STATUS) & LSR_DATA_READY != 0 {
+            let byte = self.read_register(DATA);
+
+            if self.console_input {
+                console::input::push(byte);
+            } else {
+                self.rx.push(byte);
+            }
+        }
+
+        self.fill_tx_fifo();
+        self.update_interrupt_enable();
+    }
+}
+
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Initializes all serial ports and switches the ports which are present
+/// to interrupt driven mode. The line settings of COM1 can be set with the
+/// `serial` command line option, for example `serial=9600,8n1`.
+pub fn init() {
+    use crate::cmdline;
+    use crate::device::pic8259;
+
+    let console_config = cmdline::get("serial")
+        .and_then(LineConfig::parse)
+        .unwrap_or_else(LineConfig::default);
+
+    for (port, config) in [
+        (&COM1, console_config),
+        (&COM2, LineConfig::default()),
+        (&COM3, LineConfig::default()),
+        (&COM4, LineConfig::default()),
+    ]
+    .iter()
+    {
+        let mut uart = port.lock();
+        if uart.init(*config) {
+            uart.enable_interrupts();
+        }
+    }
+
+    pic8259::unmask(pic8259::COM1_IRQ);
+    pic8259::unmask(pic8259::COM2_IRQ);
+}
+
+/// Flushes the console port, for example before halting.
+pub fn flush() {
+    COM1.lock().flush();
 }
 
 pub fn print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
-    SERIAL1.lock().write_fmt(args).expect("Printing to serial failed");
+    COM1.lock().write_fmt(args).expect("Printing to serial failed");
+}
+
+/// Console sink which writes to COM1.
+pub struct SerialConsole;
+
+pub static SERIAL_CONSOLE: SerialConsole = SerialConsole;
+
+impl Console for SerialConsole {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write_str(&self, s: &str) {
+        use core::fmt::Write;
+        COM1.lock()
+            .write_str(s)
+            .expect("Printing to serial failed");
+    }
 }
 
 // Prints to the host through the serial interface.
@@ -31,4 +424,37 @@ macro_rules! serial_println {
     () => (serial_print!("\n"));
     ($fmt:expr) => (serial_print!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => (serial_print!(concat!($fmt, "\n"), $($arg)*));
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_line_config() {
+        let config = LineConfig::parse("9600,7e2").unwrap();
+
+        assert_eq!(config.baud_rate, 9600);
+        assert_eq!(config.data_bits, DataBits::Seven);
+        assert_eq!(config.parity, Parity::Even);
+        assert_eq!(config.stop_bits, StopBits::Two);
+        assert_eq!(config.divisor(), 12);
+        assert_eq!(config.line_control(), 0b0001_1110);
+    }
+
+    #[test]
+    fn parse_baud_rate_only() {
+        let config = LineConfig::parse("38400").unwrap();
+
+        assert_eq!(config.baud_rate, 38400);
+        assert_eq!(config.line_control(), 0b0000_0011);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(LineConfig::parse("fast"), None);
+        assert_eq!(LineConfig::parse("0"), None);
+        assert_eq!(LineConfig::parse("1000"), None);
+        assert_eq!(LineConfig::parse("9600,8x1"), None);
+    }
+}
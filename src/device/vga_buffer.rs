@@ -1,6 +1,7 @@
 //! # VGA buffer interface
 //!
 //!  Interface to write to the VGA buffer.
+use crate::console::Console;
 use core::fmt;
 use spin::Mutex;
 use volatile::Volatile;
@@ -123,9 +124,13 @@ impl Writer {
     ///
     /// If the byte is a newline we write a newline.
     /// We also write a newline if the column position exceeds the buffer width.
+    /// A carriage return moves to the start of the row and a backspace moves
+    /// one column back without erasing, like on a terminal.
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
+            0x8 => self.column_position = self.column_position.saturating_sub(1),
             byte => {
                 if self.column_position >= BUFFER_WIDTH {
                     self.new_line();
@@ -182,7 +187,7 @@ impl Writer {
     pub fn write_string(&mut self, s: &str) {
         for byte in s.bytes() {
             match byte {
-                0x20..=0x7e | b'\n' => self.write_byte(byte), // printable ascii or newline
+                0x20..=0x7e | b'\n' | b'\r' | 0x8 => self.write_byte(byte), // printable ascii or cursor movement
                 _ => self.write_byte(0xfe), // print square for non-printable ascii
             }
         }
@@ -197,41 +202,25 @@ impl fmt::Write for Writer {
     }
 }
 
-#[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => ($crate::device::vga_buffer::print(format_args!($($arg)*)));
-}
+pub fn print(args: fmt::Arguments) {
+    use core::fmt::Write;
 
-#[macro_export]
-macro_rules! println {
-    () => (print!("\n"));
-    ($fmt:expr) => (print!(concat!( $fmt, "\n")));
-    ($fmt:expr, $($arg:tt)*) => (print!(concat!( $fmt, "\n"), $($arg)*));
+    WRITER.lock().write_fmt(args).unwrap();
 }
 
-#[macro_export]
-macro_rules! kprintln {
-    () => (print!("\n"));
-    ($fmt:expr) => {
-        if $crate::HEAP_ALLOCATOR.lock().size() != 0 {
-            println!(concat!("[ {:>4.4} ] ", $fmt), $crate::time::TIME.get_seconds())
-        } else {
-            println!(concat!("[ no time ] ", $fmt))
-        }
-    };
-    ($fmt:expr, $($arg:tt)*) => {
-         if $crate::HEAP_ALLOCATOR.lock().size() != 0{
-            println!(concat!("[ {:>4.4} ] ", $fmt), $crate::time::TIME.get_seconds(), $($arg)*)
-         } else {
-            println!(concat!("[ no time ] ", $fmt), $($arg)*)
-         }
-    };
-}
+/// Console sink which writes to the VGA text buffer.
+pub struct VgaConsole;
 
-pub fn print(args: fmt::Arguments) {
-    use core::fmt::Write;
+pub static VGA_CONSOLE: VgaConsole = VgaConsole;
 
-    WRITER.lock().write_fmt(args).unwrap();
+impl Console for VgaConsole {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn write_str(&self, s: &str) {
+        WRITER.lock().write_string(s);
+    }
 }
 
 /// Tests
@@ -324,4 +313,16 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn carriage_return_and_backspace() {
+        let mut writer = construct_writer();
+        writer.write_string("abc\rx\x08y");
+
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 1];
+        assert_eq!(row[0].read().ascii_character, b'y');
+        assert_eq!(row[1].read().ascii_character, b'b');
+        assert_eq!(row[2].read().ascii_character, b'c');
+        assert_eq!(writer.column_position, 1);
+    }
 }
@@ -0,0 +1,265 @@
+//! # Loading executables
+//!
+//! `load` creates an address space for an executable. Every `PT_LOAD`
+//! segment is mapped to fresh frames, readable always, writable if it has
+//! `PF_W` and executable only if it has `PF_X`. The frames come zeroed,
+//! so the part of a segment past its data, the `.bss`, is zero.
+//!
+//! The stack sits at the top of the user part. Its top page holds the
+//! arguments, environment and auxiliary vector the System V ABI puts
+//! there, the pages below it are demand paged.
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use core::ptr;
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+use super::{Elf, ElfError, ProgramHeader, PF_W, PF_X, PROGRAM_HEADER_SIZE};
+use crate::arch::memory::address_space::{self, USER_SPACE_END, USER_SPACE_START};
+use crate::arch::memory::paging::phys_to_virt;
+use crate::arch::memory::{AddressSpace, MemoryError};
+use crate::arch::user::{enter_user_mode, UserExit};
+
+/// The top of the user stack, the page above it stays unmapped.
+pub const USER_STACK_TOP: u64 = USER_SPACE_END - Size4KiB::SIZE;
+/// Size in pages of the user stack.
+pub const USER_STACK_PAGES: u64 = 256;
+
+/// Auxiliary vector entry types.
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+
+/// An executable loaded into its address space, ready to run.
+pub struct Program {
+    pub address_space: AddressSpace,
+    pub entry: VirtAddr,
+    pub stack_pointer: VirtAddr,
+}
+
+impl Program {
+    /// Runs the program in its address space until it exits or faults.
+    pub fn run(&self) -> UserExit {
+        self.address_space.switch();
+        let exit = unsafe { enter_user_mode(self.entry, self.stack_pointer) };
+        address_space::switch_to_kernel();
+        exit
+    }
+}
+
+/// Loads the executable `file` into a new address space, with `args` and
+/// `env` on its stack.
+pub fn load(file: &[u8], args: &[&str], env: &[&str]) -> Result<Program, ElfError> {
+    let elf = Elf::parse(file)?;
+    let header = elf.header();
+    if header.entry < USER_SPACE_START || header.entry >= USER_SPACE_END {
+        return Err(ElfError::BadAddress);
+    }
+
+    let mut address_space = AddressSpace::new()?;
+    for segment in elf.load_segments() {
+        load_segment(&mut address_space, &segment, segment.data(file)?)?;
+    }
+
+    let mut auxv = vec![
+        (AT_PHENT, PROGRAM_HEADER_SIZE as u64),
+        (AT_PHNUM, u64::from(header.program_header_count)),
+        (AT_PAGESZ, Size4KiB::SIZE),
+        (AT_ENTRY, header.entry),
+    ];
+    if let Some(address) = elf.program_headers_address() {
+        auxv.push((AT_PHDR, address));
+    }
+
+    let stack_pointer = map_stack(&mut address_space, args, env, &auxv)?;
+
+    Ok(Program {
+        address_space,
+        entry: VirtAddr::new(header.entry),
+        stack_pointer,
+    })
+}
+
+/// Maps the pages of `segment` and copies `data` into them.
+fn load_segment(
+    address_space: &mut AddressSpace,
+    segment: &ProgramHeader,
+    data: &[u8],
+) -> Result<(), ElfError> {
+    let start = segment.virtual_address;
+    let end = start
+        .checked_add(segment.memory_size)
+        .ok_or(ElfError::BadAddress)?;
+    if start < USER_SPACE_START || end > USER_SPACE_END {
+        return Err(ElfError::BadAddress);
+    }
+
+    let mut flags = PageTableFlags::empty();
+    if segment.flags & PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if segment.flags & PF_X == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    let data_end = start + data.len() as u64;
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+    let last = Page::containing_address(VirtAddr::new(end - 1));
+
+    for page in Page::range_inclusive(first, last) {
+        let frame = address_space
+            .map(page, flags)
+            .map_err(|error| match error {
+                MemoryError::AlreadyMapped => ElfError::OverlappingSegments,
+                error => error.into(),
+            })?;
+
+        // The part of the data in this page
+        let page_start = page.start_address().as_u64();
+        let copy_start = max(page_start, start);
+        let copy_end = min(page_start + Size4KiB::SIZE, data_end);
+        if copy_start >= copy_end {
+            continue;
+        }
+
+        let source = &data[(copy_start - start) as usize..(copy_end - start) as usize];
+        let destination = phys_to_virt(frame.start_address()) + (copy_start - page_start);
+        unsafe {
+            ptr::copy_nonoverlapping(source.as_ptr(), destination.as_mut_ptr(), source.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps the user stack and returns the initial stack pointer.
+fn map_stack(
+    address_space: &mut AddressSpace,
+    args: &[&str],
+    env: &[&str],
+    auxv: &[(u64, u64)],
+) -> Result<VirtAddr, ElfError> {
+    let top = VirtAddr::new(USER_STACK_TOP);
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let (contents, stack_pointer) = build_stack(top, args, env, auxv)?;
+
+    let top_page = Page::containing_address(top - 1u64);
+    let frame = address_space.map(top_page, flags)?;
+    unsafe {
+        ptr::copy_nonoverlapping(
+            contents.as_ptr(),
+            phys_to_virt(frame.start_address()).as_mut_ptr(),
+            contents.len(),
+        );
+    }
+
+    address_space.map_lazy(
+        top_page - (USER_STACK_PAGES - 1),
+        USER_STACK_PAGES - 1,
+        flags,
+    )?;
+
+    Ok(stack_pointer)
+}
+
+/// Lays out the initial stack in the page below `top`: argc, the argv and
+/// envp pointers, each list ending with a null pointer, the auxiliary
+/// vector ending with `AT_NULL`, and the strings above them. Returns the
+/// page and the stack pointer, which points at argc and is 16 byte
+/// aligned.
+pub fn build_stack(
+    top: VirtAddr,
+    args: &[&str],
+    env: &[&str],
+    auxv: &[(u64, u64)],
+) -> Result<(Vec<u8>, VirtAddr), ElfError> {
+    let page_size = Size4KiB::SIZE as usize;
+    let base = top.as_u64() - Size4KiB::SIZE;
+    let mut page = vec![0; page_size];
+    let mut position = page_size;
+
+    // The strings, the page is zeroed so they are null terminated
+    let mut pointers = Vec::with_capacity(args.len() + env.len());
+    for string in args.iter().chain(env.iter()) {
+        let bytes = string.as_bytes();
+        position = position
+            .checked_sub(bytes.len() + 1)
+            .ok_or(ElfError::ArgumentsTooLong)?;
+        page[position..position + bytes.len()].copy_from_slice(bytes);
+        pointers.push(base + position as u64);
+    }
+    let (arg_pointers, env_pointers) = pointers.split_at(args.len());
+
+    let mut words = Vec::new();
+    words.push(args.len() as u64);
+    words.extend_from_slice(arg_pointers);
+    words.push(0);
+    words.extend_from_slice(env_pointers);
+    words.push(0);
+    for &(kind, value) in auxv {
+        words.push(kind);
+        words.push(value);
+    }
+    words.push(AT_NULL);
+    words.push(0);
+
+    position = position
+        .checked_sub(words.len() * 8)
+        .ok_or(ElfError::ArgumentsTooLong)?
+        & !0xf;
+    for (index, word) in words.iter().enumerate() {
+        let offset = position + index * 8;
+        page[offset..offset + 8].copy_from_slice(&word.to_le_bytes());
+    }
+
+    Ok((page, VirtAddr::new(base + position as u64)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn word(page: &[u8], base: u64, address: u64) -> u64 {
+        let offset = (address - base) as usize;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&page[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn string(page: &[u8], base: u64, address: u64) -> &str {
+        let start = (address - base) as usize;
+        let len = page[start..].iter().position(|&byte| byte == 0).unwrap();
+        core::str::from_utf8(&page[start..start + len]).unwrap()
+    }
+
+    #[test]
+    fn stack_layout() {
+        let top = VirtAddr::new(USER_STACK_TOP);
+        let base = USER_STACK_TOP - Size4KiB::SIZE;
+        let (page, stack_pointer) =
+            build_stack(top, &["init", "-v"], &["HOME=/"], &[(AT_PAGESZ, 4096)]).unwrap();
+        let rsp = stack_pointer.as_u64();
+
+        assert_eq!(rsp % 16, 0);
+        assert_eq!(word(&page, base, rsp), 2);
+        assert_eq!(string(&page, base, word(&page, base, rsp + 8)), "init");
+        assert_eq!(string(&page, base, word(&page, base, rsp + 16)), "-v");
+        assert_eq!(word(&page, base, rsp + 24), 0);
+        assert_eq!(string(&page, base, word(&page, base, rsp + 32)), "HOME=/");
+        assert_eq!(word(&page, base, rsp + 40), 0);
+        assert_eq!(word(&page, base, rsp + 48), AT_PAGESZ);
+        assert_eq!(word(&page, base, rsp + 56), 4096);
+        assert_eq!(word(&page, base, rsp + 64), AT_NULL);
+    }
+
+    #[test]
+    fn arguments_too_long() {
+        let long = "x".repeat(Size4KiB::SIZE as usize);
+        let result = build_stack(VirtAddr::new(USER_STACK_TOP), &[&long], &[], &[]);
+        assert_eq!(result.err(), Some(ElfError::ArgumentsTooLong));
+    }
+}
@@ -0,0 +1,269 @@
+//! # ELF executables
+//!
+//! Parses statically linked ELF64 executables for x86_64 and loads them
+//! into an address space, see `loader`. Only what loading needs is read:
+//! the file header and the program headers. The fields are decoded from
+//! little endian bytes, so the file needs no alignment.
+use core::fmt;
+
+use crate::arch::memory::MemoryError;
+
+pub mod loader;
+#[doc(hidden)]
+pub mod testing;
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Program header types.
+pub const PT_NULL: u32 = 0;
+pub const PT_LOAD: u32 = 1;
+
+/// Segment permissions.
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file ends in the middle of a header.
+    TooShort,
+    BadMagic,
+    /// Not a 64 bit, little endian, current version ELF file.
+    UnsupportedFormat,
+    WrongMachine,
+    /// Not an executable, for example a shared object.
+    NotExecutable,
+    /// A segment does not fit in the file, or has less memory than data.
+    BadSegment,
+    /// A segment is outside of the user part of the address space.
+    BadAddress,
+    /// Two segments share a page.
+    OverlappingSegments,
+    /// The arguments and environment do not fit on the stack.
+    ArgumentsTooLong,
+    Memory(MemoryError),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::TooShort => write!(f, "file too short"),
+            ElfError::BadMagic => write!(f, "not an ELF file"),
+            ElfError::UnsupportedFormat => write!(f, "not a 64 bit little endian ELF file"),
+            ElfError::WrongMachine => write!(f, "not an x86_64 executable"),
+            ElfError::NotExecutable => write!(f, "not an executable"),
+            ElfError::BadSegment => write!(f, "invalid segment"),
+            ElfError::BadAddress => write!(f, "segment outside of user space"),
+            ElfError::OverlappingSegments => write!(f, "segments share a page"),
+            ElfError::ArgumentsTooLong => write!(f, "arguments too long"),
+            ElfError::Memory(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<MemoryError> for ElfError {
+    fn from(error: MemoryError) -> ElfError {
+        ElfError::Memory(error)
+    }
+}
+
+/// The fields of the file header the loader uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub entry: u64,
+    pub program_header_offset: u64,
+    pub program_header_count: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    /// The bytes of the segment in the file.
+    fn data<'a>(&self, file: &'a [u8]) -> Result<&'a [u8], ElfError> {
+        let end = self
+            .offset
+            .checked_add(self.file_size)
+            .ok_or(ElfError::BadSegment)?;
+        if end > file.len() as u64 || self.file_size > self.memory_size {
+            return Err(ElfError::BadSegment);
+        }
+        Ok(&file[self.offset as usize..end as usize])
+    }
+}
+
+/// A validated ELF executable.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: Header,
+}
+
+impl<'a> Elf<'a> {
+    /// Checks the file header and that the program headers are in the file.
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if &data[0..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != CLASS_64 || data[5] != DATA_LITTLE_ENDIAN || data[6] != VERSION_CURRENT {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        if read_u16(data, 18) != MACHINE_X86_64 {
+            return Err(ElfError::WrongMachine);
+        }
+        if read_u16(data, 16) != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+
+        let header = Header {
+            entry: read_u64(data, 24),
+            program_header_offset: read_u64(data, 32),
+            program_header_count: read_u16(data, 56),
+        };
+
+        let entry_size = read_u16(data, 54) as usize;
+        if header.program_header_count > 0 && entry_size != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        let table_size = header.program_header_count as u64 * PROGRAM_HEADER_SIZE as u64;
+        match header.program_header_offset.checked_add(table_size) {
+            Some(end) if end <= data.len() as u64 => {}
+            _ => return Err(ElfError::TooShort),
+        }
+
+        Ok(Elf { data, header })
+    }
+
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let offset = self.header.program_header_offset as usize;
+
+        (0..self.header.program_header_count as usize).map(move |index| {
+            let entry = offset + index * PROGRAM_HEADER_SIZE;
+            ProgramHeader {
+                kind: read_u32(data, entry),
+                flags: read_u32(data, entry + 4),
+                offset: read_u64(data, entry + 8),
+                virtual_address: read_u64(data, entry + 16),
+                file_size: read_u64(data, entry + 32),
+                memory_size: read_u64(data, entry + 40),
+                align: read_u64(data, entry + 48),
+            }
+        })
+    }
+
+    /// The `PT_LOAD` segments, the ones mapped into memory.
+    pub fn load_segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.program_headers()
+            .filter(|header| header.kind == PT_LOAD && header.memory_size > 0)
+    }
+
+    /// Returns where the program headers end up in memory, if a segment
+    /// maps them.
+    pub fn program_headers_address(&self) -> Option<u64> {
+        let offset = self.header.program_header_offset;
+        self.load_segments()
+            .find(|segment| offset >= segment.offset && offset < segment.offset + segment.file_size)
+            .map(|segment| segment.virtual_address + (offset - segment.offset))
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    let mut bytes = [0; 2];
+    bytes.copy_from_slice(&data[offset..offset + 2]);
+    u16::from_le_bytes(bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::testing::executable;
+    use super::*;
+
+    #[test]
+    fn parses_executable() {
+        let file = executable(0x0800_0000_0000, &[0xf4], 0x2000);
+        let elf = Elf::parse(&file).unwrap();
+
+        assert_eq!(elf.header().entry, 0x0800_0000_0000);
+        let segments: Vec<_> = elf.load_segments().collect();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].flags, PF_R | PF_X);
+        assert_eq!(segments[0].memory_size, 0x2000);
+        assert_eq!(segments[0].data(&file), Ok(&[0xf4][..]));
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let file = executable(0x0800_0000_0000, &[0xf4], 1);
+
+        assert_eq!(Elf::parse(&file[..32]).err(), Some(ElfError::TooShort));
+        assert_eq!(
+            Elf::parse(&file[..HEADER_SIZE + 8]).err(),
+            Some(ElfError::TooShort)
+        );
+
+        let mut bad = file.clone();
+        bad[0] = 0;
+        assert_eq!(Elf::parse(&bad).err(), Some(ElfError::BadMagic));
+
+        let mut bad = file.clone();
+        bad[4] = 1;
+        assert_eq!(Elf::parse(&bad).err(), Some(ElfError::UnsupportedFormat));
+
+        let mut bad = file.clone();
+        bad[18] = 0x28;
+        assert_eq!(Elf::parse(&bad).err(), Some(ElfError::WrongMachine));
+
+        let mut bad = file.clone();
+        bad[16] = 3;
+        assert_eq!(Elf::parse(&bad).err(), Some(ElfError::NotExecutable));
+    }
+
+    #[test]
+    fn rejects_segments_outside_the_file() {
+        let mut file = executable(0x0800_0000_0000, &[0xf4, 0xf4], 2);
+        let elf = Elf::parse(&file).unwrap();
+        let segment = elf.load_segments().next().unwrap();
+        file.pop();
+
+        assert_eq!(segment.data(&file), Err(ElfError::BadSegment));
+    }
+}
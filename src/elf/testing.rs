@@ -0,0 +1,42 @@
+//! Builds executables for the tests, which hand-assemble their programs.
+use alloc::vec::Vec;
+
+use super::{
+    CLASS_64, DATA_LITTLE_ENDIAN, HEADER_SIZE, MACHINE_X86_64, MAGIC, PF_R, PF_X,
+    PROGRAM_HEADER_SIZE, PT_LOAD, TYPE_EXECUTABLE, VERSION_CURRENT,
+};
+
+/// Builds an executable with one `PT_LOAD` segment of `code` at
+/// `address`, which is also the entry point. The segment takes
+/// `memory_size` bytes in memory.
+pub fn executable(address: u64, code: &[u8], memory_size: u64) -> Vec<u8> {
+    let code_offset = (HEADER_SIZE + PROGRAM_HEADER_SIZE) as u64;
+    let mut file = Vec::new();
+
+    file.extend_from_slice(MAGIC);
+    file.extend_from_slice(&[CLASS_64, DATA_LITTLE_ENDIAN, VERSION_CURRENT]);
+    file.resize(16, 0);
+    file.extend_from_slice(&TYPE_EXECUTABLE.to_le_bytes());
+    file.extend_from_slice(&MACHINE_X86_64.to_le_bytes());
+    file.extend_from_slice(&1u32.to_le_bytes());
+    file.extend_from_slice(&address.to_le_bytes());
+    file.extend_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    file.extend_from_slice(&0u64.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    file.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.resize(HEADER_SIZE, 0);
+
+    file.extend_from_slice(&PT_LOAD.to_le_bytes());
+    file.extend_from_slice(&(PF_R | PF_X).to_le_bytes());
+    file.extend_from_slice(&code_offset.to_le_bytes());
+    file.extend_from_slice(&address.to_le_bytes());
+    file.extend_from_slice(&address.to_le_bytes());
+    file.extend_from_slice(&(code.len() as u64).to_le_bytes());
+    file.extend_from_slice(&memory_size.to_le_bytes());
+    file.extend_from_slice(&0x1000u64.to_le_bytes());
+
+    file.extend_from_slice(code);
+    file
+}
@@ -0,0 +1,232 @@
+//! # Kernel logger
+//!
+//! Backend for the `log` crate facade. Records are timestamped, written
+//! to the console sinks with the matching console level and stored in the
+//! `DMESG` ring buffer, which can be dumped later (for example from the
+//! panic handler).
+//!
+//! The level filter is set with the `log` command line option. It is a
+//! comma separated list of directives: a bare level sets the default level
+//! and `module::path=level` sets the level for a module and its children.
+//!
+//! ```text
+//! log=warn,rust_kernel::arch::x86_64::memory=trace
+//! ```
+use core::fmt::Write;
+use core::str;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::cmdline;
+use crate::console::ring_buffer::RingBufferConsole;
+use crate::console::{self, Console, ConsoleWriter};
+use crate::sync::irq_lock::IrqLock;
+use crate::time::TIME;
+
+const MAX_DIRECTIVES: usize = 16;
+
+/// Level used when the command line does not set a default.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// Timestamped log records, oldest first.
+pub static DMESG: RingBufferConsole = RingBufferConsole::new("dmesg");
+
+static LOGGER: KernelLogger = KernelLogger {
+    filter: IrqLock::new(Filter::new()),
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Directive {
+    target: &'static str,
+    level: LevelFilter,
+}
+
+struct Filter {
+    default: LevelFilter,
+    directives: [Option<Directive>; MAX_DIRECTIVES],
+}
+
+impl Filter {
+    const fn new() -> Self {
+        Filter {
+            default: DEFAULT_LEVEL,
+            directives: [None; MAX_DIRECTIVES],
+        }
+    }
+
+    /// Adds the directives in `spec`. Invalid directives are ignored.
+    fn parse(&mut self, spec: &'static str) {
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+
+            match (parts.next(), parts.next()) {
+                (Some(level), None) => match level.parse() {
+                    Ok(level) => self.default = level,
+                    // A module without a level logs everything
+                    Err(_) => self.add(level, LevelFilter::Trace),
+                },
+                (Some(target), Some(level)) => {
+                    if let Ok(level) = level.parse() {
+                        self.add(target, level);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn add(&mut self, target: &'static str, level: LevelFilter) {
+        if let Some(slot) = self.directives.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(Directive { target, level });
+        }
+    }
+
+    /// The most specific directive matching the target wins.
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .filter(|directive| {
+                target.starts_with(directive.target)
+                    && (target.len() == directive.target.len()
+                        || target[directive.target.len()..].starts_with("::"))
+            })
+            .max_by_key(|directive| directive.target.len())
+            .map_or(self.default, |directive| directive.level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .map(|directive| directive.level)
+            .fold(self.default, core::cmp::max)
+    }
+}
+
+pub struct KernelLogger {
+    filter: IrqLock<Filter>,
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.lock().level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let seconds = TIME.get_seconds();
+
+        writeln!(
+            ConsoleWriter(&DMESG),
+            "[ {:>4.4} ] {:<5} {}: {}",
+            seconds,
+            record.level(),
+            record.target(),
+            record.args()
+        )
+        .unwrap();
+
+        console::print(
+            to_console_level(record.level()),
+            format_args!("[ {:>4.4} ] {}\n", seconds, record.args()),
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+fn to_console_level(level: Level) -> console::Level {
+    match level {
+        Level::Error => console::Level::Error,
+        Level::Warn => console::Level::Warn,
+        Level::Info => console::Level::Info,
+        Level::Debug => console::Level::Debug,
+        Level::Trace => console::Level::Trace,
+    }
+}
+
+/// Installs the kernel logger and applies the `log` command line option.
+pub fn init() {
+    assert_has_not_been_called!("The logger should only be initialized once!");
+
+    let max_level = {
+        let mut filter = LOGGER.filter.lock();
+        if let Some(spec) = cmdline::get("log") {
+            filter.parse(spec);
+        }
+        filter.max_level()
+    };
+
+    log::set_logger(&LOGGER).expect("Logger already set");
+    log::set_max_level(max_level);
+}
+
+/// Writes the contents of `DMESG` to `console`.
+pub fn dump(console: &dyn Console) {
+    DMESG.read(|first, second| {
+        write_bytes(console, first);
+        write_bytes(console, second);
+    });
+}
+
+/// Writes `bytes` as text. The ring buffer can split a multi byte
+/// character, invalid bytes are replaced by a question mark.
+fn write_bytes(console: &dyn Console, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        match str::from_utf8(bytes) {
+            Ok(s) => {
+                console.write_str(s);
+                break;
+            }
+            Err(error) => {
+                let (valid, rest) = bytes.split_at(error.valid_up_to());
+                console.write_str(unsafe { str::from_utf8_unchecked(valid) });
+                console.write_str("?");
+                bytes = &rest[1..];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_level() {
+        let mut filter = Filter::new();
+        filter.parse("debug");
+
+        assert_eq!(filter.level_for("rust_kernel"), LevelFilter::Debug);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn module_levels() {
+        let mut filter = Filter::new();
+        filter.parse("warn, rust_kernel::arch=debug,rust_kernel::arch::x86_64::memory=trace");
+
+        assert_eq!(filter.level_for("rust_kernel::time"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("rust_kernel::arch"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("rust_kernel::arch::x86_64::gdt"), LevelFilter::Debug);
+        assert_eq!(
+            filter.level_for("rust_kernel::arch::x86_64::memory::heap"),
+            LevelFilter::Trace
+        );
+        // Only whole path segments match
+        assert_eq!(filter.level_for("rust_kernel::architecture"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn invalid_directives_are_ignored() {
+        let mut filter = Filter::new();
+        filter.parse("rust_kernel=loud,,");
+
+        assert_eq!(filter.level_for("rust_kernel"), DEFAULT_LEVEL);
+    }
+}
@@ -6,10 +6,10 @@
 #![feature(abi_x86_interrupt, asm, allocator_api, alloc_error_handler, global_asm, llvm_asm)]
 
 extern crate bootloader;
+extern crate font8x8;
 extern crate linked_list_allocator;
 extern crate pic8259_simple;
 extern crate spin;
-extern crate uart_16550;
 extern crate volatile;
 extern crate x86_64;
 extern crate alloc;
@@ -17,6 +17,9 @@ extern crate alloc;
 #[macro_use]
 extern crate lazy_static;
 
+#[macro_use]
+extern crate log;
+
 #[macro_use]
 extern crate once;
 
@@ -25,19 +28,20 @@ extern crate array_init;
 #[cfg(test)]
 extern crate std;
 
+pub mod cmdline;
 pub mod time;
 
+#[macro_use]
+pub mod console;
 #[macro_use]
 pub mod device;
 pub mod arch;
+pub mod elf;
+pub mod klog;
+pub mod process;
+pub mod shell;
 pub mod sync;
-
-pub unsafe fn exit_qemu() {
-    use x86_64::instructions::port::Port;
-
-    let mut port = Port::<u32>::new(0xf4);
-    port.write(0);
-}
+pub mod syscall;
 
 // Do not include when testing, std has an alloc handler :)
 #[cfg(not(test))]
@@ -50,8 +54,8 @@ pub fn rust_oom(info: core::alloc::Layout) -> ! {
     );
 }
 
-use linked_list_allocator::LockedHeap;
+use arch::memory::heap::{self, KernelBackend, KernelHeap};
 
 // Todo: make private
 #[global_allocator]
-pub static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static HEAP_ALLOCATOR: KernelHeap<KernelBackend> = KernelHeap::new(heap::kernel_backend());
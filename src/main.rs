@@ -13,8 +13,10 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use core::panic::PanicInfo;
 use rust_kernel::arch;
-use rust_kernel::arch::interrupts;
-use rust_kernel::device::keyboard::KEYBOARD;
+use rust_kernel::device::serial::SERIAL_CONSOLE;
+use rust_kernel::klog;
+use rust_kernel::process::programs;
+use rust_kernel::shell;
 
 
 /// The kernel is compiled using the bootimage and bootloader crates.
@@ -26,20 +28,24 @@ use rust_kernel::device::keyboard::KEYBOARD;
 #[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _start(boot_info_address: usize) -> ! {
+    klog::init();
+
     kprintln!("Rust test kernel starting{}", "...");
-    kprintln!("Memory status {}", rust_kernel::HEAP_ALLOCATOR.lock().size());
+    kprintln!("Memory status {}", rust_kernel::HEAP_ALLOCATOR.stats().size);
 
     // Let's init the kernel
     arch::init(boot_info_address);
+    programs::init();
 
-    kprintln!("Memory status {}", rust_kernel::HEAP_ALLOCATOR.lock().size());
+    kprintln!("Memory status {}", rust_kernel::HEAP_ALLOCATOR.stats().size);
 
     let t = String::from("test");
 
     kprintln!("Test string contents: {}", t);
     kprintln!("Test string ptr: {:?}", t.as_ptr());
 
-    const VECSIZE: usize = 1024 * 2; //2kb
+    // Larger than the initial heap, so the heap has to grow
+    const VECSIZE: usize = 1024 * 1024 * 4; //4mb
     let mut a: Vec<u8> = Vec::with_capacity(VECSIZE);
 
     kprintln!("Allocated vec of size {}", VECSIZE);
@@ -48,7 +54,7 @@ pub extern "C" fn _start(boot_info_address: usize) -> ! {
         a.push(i as u8);
     }
 
-    //kprintln!("Pushed ints to vec.");
+    kprintln!("Memory status {}", rust_kernel::HEAP_ALLOCATOR.stats().size);
     kprintln!("Done... to main loop.");
 
     //kprintln!("{}", rust_kernel::time::TIME.lock().ticks);
@@ -56,32 +62,14 @@ pub extern "C" fn _start(boot_info_address: usize) -> ! {
     // Todo:
     // - time system
     // - context structs and functions for cpu contetx switching
-    // - tasks: processes, create, pid, switch context
+    // - tasks: switch context
     // - task scheduler
     // - process communication
-    // - syscalls: handling processes
     // - create console as process
     // - filesystem?
 
     // start console program
-
-
-    loop {
-        if let Some(key) = KEYBOARD.lock().process_scancode() {
-            if let Some(character) = key.character {
-                print!("{}", character);
-            } else {
-                kprintln!("{:?}", key);
-            }
-        }
-        
-        // kprintln!(
-        //     "Time: {}", (rust_kernel::time::TIME.get_seconds())
-        // );
-
-
-       interrupts::pause();
-    }
+    shell::run();
 }
 
 /// This function is called on panic.
@@ -90,6 +78,10 @@ pub extern "C" fn _start(boot_info_address: usize) -> ! {
 #[no_mangle]
 pub fn panic(info: &PanicInfo) -> ! {
     kprintln!("{}", info);
+
+    serial_println!("--- dmesg ---");
+    klog::dump(&SERIAL_CONSOLE);
+
     loop {}
 }
 
@@ -0,0 +1,700 @@
+//! # Processes
+//!
+//! A process owns an address space, its threads, its open handles and,
+//! once it exited, its exit status. Processes live in a table and are
+//! known by their PID.
+//!
+//! There is no scheduler: `run` starts a process on the kernel stack of
+//! its thread, and returns when it exits or faults. A process which runs
+//! another one, for example a child it created, waits in the kernel until
+//! the child is done.
+//!
+//! `fork` copies a process: the child shares the memory of its parent
+//! copy-on-write, and starts in the state of the system call which
+//! created it. `exec` replaces the program of a process with one of the
+//! `programs` the kernel knows. File descriptors are inherited by the
+//! child, and kept by `exec` unless they are close-on-exec.
+//!
+//! Processes get POSIX-style signals, see `signal`. A process a signal
+//! stops keeps its registers, and runs on from them once it is continued.
+//!
+//! A process which exited stays in the table as a zombie, with its exit
+//! status, until its parent reaps it with `wait`. Processes the kernel
+//! started have no parent, the kernel reaps them. When a process exits,
+//! its children lose their parent, its zombie children are reaped.
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+
+use crate::arch::gdt;
+use crate::arch::memory::{self, address_space, AddressSpace, MemoryError, Stack};
+use crate::arch::user::{resume_user_mode, Registers, UserExit};
+use crate::elf::{loader, ElfError};
+use crate::sync::irq_lock::IrqLock;
+
+use self::signal::Signals;
+
+pub mod programs;
+pub mod signal;
+
+/// Size in pages of the kernel stack of a thread, used for its system
+/// calls and interrupts.
+const KERNEL_STACK_PAGES: usize = 8;
+
+/// PIDs go up to here, then start over at 1.
+const MAX_PID: u64 = 32768;
+
+/// Signals which end a process, see `ExitStatus::Killed`.
+pub const SIGILL: u8 = 4;
+pub const SIGFPE: u8 = 8;
+pub const SIGSEGV: u8 = 11;
+
+static PROCESSES: IrqLock<Vec<Process>> = IrqLock::new(Vec::new());
+static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+/// The PID of the running process, 0 if the kernel runs.
+static CURRENT: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(pub u64);
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process called `exit` with this code.
+    Exited(i32),
+    /// The process was ended by a signal, for example for a fault.
+    Killed(u8),
+    /// The process was stopped by a signal, it goes on after `SIGCONT`.
+    Stopped(u8),
+}
+
+impl ExitStatus {
+    /// Encodes the status like `waitpid` does.
+    pub fn wait_status(self) -> i32 {
+        match self {
+            ExitStatus::Exited(code) => (code & 0xff) << 8,
+            ExitStatus::Killed(signal) => i32::from(signal),
+            ExitStatus::Stopped(signal) => i32::from(signal) << 8 | 0x7f,
+        }
+    }
+}
+
+impl From<UserExit> for ExitStatus {
+    fn from(exit: UserExit) -> ExitStatus {
+        match exit {
+            UserExit::Exit(code) => ExitStatus::Exited(code),
+            UserExit::Fault { exception, .. } => ExitStatus::Killed(match exception {
+                "divide error" => SIGFPE,
+                "invalid opcode" => SIGILL,
+                _ => SIGSEGV,
+            }),
+            UserExit::Killed(signal) => ExitStatus::Killed(signal),
+            UserExit::Stopped(signal) => ExitStatus::Stopped(signal),
+        }
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitStatus::Exited(code) => write!(f, "exited with {}", code),
+            ExitStatus::Killed(signal) => write!(f, "killed by signal {}", signal),
+            ExitStatus::Stopped(signal) => write!(f, "stopped by signal {}", signal),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Created, or continued after a stop, but not running.
+    Ready,
+    Running,
+    /// Stopped by a signal, until `SIGCONT`.
+    Stopped(u8),
+    /// Exited, waiting to be reaped.
+    Zombie(ExitStatus),
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::Ready => f.pad("ready"),
+            State::Running => f.pad("running"),
+            State::Stopped(_) => f.pad("stopped"),
+            State::Zombie(_) => f.pad("zombie"),
+        }
+    }
+}
+
+/// Something a file descriptor refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handle {
+    /// Reads console input.
+    ConsoleInput,
+    /// Writes to every console sink.
+    ConsoleOutput,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenHandle {
+    pub handle: Handle,
+    pub close_on_exec: bool,
+}
+
+/// The open handles of a process, indexed by file descriptor.
+#[derive(Debug, Clone)]
+pub struct Handles(Vec<Option<OpenHandle>>);
+
+impl Handles {
+    /// Standard input, output and error on the console.
+    pub fn console() -> Handles {
+        let open = |handle| {
+            Some(OpenHandle {
+                handle,
+                close_on_exec: false,
+            })
+        };
+        Handles(vec![
+            open(Handle::ConsoleInput),
+            open(Handle::ConsoleOutput),
+            open(Handle::ConsoleOutput),
+        ])
+    }
+
+    pub fn get(&self, fd: u32) -> Option<OpenHandle> {
+        self.0.get(fd as usize).copied().flatten()
+    }
+
+    /// Sets the close-on-exec flag of `fd`, `None` if it is not open.
+    pub fn set_close_on_exec(&mut self, fd: u32, close_on_exec: bool) -> Option<()> {
+        let handle = self.0.get_mut(fd as usize)?.as_mut()?;
+        handle.close_on_exec = close_on_exec;
+        Some(())
+    }
+
+    /// Closes the handles which are close-on-exec.
+    pub fn close_on_exec(&mut self) {
+        for handle in self.0.iter_mut() {
+            if handle.map_or(false, |handle| handle.close_on_exec) {
+                *handle = None;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, OpenHandle)> + '_ {
+        self.0
+            .iter()
+            .enumerate()
+            .filter_map(|(fd, handle)| handle.map(|handle| (fd as u32, handle)))
+    }
+}
+
+/// A thread of a process, with the user state it starts from.
+#[derive(Debug)]
+pub struct Thread {
+    pub kernel_stack: Stack,
+    pub registers: Registers,
+}
+
+pub struct Process {
+    pub pid: Pid,
+    pub parent: Option<Pid>,
+    pub name: String,
+    pub state: State,
+    /// Freed when the process exits.
+    address_space: Option<AddressSpace>,
+    threads: Vec<Thread>,
+    handles: Handles,
+    signals: Signals,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    Elf(ElfError),
+    Memory(MemoryError),
+    NoSuchProcess,
+    /// There is no program with the name.
+    NotFound,
+    /// The signal number, or what to do with it, is not valid.
+    InvalidSignal,
+    /// Every PID is in use.
+    TooManyProcesses,
+    /// The process was started already.
+    NotReady,
+    /// No child matches.
+    NoChild,
+    /// The child did not exit yet.
+    NotExited,
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessError::Elf(error) => write!(f, "{}", error),
+            ProcessError::Memory(error) => write!(f, "{}", error),
+            ProcessError::NoSuchProcess => write!(f, "no such process"),
+            ProcessError::NotFound => write!(f, "no such program"),
+            ProcessError::InvalidSignal => write!(f, "invalid signal"),
+            ProcessError::TooManyProcesses => write!(f, "too many processes"),
+            ProcessError::NotReady => write!(f, "process already started"),
+            ProcessError::NoChild => write!(f, "no child process"),
+            ProcessError::NotExited => write!(f, "child has not exited"),
+        }
+    }
+}
+
+impl From<ElfError> for ProcessError {
+    fn from(error: ElfError) -> ProcessError {
+        ProcessError::Elf(error)
+    }
+}
+
+impl From<MemoryError> for ProcessError {
+    fn from(error: MemoryError) -> ProcessError {
+        ProcessError::Memory(error)
+    }
+}
+
+/// What the shell lists about a process.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub parent: Option<Pid>,
+    pub name: String,
+    pub state: State,
+    pub threads: usize,
+}
+
+/// Returns the running process, `None` while the kernel runs.
+pub fn current() -> Option<Pid> {
+    match CURRENT.load(Ordering::SeqCst) {
+        0 => None,
+        pid => Some(Pid(pid)),
+    }
+}
+
+/// Returns the handle of file descriptor `fd` of the running process. The
+/// kernel, or user code it runs without a process, has the console.
+pub fn handle(fd: u32) -> Option<OpenHandle> {
+    match current() {
+        Some(pid) => with_process(pid, |process| process.handles.get(fd)).ok()?,
+        None => Handles::console().get(fd),
+    }
+}
+
+/// Sets the close-on-exec flag of file descriptor `fd` of the running
+/// process. `None` if it is not open, or no process runs.
+pub fn set_close_on_exec(fd: u32, close_on_exec: bool) -> Option<()> {
+    with_process(current()?, |process| {
+        process.handles.set_close_on_exec(fd, close_on_exec)
+    })
+    .ok()?
+}
+
+/// Loads the executable `file` into a new process, which is ready to run.
+pub fn spawn(
+    name: &str,
+    file: &[u8],
+    args: &[&str],
+    env: &[&str],
+    parent: Option<Pid>,
+) -> Result<Pid, ProcessError> {
+    let program = loader::load(file, args, env)?;
+    let thread = Thread {
+        kernel_stack: memory::alloc_stack(KERNEL_STACK_PAGES, "process kernel stack")?,
+        registers: Registers::entry(program.entry, program.stack_pointer),
+    };
+
+    insert(Process {
+        pid: Pid(0),
+        parent,
+        name: String::from(name),
+        state: State::Ready,
+        address_space: Some(program.address_space),
+        threads: vec![thread],
+        handles: Handles::console(),
+        signals: Signals::new(),
+    })
+}
+
+/// Creates a child of the running process, with a copy-on-write copy of
+/// its memory and a copy of its handles. The child starts in the state
+/// `registers` of the `fork` system call, which returns 0 to it.
+pub fn fork(registers: &Registers) -> Result<Pid, ProcessError> {
+    let parent = current().ok_or(ProcessError::NoSuchProcess)?;
+    let (address_space, name, handles, signals) = with_process(parent, |process| {
+        let address_space = match process.address_space.as_mut() {
+            Some(address_space) => address_space.duplicate()?,
+            None => return Err(ProcessError::NoSuchProcess),
+        };
+        Ok((
+            address_space,
+            process.name.clone(),
+            process.handles.clone(),
+            process.signals.fork(),
+        ))
+    })??;
+
+    let thread = Thread {
+        kernel_stack: memory::alloc_stack(KERNEL_STACK_PAGES, "process kernel stack")?,
+        registers: Registers {
+            rax: 0,
+            ..registers.clone()
+        },
+    };
+
+    insert(Process {
+        pid: Pid(0),
+        parent: Some(parent),
+        name,
+        state: State::Ready,
+        address_space: Some(address_space),
+        threads: vec![thread],
+        handles,
+        signals,
+    })
+}
+
+/// Replaces the program of the running process with the program `path`,
+/// started with `args` and `env`. Handles which are close-on-exec are
+/// closed, signal handlers are reset. `registers` are set to the start
+/// of the new program, the system call returns there. On errors the old
+/// program goes on.
+pub fn exec(
+    registers: &mut Registers,
+    path: &str,
+    args: &[&str],
+    env: &[&str],
+) -> Result<(), ProcessError> {
+    let pid = current().ok_or(ProcessError::NoSuchProcess)?;
+    let file = programs::find(path).ok_or(ProcessError::NotFound)?;
+    let program = loader::load(file, args, env)?;
+    let start = Registers::entry(program.entry, program.stack_pointer);
+
+    let old_address_space = with_process(pid, |process| {
+        process.name = String::from(args.first().copied().unwrap_or(path));
+        process.handles.close_on_exec();
+        process.signals.exec();
+        if let Some(thread) = process.threads.first_mut() {
+            thread.registers = start.clone();
+        }
+        mem::replace(&mut process.address_space, Some(program.address_space))
+    })?;
+
+    activate(Some(pid));
+    drop(old_address_space);
+
+    *registers = start;
+    Ok(())
+}
+
+/// Runs process `pid` until it exits or stops and returns its status. A
+/// process which exited stays a zombie until it is reaped with `wait`,
+/// one which stopped can run again once it is continued. Registers which
+/// do not return to user code are refused rather than resumed.
+pub fn run(pid: Pid) -> Result<ExitStatus, ProcessError> {
+    let (registers, kernel_stack) = with_process(pid, |process| {
+        if process.state != State::Ready {
+            return Err(ProcessError::NotReady);
+        }
+        let thread = process.threads.first().ok_or(ProcessError::NotReady)?;
+        if !thread.registers.is_valid() {
+            return Err(ProcessError::Memory(MemoryError::NotUserAddress));
+        }
+        process.state = State::Running;
+        Ok((thread.registers.clone(), thread.kernel_stack.top()))
+    })??;
+
+    let previous = current();
+    let previous_kernel_stack = gdt::kernel_stack();
+
+    CURRENT.store(pid.0, Ordering::SeqCst);
+    gdt::set_kernel_stack(VirtAddr::new(kernel_stack as u64));
+    activate(Some(pid));
+
+    let exit = unsafe { resume_user_mode(&registers) };
+
+    gdt::set_kernel_stack(previous_kernel_stack);
+    CURRENT.store(previous.map_or(0, |pid| pid.0), Ordering::SeqCst);
+    activate(previous);
+
+    let status = ExitStatus::from(exit);
+    match status {
+        ExitStatus::Stopped(signal) => {
+            with_process(pid, |process| process.state = State::Stopped(signal))?;
+            debug!("Process {} {}", pid, status);
+        }
+        _ => exit_process(pid, status),
+    }
+    Ok(status)
+}
+
+/// Reaps a zombie child of `parent`, `None` for the kernel. `child`
+/// picks the child, or any child if it is `None`. Returns the PID and
+/// the exit status of the reaped child.
+pub fn wait(parent: Option<Pid>, child: Option<Pid>) -> Result<(Pid, ExitStatus), ProcessError> {
+    let mut processes = PROCESSES.lock();
+    let is_child = |process: &Process| {
+        process.parent == parent && child.map_or(true, |child| process.pid == child)
+    };
+
+    if !processes.iter().any(|process| is_child(process)) {
+        return Err(ProcessError::NoChild);
+    }
+
+    let index = processes
+        .iter()
+        .position(|process| is_child(process) && process.is_zombie())
+        .ok_or(ProcessError::NotExited)?;
+    let process = processes.remove(index);
+
+    match process.state {
+        State::Zombie(status) => Ok((process.pid, status)),
+        _ => unreachable!(),
+    }
+}
+
+/// Calls `f` with every process.
+pub fn for_each<F: FnMut(ProcessInfo)>(mut f: F) {
+    let processes: Vec<ProcessInfo> = PROCESSES.lock().iter().map(Process::info).collect();
+    for info in processes {
+        f(info);
+    }
+}
+
+impl Process {
+    fn is_zombie(&self) -> bool {
+        matches!(self.state, State::Zombie(_))
+    }
+
+    fn info(&self) -> ProcessInfo {
+        ProcessInfo {
+            pid: self.pid,
+            parent: self.parent,
+            name: self.name.clone(),
+            state: self.state,
+            threads: self.threads.len(),
+        }
+    }
+}
+
+/// Runs `f` with process `pid` and the table locked.
+fn with_process<R, F>(pid: Pid, f: F) -> Result<R, ProcessError>
+where
+    F: FnOnce(&mut Process) -> R,
+{
+    let mut processes = PROCESSES.lock();
+    let process = processes
+        .iter_mut()
+        .find(|process| process.pid == pid)
+        .ok_or(ProcessError::NoSuchProcess)?;
+    Ok(f(process))
+}
+
+/// Gives `process` a free PID and adds it to the table. If there is
+/// none, the kernel stacks of `process` are freed.
+fn insert(mut process: Process) -> Result<Pid, ProcessError> {
+    let mut processes = PROCESSES.lock();
+
+    let mut pid = NEXT_PID.load(Ordering::SeqCst);
+    for _ in 0..MAX_PID {
+        if !processes.iter().any(|process| process.pid.0 == pid) {
+            NEXT_PID.store(pid % MAX_PID + 1, Ordering::SeqCst);
+            process.pid = Pid(pid);
+            processes.push(process);
+            return Ok(Pid(pid));
+        }
+        pid = pid % MAX_PID + 1;
+    }
+
+    drop(processes);
+    free_threads(&process.name, process.threads);
+    Err(ProcessError::TooManyProcesses)
+}
+
+/// Loads the address space of `pid`, or the kernel's.
+fn activate(pid: Option<Pid>) {
+    let activated = pid.and_then(|pid| {
+        with_process(pid, |process| {
+            process.address_space.as_ref().map(AddressSpace::switch)
+        })
+        .ok()?
+    });
+
+    if activated.is_none() {
+        address_space::switch_to_kernel();
+    }
+}
+
+/// Makes `pid` a zombie with `status` and frees what it owns.
+fn exit_process(pid: Pid, status: ExitStatus) {
+    let mut orphans = Vec::new();
+
+    let freed = with_process(pid, |process| {
+        process.state = State::Zombie(status);
+        process.handles.clear();
+        (
+            process.address_space.take(),
+            mem::replace(&mut process.threads, Vec::new()),
+        )
+    });
+
+    // Its children lose their parent, nobody can wait for the zombies
+    // among them anymore
+    {
+        let mut processes = PROCESSES.lock();
+        let mut index = 0;
+        while index < processes.len() {
+            let process = &mut processes[index];
+            if process.parent == Some(pid) {
+                if process.is_zombie() {
+                    orphans.push(processes.remove(index));
+                    continue;
+                }
+                process.parent = None;
+            }
+            index += 1;
+        }
+    }
+
+    if let Ok((address_space, threads)) = freed {
+        drop(address_space);
+        free_threads(&pid, threads);
+    }
+    drop(orphans);
+
+    debug!("Process {} {}", pid, status);
+}
+
+/// Frees the kernel stacks of `threads`, of the process `process`.
+fn free_threads<P: fmt::Display + ?Sized>(process: &P, threads: Vec<Thread>) {
+    for thread in threads {
+        if let Err(error) = memory::free_stack(thread.kernel_stack) {
+            warn!(
+                "Freeing the kernel stack of process {} failed: {}",
+                process, error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn insert_process(name: &str, parent: Option<Pid>) -> Pid {
+        insert(Process {
+            pid: Pid(0),
+            parent,
+            name: String::from(name),
+            state: State::Running,
+            address_space: None,
+            threads: Vec::new(),
+            handles: Handles::console(),
+            signals: Signals::new(),
+        })
+        .unwrap()
+    }
+
+    fn exists(pid: Pid) -> bool {
+        with_process(pid, |_| ()).is_ok()
+    }
+
+    #[test]
+    fn exit_and_wait() {
+        let parent = insert_process("parent", None);
+        let child = insert_process("child", Some(parent));
+        let orphan = insert_process("orphan", Some(parent));
+        let zombie = insert_process("zombie", Some(parent));
+        assert!(child != parent && orphan != child);
+
+        assert_eq!(wait(Some(parent), None), Err(ProcessError::NotExited));
+
+        exit_process(child, ExitStatus::Exited(3));
+        assert_eq!(
+            wait(Some(parent), Some(child)),
+            Ok((child, ExitStatus::Exited(3)))
+        );
+        assert_eq!(wait(Some(parent), Some(child)), Err(ProcessError::NoChild));
+
+        // Zombie children are reaped with their parent, the others lose it
+        exit_process(zombie, ExitStatus::Killed(SIGSEGV));
+        exit_process(parent, ExitStatus::Exited(0));
+        assert!(!exists(zombie));
+        assert_eq!(with_process(orphan, |process| process.parent), Ok(None));
+
+        assert_eq!(
+            wait(None, Some(parent)),
+            Ok((parent, ExitStatus::Exited(0)))
+        );
+        exit_process(orphan, ExitStatus::Exited(1));
+        assert_eq!(wait(None, Some(orphan)).map(|(pid, _)| pid), Ok(orphan));
+    }
+
+    #[test]
+    fn wait_status() {
+        assert_eq!(ExitStatus::Exited(1).wait_status(), 0x100);
+        assert_eq!(ExitStatus::Killed(SIGSEGV).wait_status(), 11);
+        assert_eq!(ExitStatus::Stopped(signal::SIGSTOP).wait_status(), 0x137f);
+    }
+
+    #[test]
+    fn kill_and_continue() {
+        let ready = insert_process("ready", None);
+        with_process(ready, |process| process.state = State::Ready).unwrap();
+        assert_eq!(signal::send(ready, signal::SIGCHLD), Ok(()));
+        assert_eq!(signal::send(ready, signal::SIGTERM), Ok(()));
+        assert_eq!(
+            wait(None, Some(ready)),
+            Ok((ready, ExitStatus::Killed(signal::SIGTERM)))
+        );
+
+        // A blocked signal stays pending, SIGKILL can not be blocked
+        let blocking = insert_process("blocking", None);
+        with_process(blocking, |process| {
+            process.state = State::Ready;
+            process
+                .signals
+                .set_mask(signal::SIG_BLOCK, 1 << signal::SIGTERM)
+        })
+        .unwrap()
+        .unwrap();
+        signal::send(blocking, signal::SIGTERM).unwrap();
+        assert_eq!(
+            with_process(blocking, |process| process.signals.pending()),
+            Ok(1 << signal::SIGTERM)
+        );
+        signal::send(blocking, signal::SIGKILL).unwrap();
+        assert_eq!(
+            wait(None, Some(blocking)),
+            Ok((blocking, ExitStatus::Killed(signal::SIGKILL)))
+        );
+
+        let stopped = insert_process("stopped", None);
+        with_process(stopped, |process| {
+            process.state = State::Stopped(signal::SIGSTOP)
+        })
+        .unwrap();
+        signal::send(stopped, signal::SIGTERM).unwrap();
+        signal::send(stopped, signal::SIGCONT).unwrap();
+        assert_eq!(
+            with_process(stopped, |process| process.state),
+            Ok(State::Ready)
+        );
+        assert_eq!(signal::send(stopped, 64), Err(ProcessError::InvalidSignal));
+        assert_eq!(signal::send(Pid(0), 0), Err(ProcessError::NoSuchProcess));
+    }
+}
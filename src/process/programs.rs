@@ -0,0 +1,62 @@
+//! # Programs
+//!
+//! There is no file system yet. The executables `exec` can start are
+//! registered here by name. `init` registers the programs of the `user`
+//! crate which `build.rs` built into the kernel.
+use alloc::vec::Vec;
+use spin::Mutex;
+
+include!(concat!(env!("OUT_DIR"), "/user_programs.rs"));
+
+struct Program {
+    name: &'static str,
+    file: &'static [u8],
+}
+
+static PROGRAMS: Mutex<Vec<Program>> = Mutex::new(Vec::new());
+
+/// Registers the programs built into the kernel.
+pub fn init() {
+    for &(name, file) in BUILT_IN.iter() {
+        register(name, file);
+    }
+    debug!("{} built-in programs", BUILT_IN.len());
+}
+
+/// Makes the executable `file` known as `name`, replacing a program
+/// registered with the same name.
+pub fn register(name: &'static str, file: &'static [u8]) {
+    let mut programs = PROGRAMS.lock();
+    match programs.iter_mut().find(|program| program.name == name) {
+        Some(program) => program.file = file,
+        None => programs.push(Program { name, file }),
+    }
+}
+
+/// Returns the executable registered as `name`.
+pub fn find(name: &str) -> Option<&'static [u8]> {
+    PROGRAMS
+        .lock()
+        .iter()
+        .find(|program| program.name == name)
+        .map(|program| program.file)
+}
+
+/// Returns the names of the registered programs.
+pub fn names() -> Vec<&'static str> {
+    PROGRAMS.lock().iter().map(|program| program.name).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn register_and_find() {
+        register("true", b"first");
+        register("true", b"second");
+        assert_eq!(find("true"), Some(&b"second"[..]));
+        assert_eq!(find("false"), None);
+        assert!(names().contains(&"true"));
+    }
+}
@@ -0,0 +1,600 @@
+//! # Signals
+//!
+//! POSIX-style signals. `send` marks a signal pending on a process, and
+//! the process acts on it when it returns to user mode: it runs the
+//! handler it installed with `sigaction`, or the default action of the
+//! signal, which terminates it, stops it or ignores the signal. Signals
+//! in the mask of the process stay pending until they are unblocked.
+//!
+//! A handler runs on the user stack, on top of a `SignalFrame` with the
+//! registers it interrupted. It returns to the restorer it was installed
+//! with, which calls `sigreturn` to go on where the signal arrived.
+//!
+//! Handlers and stops need the registers of the user code, which the
+//! system calls have. Interrupts from user mode only end the process if
+//! a pending signal terminates it, the rest waits for its next system
+//! call. Blocking system calls return early when a signal is pending.
+//!
+//! Ctrl+C on the console sends `SIGINT` to the foreground process, the
+//! one the shell runs, and to its children.
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+
+use super::{current, exit_process, with_process, ExitStatus, Pid, Process, ProcessError, State};
+use super::{PROCESSES, SIGFPE, SIGILL, SIGSEGV};
+use crate::arch::memory::address_space;
+use crate::arch::memory::MemoryError;
+use crate::arch::user::{exit_user_mode, Registers, UserExit};
+
+/// Signals are numbered from 1 to `NSIG - 1`.
+pub const NSIG: usize = 32;
+
+pub const SIGHUP: u8 = 1;
+pub const SIGINT: u8 = 2;
+pub const SIGQUIT: u8 = 3;
+pub const SIGTRAP: u8 = 5;
+pub const SIGABRT: u8 = 6;
+pub const SIGBUS: u8 = 7;
+pub const SIGKILL: u8 = 9;
+pub const SIGUSR1: u8 = 10;
+pub const SIGUSR2: u8 = 12;
+pub const SIGPIPE: u8 = 13;
+pub const SIGALRM: u8 = 14;
+pub const SIGTERM: u8 = 15;
+pub const SIGCHLD: u8 = 17;
+pub const SIGCONT: u8 = 18;
+pub const SIGSTOP: u8 = 19;
+pub const SIGTSTP: u8 = 20;
+pub const SIGTTIN: u8 = 21;
+pub const SIGTTOU: u8 = 22;
+
+/// The handlers of `SigAction` which are not functions.
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+/// `SigAction::flags`: do not block the signal while its handler runs.
+pub const SA_NODEFER: u64 = 0x4000_0000;
+
+/// How `set_mask` changes the mask.
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+/// User code may use 128 bytes below its stack pointer, the System V
+/// ABI red zone. Signal frames go below it.
+const RED_ZONE: u64 = 128;
+
+/// The process the shell runs, 0 if none.
+static FOREGROUND: AtomicU64 = AtomicU64::new(0);
+
+/// What happens to a process on a signal it has no handler for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    /// Continues the process if it is stopped, ignored otherwise.
+    Continue,
+}
+
+pub fn default_action(signal: u8) -> DefaultAction {
+    match signal {
+        SIGCHLD => DefaultAction::Ignore,
+        SIGCONT => DefaultAction::Continue,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Returns the number of the signal called `name`, with or without the
+/// `SIG` prefix.
+pub fn from_name(name: &str) -> Option<u8> {
+    const NAMES: &[(&str, u8)] = &[
+        ("HUP", SIGHUP),
+        ("INT", SIGINT),
+        ("QUIT", SIGQUIT),
+        ("ILL", SIGILL),
+        ("TRAP", SIGTRAP),
+        ("ABRT", SIGABRT),
+        ("BUS", SIGBUS),
+        ("FPE", SIGFPE),
+        ("KILL", SIGKILL),
+        ("USR1", SIGUSR1),
+        ("SEGV", SIGSEGV),
+        ("USR2", SIGUSR2),
+        ("PIPE", SIGPIPE),
+        ("ALRM", SIGALRM),
+        ("TERM", SIGTERM),
+        ("CHLD", SIGCHLD),
+        ("CONT", SIGCONT),
+        ("STOP", SIGSTOP),
+        ("TSTP", SIGTSTP),
+        ("TTIN", SIGTTIN),
+        ("TTOU", SIGTTOU),
+    ];
+
+    let name = name.trim_start_matches("SIG");
+    NAMES
+        .iter()
+        .find(|&&(known, _)| known == name)
+        .map(|&(_, signal)| signal)
+}
+
+/// What a process does on a signal, laid out like the `sigaction` of
+/// Linux on x86_64.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN` or the address of the handler, which gets
+    /// the signal number in `rdi`.
+    pub handler: u64,
+    pub flags: u64,
+    /// Where the handler returns to, it has to call `sigreturn`.
+    pub restorer: u64,
+    /// Signals blocked while the handler runs.
+    pub mask: u64,
+}
+
+/// Pushed on the user stack under a handler. `return_address` is what
+/// the handler returns to, the rest is restored by `sigreturn`.
+#[repr(C)]
+struct SignalFrame {
+    return_address: u64,
+    registers: Registers,
+    mask: u64,
+}
+
+const fn bit(signal: u8) -> u64 {
+    1 << signal
+}
+
+/// Signals which cannot be handled, ignored or blocked.
+const UNBLOCKABLE: u64 = bit(SIGKILL) | bit(SIGSTOP);
+const STOP_SIGNALS: u64 = bit(SIGSTOP) | bit(SIGTSTP) | bit(SIGTTIN) | bit(SIGTTOU);
+
+/// The signal state of a process.
+#[derive(Debug, Clone)]
+pub struct Signals {
+    pending: u64,
+    mask: u64,
+    actions: [SigAction; NSIG],
+}
+
+impl Signals {
+    pub fn new() -> Signals {
+        Signals {
+            pending: 0,
+            mask: 0,
+            actions: [SigAction::default(); NSIG],
+        }
+    }
+
+    /// The state of a child created by `fork`: the same handlers and
+    /// mask, nothing pending.
+    pub fn fork(&self) -> Signals {
+        Signals {
+            pending: 0,
+            ..self.clone()
+        }
+    }
+
+    /// Handlers are lost with the program on `exec`, ignored signals stay
+    /// ignored.
+    pub fn exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    pub fn mask(&self) -> u64 {
+        self.mask
+    }
+
+    pub fn action(&self, signal: u8) -> SigAction {
+        self.actions[usize::from(signal)]
+    }
+
+    /// Installs `action` for `signal`, and returns the previous one.
+    pub fn set_action(&mut self, signal: u8, action: SigAction) -> Result<SigAction, ProcessError> {
+        if !is_valid(signal) || signal == 0 || bit(signal) & UNBLOCKABLE != 0 {
+            return Err(ProcessError::InvalidSignal);
+        }
+        let handler = action.handler != SIG_DFL && action.handler != SIG_IGN;
+        if handler && action.restorer == 0 {
+            return Err(ProcessError::InvalidSignal);
+        }
+
+        let old = mem::replace(&mut self.actions[usize::from(signal)], action);
+        // Ignoring a signal discards it
+        if self.ignores(signal) {
+            self.pending &= !bit(signal);
+        }
+        Ok(old)
+    }
+
+    /// Changes the mask like `sigprocmask` with `how`, and returns the
+    /// previous mask. `SIGKILL` and `SIGSTOP` cannot be blocked.
+    pub fn set_mask(&mut self, how: u64, set: u64) -> Result<u64, ProcessError> {
+        let old = self.mask;
+        let mask = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old & !set,
+            SIG_SETMASK => set,
+            _ => return Err(ProcessError::InvalidSignal),
+        };
+        self.mask = mask & !UNBLOCKABLE & !1;
+        Ok(old)
+    }
+
+    /// Whether `signal` would be dropped, by `SIG_IGN` or its default
+    /// action.
+    fn ignores(&self, signal: u8) -> bool {
+        match self.action(signal).handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(
+                default_action(signal),
+                DefaultAction::Ignore | DefaultAction::Continue
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether `signal` ends the process when it is acted on.
+    fn terminates(&self, signal: u8) -> bool {
+        signal == SIGKILL
+            || (self.action(signal).handler == SIG_DFL
+                && default_action(signal) == DefaultAction::Terminate)
+    }
+
+    /// Marks `signal` pending, unless the process drops it.
+    fn raise(&mut self, signal: u8) {
+        match signal {
+            SIGCONT => self.pending &= !STOP_SIGNALS,
+            _ if bit(signal) & STOP_SIGNALS != 0 => self.pending &= !bit(SIGCONT),
+            _ => {}
+        }
+        if !self.ignores(signal) {
+            self.pending |= bit(signal);
+        }
+    }
+
+    fn deliverable(&self) -> u64 {
+        self.pending & !self.mask
+    }
+
+    /// Takes the lowest pending signal which is not blocked.
+    fn take(&mut self) -> Option<u8> {
+        self.take_matching(|_, _| true)
+    }
+
+    /// Takes the lowest pending signal which is not blocked and ends the
+    /// process.
+    fn take_terminating(&mut self) -> Option<u8> {
+        self.take_matching(Signals::terminates)
+    }
+
+    fn take_matching<F: Fn(&Signals, u8) -> bool>(&mut self, matches: F) -> Option<u8> {
+        let signal = (1..NSIG as u8)
+            .find(|&signal| self.deliverable() & bit(signal) != 0 && matches(self, signal))?;
+        self.pending &= !bit(signal);
+        Some(signal)
+    }
+}
+
+impl Default for Signals {
+    fn default() -> Signals {
+        Signals::new()
+    }
+}
+
+fn is_valid(signal: u8) -> bool {
+    usize::from(signal) < NSIG
+}
+
+/// Sends `signal` to process `pid`. Signal 0 only checks that the
+/// process exists. A process which is not running is ended right away if
+/// the signal kills it and is not blocked: a stopped one only by
+/// `SIGKILL`, as other signals wait until it continues.
+pub fn send(pid: Pid, signal: u8) -> Result<(), ProcessError> {
+    if !is_valid(signal) {
+        return Err(ProcessError::InvalidSignal);
+    }
+
+    let killed = with_process(pid, |process| {
+        if signal == 0 {
+            return false;
+        }
+        raise(process, signal);
+
+        match process.state {
+            State::Ready => {
+                let blocked = process.signals.deliverable() & bit(signal) == 0;
+                signal == SIGKILL || (!blocked && process.signals.terminates(signal))
+            }
+            State::Stopped(_) => signal == SIGKILL,
+            _ => false,
+        }
+    })?;
+
+    if killed {
+        exit_process(pid, ExitStatus::Killed(signal));
+    }
+    Ok(())
+}
+
+/// Marks `signal` pending on `process`, `SIGCONT` continues it.
+fn raise(process: &mut Process, signal: u8) {
+    if process.is_zombie() {
+        return;
+    }
+    if let (SIGCONT, State::Stopped(_)) = (signal, process.state) {
+        process.state = State::Ready;
+    }
+    process.signals.raise(signal);
+}
+
+/// Installs `action` for `signal` in the running process if it is not
+/// `None`, and returns the previous action.
+pub fn change_action(signal: u8, action: Option<SigAction>) -> Result<SigAction, ProcessError> {
+    if !is_valid(signal) {
+        return Err(ProcessError::InvalidSignal);
+    }
+    let pid = current().ok_or(ProcessError::NoSuchProcess)?;
+    with_process(pid, |process| match action {
+        Some(action) => process.signals.set_action(signal, action),
+        None => Ok(process.signals.action(signal)),
+    })?
+}
+
+/// Changes the mask of the running process like `sigprocmask` if `set`
+/// is not `None`, and returns the previous mask.
+pub fn change_mask(how: u64, set: Option<u64>) -> Result<u64, ProcessError> {
+    let pid = current().ok_or(ProcessError::NoSuchProcess)?;
+    with_process(pid, |process| match set {
+        Some(set) => process.signals.set_mask(how, set),
+        None => Ok(process.signals.mask()),
+    })?
+}
+
+/// Makes `pid` the foreground process, which gets `SIGINT` for Ctrl+C.
+pub fn set_foreground(pid: Option<Pid>) {
+    FOREGROUND.store(pid.map_or(0, |pid| pid.0), Ordering::SeqCst);
+}
+
+pub fn foreground() -> Option<Pid> {
+    match FOREGROUND.load(Ordering::SeqCst) {
+        0 => None,
+        pid => Some(Pid(pid)),
+    }
+}
+
+/// Sends `SIGINT` to the foreground process and its descendants, like to
+/// a process group. Called for Ctrl+C by the console input, in interrupt
+/// context, so it does not allocate. Returns false if there is no
+/// foreground process.
+pub fn interrupt_foreground() -> bool {
+    let foreground = match foreground() {
+        Some(pid) => pid,
+        None => return false,
+    };
+
+    let mut processes = PROCESSES.lock();
+    for index in 0..processes.len() {
+        // Walk up the parents, there are no cycles
+        let mut ancestor = Some(processes[index].pid);
+        while let Some(pid) = ancestor {
+            if pid == foreground {
+                raise(&mut processes[index], SIGINT);
+                break;
+            }
+            ancestor = processes
+                .iter()
+                .find(|process| process.pid == pid)
+                .and_then(|process| process.parent);
+        }
+    }
+    true
+}
+
+/// Whether the running process has a signal to act on, blocking system
+/// calls return early for it.
+pub fn interrupted() -> bool {
+    current()
+        .and_then(|pid| with_process(pid, |process| process.signals.deliverable() != 0).ok())
+        .unwrap_or(false)
+}
+
+/// Ends the running process if a pending signal terminates it. Called
+/// when an interrupt returns to user mode, where the registers for a
+/// handler are not at hand.
+pub fn exit_if_killed() {
+    let signal = current()
+        .and_then(|pid| with_process(pid, |process| process.signals.take_terminating()).ok())
+        .flatten();
+
+    if let Some(signal) = signal {
+        unsafe { exit_user_mode(UserExit::Killed(signal)) }
+    }
+}
+
+/// Acts on the pending signals of the running process before a system
+/// call returns to `registers`. Runs at most one handler: `registers`
+/// are changed to enter it, and its frame holds the old ones.
+pub fn deliver(registers: &mut Registers) {
+    let pid = match current() {
+        Some(pid) => pid,
+        None => return,
+    };
+
+    loop {
+        let next = with_process(pid, |process| {
+            let signal = process.signals.take()?;
+            Some((signal, process.signals.action(signal)))
+        });
+        let (signal, action) = match next {
+            Ok(Some(next)) => next,
+            _ => return,
+        };
+
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(signal) {
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+                DefaultAction::Terminate => unsafe { exit_user_mode(UserExit::Killed(signal)) },
+                DefaultAction::Stop => {
+                    let _ = with_process(pid, |process| {
+                        if let Some(thread) = process.threads.first_mut() {
+                            thread.registers = registers.clone();
+                        }
+                    });
+                    unsafe { exit_user_mode(UserExit::Stopped(signal)) }
+                }
+            },
+            _ => {
+                if let Err(error) = push_frame(pid, signal, &action, registers) {
+                    warn!("Process {} cannot handle signal {}: {}", pid, signal, error);
+                    unsafe { exit_user_mode(UserExit::Killed(SIGSEGV)) }
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Pushes a `SignalFrame` on the user stack and sets `registers` to run
+/// the handler of `action`.
+fn push_frame(
+    pid: Pid,
+    signal: u8,
+    action: &SigAction,
+    registers: &mut Registers,
+) -> Result<(), ProcessError> {
+    let size = mem::size_of::<SignalFrame>() as u64;
+    // Like after a call: the stack is 16 byte aligned above the return
+    // address
+    let address = registers
+        .rsp
+        .checked_sub(RED_ZONE + size)
+        .map(|address| (address & !0xf).saturating_sub(8))
+        .and_then(|address| VirtAddr::try_new(address).ok())
+        .ok_or(ProcessError::Memory(MemoryError::NotUserAddress))?;
+    address_space::check_user_range(address, size, true)?;
+
+    let mask = with_process(pid, |process| {
+        let signals = &mut process.signals;
+        let old = signals.mask;
+        let mut blocked = action.mask;
+        if action.flags & SA_NODEFER == 0 {
+            blocked |= bit(signal);
+        }
+        let _ = signals.set_mask(SIG_BLOCK, blocked);
+        old
+    })?;
+
+    let frame = SignalFrame {
+        return_address: action.restorer,
+        registers: registers.clone(),
+        mask,
+    };
+    unsafe { address.as_mut_ptr::<SignalFrame>().write_unaligned(frame) };
+
+    registers.rip = action.handler;
+    registers.rsp = address.as_u64();
+    registers.rdi = u64::from(signal);
+    Ok(())
+}
+
+/// Returns from a handler: restores the registers and the mask from the
+/// `SignalFrame` below the user stack pointer in `registers`. The
+/// handler returned to the restorer, which popped the return address.
+/// The frame is user memory: one which does not return to user code is
+/// rejected, the process could be stopped and resumed with it.
+pub fn restore(registers: &mut Registers) -> Result<(), ProcessError> {
+    let pid = current().ok_or(ProcessError::NoSuchProcess)?;
+    let size = mem::size_of::<SignalFrame>() as u64;
+    let address = registers
+        .rsp
+        .checked_sub(8)
+        .and_then(|address| VirtAddr::try_new(address).ok())
+        .ok_or(ProcessError::Memory(MemoryError::NotUserAddress))?;
+    address_space::check_user_range(address, size, false)?;
+
+    let frame = unsafe { address.as_ptr::<SignalFrame>().read_unaligned() };
+    if !frame.registers.is_valid() {
+        return Err(ProcessError::Memory(MemoryError::NotUserAddress));
+    }
+    with_process(pid, |process| {
+        process.signals.set_mask(SIG_SETMASK, frame.mask)
+    })??;
+    *registers = frame.registers;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_actions() {
+        let mut signals = Signals::new();
+        signals.raise(SIGCHLD);
+        assert_eq!(signals.pending(), 0);
+
+        signals.raise(SIGTERM);
+        signals.raise(SIGSTOP);
+        assert_eq!(signals.take_terminating(), Some(SIGTERM));
+        assert_eq!(signals.take_terminating(), None);
+
+        // SIGCONT discards pending stops
+        signals.raise(SIGCONT);
+        assert_eq!(signals.pending(), 0);
+    }
+
+    #[test]
+    fn masks_and_handlers() {
+        let mut signals = Signals::new();
+        let handler = SigAction {
+            handler: 0x1000,
+            restorer: 0x2000,
+            ..SigAction::default()
+        };
+        assert_eq!(
+            signals.set_action(SIGKILL, handler),
+            Err(ProcessError::InvalidSignal)
+        );
+        assert_eq!(
+            signals.set_action(SIGINT, handler),
+            Ok(SigAction::default())
+        );
+
+        signals
+            .set_mask(SIG_BLOCK, bit(SIGINT) | bit(SIGKILL))
+            .unwrap();
+        assert_eq!(signals.mask(), bit(SIGINT));
+        signals.raise(SIGINT);
+        assert_eq!(signals.take(), None);
+
+        signals.set_mask(SIG_UNBLOCK, bit(SIGINT)).unwrap();
+        assert!(!signals.terminates(SIGINT));
+        assert_eq!(signals.take(), Some(SIGINT));
+
+        // Handlers do not survive exec, a fork keeps them
+        assert_eq!(signals.fork().action(SIGINT), handler);
+        signals.exec();
+        assert_eq!(signals.action(SIGINT), SigAction::default());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(from_name("INT"), Some(SIGINT));
+        assert_eq!(from_name("SIGKILL"), Some(SIGKILL));
+        assert_eq!(from_name("NOPE"), None);
+    }
+}
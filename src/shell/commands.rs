@@ -0,0 +1,384 @@
+//! Shell command registry and the built-in commands.
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::arch::interrupts::{exceptions, irq};
+use crate::arch::memory::{self, heap};
+use crate::arch::power;
+use crate::device::pci;
+use crate::klog;
+use crate::process::signal::{self, SIGCONT, SIGTERM};
+use crate::process::{self, programs, ExitStatus, Pid, ProcessError, State};
+use crate::shell::Output;
+use crate::time::TIME;
+
+/// A shell command. `run` gets the arguments without the command name.
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub run: fn(&[&str]),
+}
+
+static BUILTINS: &[Command] = &[
+    Command {
+        name: "help",
+        description: "List the available commands",
+        run: help,
+    },
+    Command {
+        name: "echo",
+        description: "Print the arguments",
+        run: echo,
+    },
+    Command {
+        name: "mem",
+        description: "Show heap usage",
+        run: mem,
+    },
+    Command {
+        name: "allocs",
+        description: "List live heap allocations (heap-debug)",
+        run: allocs,
+    },
+    Command {
+        name: "slabs",
+        description: "Show slab cache usage",
+        run: slabs,
+    },
+    Command {
+        name: "vm",
+        description: "List kernel virtual memory regions",
+        run: vm,
+    },
+    Command {
+        name: "uptime",
+        description: "Show the time since boot",
+        run: uptime,
+    },
+    Command {
+        name: "irqs",
+        description: "Show interrupt counts per IRQ line",
+        run: irqs,
+    },
+    Command {
+        name: "tasks",
+        description: "List processes",
+        run: tasks,
+    },
+    Command {
+        name: "run",
+        description: "Run a user program, list them without arguments",
+        run: run,
+    },
+    Command {
+        name: "kill",
+        description: "Send a signal to a process, SIGTERM by default",
+        run: kill,
+    },
+    Command {
+        name: "fg",
+        description: "Continue a stopped process in the foreground",
+        run: fg,
+    },
+    Command {
+        name: "lspci",
+        description: "List PCI devices",
+        run: lspci,
+    },
+    Command {
+        name: "dmesg",
+        description: "Print the kernel log",
+        run: dmesg,
+    },
+    Command {
+        name: "reboot",
+        description: "Reboot the machine",
+        run: reboot,
+    },
+    Command {
+        name: "shutdown",
+        description: "Power the machine off",
+        run: shutdown,
+    },
+    Command {
+        name: "halt",
+        description: "Stop the CPU",
+        run: halt,
+    },
+];
+
+/// Commands registered at runtime by other parts of the kernel.
+static COMMANDS: Mutex<Vec<Command>> = Mutex::new(Vec::new());
+
+/// Adds a command to the shell. A command with the same name as an
+/// existing command or a builtin replaces it.
+pub fn register(command: Command) {
+    let mut commands = COMMANDS.lock();
+    commands.retain(|existing| existing.name != command.name);
+    commands.push(command);
+}
+
+fn find(name: &str) -> Option<Command> {
+    COMMANDS
+        .lock()
+        .iter()
+        .chain(BUILTINS.iter())
+        .find(|command| command.name == name)
+        .copied()
+}
+
+/// Runs a command line.
+pub fn execute(line: &str) {
+    let mut words = line.split_whitespace();
+
+    let name = match words.next() {
+        Some(name) => name,
+        None => return,
+    };
+    let args: Vec<&str> = words.collect();
+
+    match find(name) {
+        Some(command) => (command.run)(&args),
+        None => println!("{}: command not found, try help", name),
+    }
+}
+
+fn help(_args: &[&str]) {
+    let registered = COMMANDS.lock().clone();
+    // A registered command replaces the builtin of the same name
+    let builtins = BUILTINS.iter().filter(|builtin| {
+        registered
+            .iter()
+            .all(|command| command.name != builtin.name)
+    });
+
+    for command in builtins.chain(registered.iter()) {
+        println!("{:<10} {}", command.name, command.description);
+    }
+}
+
+fn echo(args: &[&str]) {
+    println!("{}", args.join(" "));
+}
+
+fn mem(_args: &[&str]) {
+    let stats = crate::HEAP_ALLOCATOR.stats();
+
+    println!("Heap backend:   {:>10}", crate::HEAP_ALLOCATOR.backend_name());
+    println!("Heap size:      {:>10} bytes", stats.size);
+    println!("Heap limit:     {:>10} bytes", stats.limit);
+    println!("Heap used:      {:>10} bytes", stats.used);
+    println!("Heap free:      {:>10} bytes", stats.free);
+    println!("Heap peak:      {:>10} bytes", stats.peak);
+    println!("Requested:      {:>10} bytes", stats.requested);
+    println!("Allocations:    {:>10}", stats.allocations);
+    println!("Times grown:    {:>10}", stats.grow_count);
+    println!("Fragmentation:  {:>9}%", stats.fragmentation());
+}
+
+fn allocs(_args: &[&str]) {
+    if !heap::dump_allocations(&mut Output) {
+        println!("Allocations are only tracked with the heap-debug feature");
+    }
+}
+
+fn slabs(_args: &[&str]) {
+    let caches = match crate::HEAP_ALLOCATOR.cache_stats() {
+        Some(caches) => caches,
+        None => {
+            println!("The {} heap has no slab caches", crate::HEAP_ALLOCATOR.backend_name());
+            return;
+        }
+    };
+
+    println!(
+        "{:>6} {:>6} {:>8} {:>8} {:>10}",
+        "size", "slabs", "objects", "in use", "allocs"
+    );
+
+    for cache in caches.iter() {
+        println!(
+            "{:>6} {:>6} {:>8} {:>8} {:>10}",
+            cache.object_size, cache.slabs, cache.objects, cache.in_use, cache.allocations
+        );
+    }
+}
+
+fn vm(_args: &[&str]) {
+    memory::for_each_region(|region| println!("{}", region));
+}
+
+fn uptime(_args: &[&str]) {
+    println!(
+        "Up {:.2} seconds ({} ticks)",
+        TIME.get_seconds(),
+        TIME.get_ticks()
+    );
+}
+
+fn irqs(_args: &[&str]) {
+    for line in 0..irq::IRQ_LINES as u8 {
+        let count = irq::irq_count(line);
+        if count > 0 {
+            println!("IRQ {:>2}: {}", line, count);
+        }
+    }
+    let nmis = exceptions::nmi_count();
+    if nmis > 0 {
+        println!("NMI:    {}", nmis);
+    }
+}
+
+fn tasks(_args: &[&str]) {
+    println!("{:>5} {:>5} {:<8} {:>7} NAME", "PID", "PPID", "STATE", "THREADS");
+    process::for_each(|info| {
+        print!(
+            "{:>5} {:>5} {:<8} {:>7} {}",
+            info.pid,
+            info.parent.map_or(0, |pid| pid.0),
+            info.state,
+            info.threads,
+            info.name
+        );
+        match info.state {
+            State::Zombie(status) => println!(" ({})", status),
+            _ => println!(),
+        }
+    });
+}
+
+/// Runs a program in a new process, with the arguments after the name.
+/// The shell waits until it exits or stops.
+fn run(args: &[&str]) {
+    let name = match args.first() {
+        Some(name) => *name,
+        None => {
+            for name in programs::names() {
+                println!("{}", name);
+            }
+            return;
+        }
+    };
+    let file = match programs::find(name) {
+        Some(file) => file,
+        None => {
+            println!("{}: no such program", name);
+            return;
+        }
+    };
+
+    let pid = match process::spawn(name, file, args, &[], None) {
+        Ok(pid) => pid,
+        Err(error) => {
+            println!("{}: {}", name, error);
+            return;
+        }
+    };
+    run_in_foreground(name, pid);
+}
+
+/// Runs process `pid`, which gets the Ctrl+C of the console. It is
+/// reaped once it exits, a stopped one is left for `fg`.
+fn run_in_foreground(name: &str, pid: Pid) {
+    signal::set_foreground(Some(pid));
+    let status = process::run(pid);
+    signal::set_foreground(None);
+
+    match status {
+        Ok(ExitStatus::Exited(0)) => {}
+        Ok(status @ ExitStatus::Stopped(_)) => {
+            println!("{}: {}, continue it with fg {}", name, status, pid);
+            return;
+        }
+        Ok(status) => println!("{}: {}", name, status),
+        Err(error) => println!("{}: {}", name, error),
+    }
+    let _ = process::wait(None, Some(pid));
+}
+
+fn kill(args: &[&str]) {
+    let pid = match args.first().and_then(|pid| pid.parse().ok()) {
+        Some(pid) => Pid(pid),
+        None => {
+            println!("Usage: kill <pid> [signal]");
+            return;
+        }
+    };
+    let signal = match args.get(1) {
+        Some(name) => match name.parse().ok().or_else(|| signal::from_name(name)) {
+            Some(signal) => signal,
+            None => {
+                println!("{}: no such signal", name);
+                return;
+            }
+        },
+        None => SIGTERM,
+    };
+
+    if let Err(error) = signal::send(pid, signal) {
+        println!("kill {}: {}", pid, error);
+    }
+}
+
+fn fg(args: &[&str]) {
+    let pid = match args.first().and_then(|pid| pid.parse().ok()) {
+        Some(pid) => Pid(pid),
+        None => {
+            println!("Usage: fg <pid>");
+            return;
+        }
+    };
+
+    let mut name = None;
+    process::for_each(|info| {
+        if info.pid == pid {
+            name = Some(info.name);
+        }
+    });
+    let name = match name {
+        Some(name) => name,
+        None => {
+            println!("fg {}: {}", pid, ProcessError::NoSuchProcess);
+            return;
+        }
+    };
+
+    match signal::send(pid, SIGCONT) {
+        Ok(()) => run_in_foreground(&name, pid),
+        Err(error) => println!("fg {}: {}", pid, error),
+    }
+}
+
+fn lspci(_args: &[&str]) {
+    pci::for_each_function(|function| {
+        println!(
+            "{:02x}:{:02x}.{} {:04x}:{:04x} {}",
+            function.address.bus,
+            function.address.device,
+            function.address.function,
+            function.vendor_id,
+            function.device_id,
+            function.class_name()
+        );
+    });
+}
+
+fn dmesg(_args: &[&str]) {
+    klog::dump(&Output);
+}
+
+fn reboot(_args: &[&str]) {
+    println!("Rebooting...");
+    power::reboot();
+}
+
+fn shutdown(_args: &[&str]) {
+    println!("Shutting down...");
+    power::shutdown();
+}
+
+fn halt(_args: &[&str]) {
+    println!("System halted.");
+    power::halt();
+}
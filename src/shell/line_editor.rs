@@ -0,0 +1,334 @@
+//! Line editor for the shell.
+//!
+//! Takes console input one byte at a time. Arrow keys arrive as the ANSI
+//! escape sequences a terminal sends (the keyboard driver generates the
+//! same sequences), so the editor works on the VGA and the serial console.
+//!
+//! To stay compatible with the VGA console the editor only outputs
+//! printable characters, carriage returns and backspaces: after every edit
+//! the line is redrawn from the start and the cursor is moved back with
+//! backspaces.
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+/// Longest line that fits on one VGA row together with the prompt.
+pub const MAX_LINE_LENGTH: usize = 72;
+
+const HISTORY_SIZE: usize = 32;
+
+const ESCAPE: u8 = 0x1B;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+const CTRL_A: u8 = 0x01;
+const CTRL_C: u8 = 0x03;
+const CTRL_E: u8 = 0x05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    /// Received ESC
+    Start,
+    /// Received ESC [ and optionally a number parameter
+    Sequence(Option<u8>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// Enter was pressed, contains the line.
+    Line(String),
+    /// Ctrl+C was pressed, the line was discarded.
+    Interrupt,
+}
+
+pub struct LineEditor {
+    prompt: &'static str,
+    /// Only contains printable ASCII, so byte and character positions are
+    /// the same.
+    line: String,
+    cursor: usize,
+    /// Length of the line when it was last drawn.
+    drawn_length: usize,
+    history: VecDeque<String>,
+    /// Position while browsing the history, `None` when editing a new line.
+    history_index: Option<usize>,
+    escape: Escape,
+    last_byte: u8,
+}
+
+impl LineEditor {
+    pub fn new(prompt: &'static str) -> Self {
+        LineEditor {
+            prompt,
+            line: String::new(),
+            cursor: 0,
+            drawn_length: 0,
+            history: VecDeque::new(),
+            history_index: None,
+            escape: Escape::None,
+            last_byte: 0,
+        }
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(String::as_str)
+    }
+
+    /// Writes the prompt, call this before reading a new line.
+    pub fn start<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        self.reset();
+        out.write_str(self.prompt)
+    }
+
+    /// Starts with an empty line, returning the old line.
+    fn reset(&mut self) -> String {
+        self.cursor = 0;
+        self.drawn_length = 0;
+        self.history_index = None;
+        core::mem::replace(&mut self.line, String::new())
+    }
+
+    /// Handles one byte of input, echoing the result to `out`.
+    pub fn feed<W: Write>(&mut self, byte: u8, out: &mut W) -> Result<Option<Event>, fmt::Error> {
+        let last_byte = self.last_byte;
+        self.last_byte = byte;
+
+        match self.escape {
+            Escape::Start => {
+                self.escape = if byte == b'[' {
+                    Escape::Sequence(None)
+                } else {
+                    Escape::None
+                };
+                return Ok(None);
+            }
+            Escape::Sequence(parameter) => {
+                if byte.is_ascii_digit() {
+                    let digit = byte - b'0';
+                    self.escape = Escape::Sequence(Some(parameter.unwrap_or(0) * 10 + digit));
+                } else {
+                    self.escape = Escape::None;
+                    self.handle_escape(byte, parameter, out)?;
+                }
+                return Ok(None);
+            }
+            Escape::None => {}
+        }
+
+        match byte {
+            ESCAPE => self.escape = Escape::Start,
+            b'\r' | b'\n' => {
+                // Terminals send \r or \r\n for enter
+                if byte == b'\n' && last_byte == b'\r' {
+                    return Ok(None);
+                }
+                return self.submit(out).map(Some);
+            }
+            CTRL_C => {
+                out.write_str("^C\n")?;
+                self.reset();
+                return Ok(Some(Event::Interrupt));
+            }
+            BACKSPACE | DELETE => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                    self.redraw(out)?;
+                }
+            }
+            CTRL_A => self.move_cursor(0, out)?,
+            CTRL_E => self.move_cursor(self.line.len(), out)?,
+            0x20..=0x7e => {
+                if self.line.len() < MAX_LINE_LENGTH {
+                    self.line.insert(self.cursor, byte as char);
+                    self.cursor += 1;
+                    self.redraw(out)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(None)
+    }
+
+    fn handle_escape<W: Write>(
+        &mut self,
+        byte: u8,
+        parameter: Option<u8>,
+        out: &mut W,
+    ) -> fmt::Result {
+        match (byte, parameter) {
+            (b'A', _) => self.history_previous(out),
+            (b'B', _) => self.history_next(out),
+            (b'C', _) => self.move_cursor((self.cursor + 1).min(self.line.len()), out),
+            (b'D', _) => self.move_cursor(self.cursor.saturating_sub(1), out),
+            (b'H', _) | (b'~', Some(1)) => self.move_cursor(0, out),
+            (b'F', _) | (b'~', Some(4)) => self.move_cursor(self.line.len(), out),
+            (b'~', Some(3)) => {
+                // Delete key
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                    self.redraw(out)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn submit<W: Write>(&mut self, out: &mut W) -> Result<Event, fmt::Error> {
+        out.write_str("\n")?;
+
+        let line = self.reset();
+
+        let is_repeat = self.history.back().map_or(false, |last| *last == line);
+        if !line.trim().is_empty() && !is_repeat {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(line.clone());
+        }
+
+        Ok(Event::Line(line))
+    }
+
+    fn history_previous<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        let index = match self.history_index {
+            None if self.history.is_empty() => return Ok(()),
+            None => self.history.len() - 1,
+            Some(0) => return Ok(()),
+            Some(index) => index - 1,
+        };
+
+        self.show_history(Some(index), out)
+    }
+
+    fn history_next<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        match self.history_index {
+            None => Ok(()),
+            Some(index) if index + 1 < self.history.len() => {
+                self.show_history(Some(index + 1), out)
+            }
+            Some(_) => self.show_history(None, out),
+        }
+    }
+
+    fn show_history<W: Write>(&mut self, index: Option<usize>, out: &mut W) -> fmt::Result {
+        self.history_index = index;
+        self.line = match index {
+            Some(index) => self.history[index].clone(),
+            None => String::new(),
+        };
+        self.cursor = self.line.len();
+        self.redraw(out)
+    }
+
+    fn move_cursor<W: Write>(&mut self, position: usize, out: &mut W) -> fmt::Result {
+        if position != self.cursor {
+            self.cursor = position;
+            self.redraw(out)?;
+        }
+        Ok(())
+    }
+
+    /// Draws the prompt and line from the start of the row, blanks what is
+    /// left of a longer previous line and moves back to the cursor.
+    fn redraw<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        out.write_char('\r')?;
+        out.write_str(self.prompt)?;
+        out.write_str(&self.line)?;
+
+        let blanks = self.drawn_length.saturating_sub(self.line.len());
+        for _ in 0..blanks {
+            out.write_char(' ')?;
+        }
+        for _ in 0..blanks + self.line.len() - self.cursor {
+            out.write_char(BACKSPACE as char)?;
+        }
+
+        self.drawn_length = self.line.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn feed_all(editor: &mut LineEditor, input: &[u8]) -> Option<Event> {
+        let mut out = String::new();
+        let mut event = None;
+        for &byte in input {
+            if let Some(e) = editor.feed(byte, &mut out).unwrap() {
+                event = Some(e);
+            }
+        }
+        event
+    }
+
+    fn line(s: &str) -> Option<Event> {
+        Some(Event::Line(String::from(s)))
+    }
+
+    #[test]
+    fn type_and_submit() {
+        let mut editor = LineEditor::new("> ");
+
+        assert_eq!(feed_all(&mut editor, b"echo hi\r\n"), line("echo hi"));
+        assert_eq!(feed_all(&mut editor, b"\n"), line(""));
+    }
+
+    #[test]
+    fn backspace_and_cursor_movement() {
+        let mut editor = LineEditor::new("> ");
+
+        // Type "helo", move left once, insert "l", remove the last "o" and
+        // add a "p".
+        let event = feed_all(&mut editor, b"helo\x1b[Dl\x1b[C\x08p\r");
+        assert_eq!(event, line("hellp"));
+
+        // Home and delete
+        let event = feed_all(&mut editor, b"xabc\x1b[H\x1b[3~\r");
+        assert_eq!(event, line("abc"));
+    }
+
+    #[test]
+    fn history() {
+        let mut editor = LineEditor::new("> ");
+        feed_all(&mut editor, b"first\r");
+        feed_all(&mut editor, b"second\r");
+        feed_all(&mut editor, b"second\r");
+
+        assert_eq!(editor.history().count(), 2);
+        assert_eq!(feed_all(&mut editor, b"\x1b[A\x1b[A\r"), line("first"));
+        // The history is now first, second, first
+        assert_eq!(
+            feed_all(&mut editor, b"\x1b[A\x1b[A\x1b[A\x1b[B\r"),
+            line("second")
+        );
+        assert_eq!(feed_all(&mut editor, b"\x1b[A\x1b[Bnew\r"), line("new"));
+    }
+
+    #[test]
+    fn interrupt_discards_line() {
+        let mut editor = LineEditor::new("> ");
+
+        assert_eq!(feed_all(&mut editor, b"abc\x03"), Some(Event::Interrupt));
+        assert_eq!(feed_all(&mut editor, b"d\r"), line("d"));
+    }
+
+    #[test]
+    fn redraw_output() {
+        let mut editor = LineEditor::new("> ");
+        let mut out = String::new();
+
+        editor.start(&mut out).unwrap();
+        for &byte in b"ab\x1b[D\x08" {
+            editor.feed(byte, &mut out).unwrap();
+        }
+
+        // The last redraw shows "b", blanks the old second character and
+        // moves back over it and the "b".
+        assert!(out.ends_with("\r> b \x08\x08"));
+    }
+}
@@ -0,0 +1,60 @@
+//! # Kernel shell
+//!
+//! Reads lines from the console input and runs the matching command.
+//! Input comes from the keyboard and the serial console, output goes to
+//! every console sink, so the shell can be used on the screen and over
+//! `-serial mon:stdio`.
+use core::fmt;
+
+use crate::arch::interrupts;
+use crate::console::{self, Console};
+
+use self::line_editor::{Event, LineEditor};
+
+pub mod commands;
+pub mod line_editor;
+
+const PROMPT: &str = "kernel> ";
+
+/// Writes shell output to all console sinks.
+pub struct Output;
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+        Ok(())
+    }
+}
+
+impl Console for Output {
+    fn name(&self) -> &'static str {
+        "shell"
+    }
+
+    fn write_str(&self, s: &str) {
+        print!("{}", s);
+    }
+}
+
+/// Runs the shell, never returns.
+pub fn run() -> ! {
+    let mut editor = LineEditor::new(PROMPT);
+    let mut out = Output;
+
+    editor.start(&mut out).unwrap();
+
+    loop {
+        while let Some(byte) = console::input::read() {
+            match editor.feed(byte, &mut out).unwrap() {
+                Some(Event::Line(line)) => {
+                    commands::execute(&line);
+                    editor.start(&mut out).unwrap();
+                }
+                Some(Event::Interrupt) => editor.start(&mut out).unwrap(),
+                None => {}
+            }
+        }
+
+        interrupts::pause();
+    }
+}
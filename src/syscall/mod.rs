@@ -0,0 +1,614 @@
+//! # System calls
+//!
+//! User code asks the kernel for services with the `syscall` instruction,
+//! or `int 0x80`. The number of the system call is passed in `rax`, up to
+//! six arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`, and the
+//! result comes back in `rax`. Errors are returned as the negative value
+//! of an `Error`.
+//!
+//! | number | name   | arguments          | result           |
+//! |--------|--------|--------------------|------------------|
+//! | 0      | read   | fd, buffer, length | bytes read       |
+//! | 1      | write  | fd, buffer, length | bytes written    |
+//! | 2      | exit   | code               | does not return  |
+//! | 3      | yield  |                    | 0                |
+//! | 4      | sleep  | milliseconds       | 0                |
+//! | 5      | getpid |                    | process id       |
+//! | 6      | wait   | pid, status        | pid of the child |
+//! | 7      | fork   |                    | pid, 0 in child  |
+//! | 8      | execve | path, argv, envp   | does not return  |
+//! | 9      | fcntl  | fd, command, value | depends          |
+//! | 10     | kill   | pid, signal        | 0                |
+//! | 11     | sigaction   | signal, action, old action | 0       |
+//! | 12     | sigprocmask | how, set, old set          | 0       |
+//! | 13     | sigreturn   |                            | does not return |
+//!
+//! The architecture code saves the registers and calls `dispatch`, which
+//! returns to the state in them. `execve` replaces them, and so do
+//! signals: `dispatch` delivers the pending ones before it returns.
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{mem, slice, str};
+use x86_64::VirtAddr;
+
+use crate::arch::interrupts;
+use crate::arch::memory::address_space;
+use crate::arch::user::{self, Registers, UserExit};
+use crate::console;
+use crate::process::signal::{self, SigAction};
+use crate::process::{self, Handle, Pid, ProcessError};
+use crate::time::{self, TIME};
+
+pub const READ: u64 = 0;
+pub const WRITE: u64 = 1;
+pub const EXIT: u64 = 2;
+pub const YIELD: u64 = 3;
+pub const SLEEP: u64 = 4;
+pub const GETPID: u64 = 5;
+pub const WAIT: u64 = 6;
+pub const FORK: u64 = 7;
+pub const EXECVE: u64 = 8;
+pub const FCNTL: u64 = 9;
+pub const KILL: u64 = 10;
+pub const SIGACTION: u64 = 11;
+pub const SIGPROCMASK: u64 = 12;
+pub const SIGRETURN: u64 = 13;
+
+pub const STDIN: u32 = 0;
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+
+/// `fcntl` commands and the flag of `F_GETFD` and `F_SETFD`.
+pub const F_GETFD: u64 = 1;
+pub const F_SETFD: u64 = 2;
+pub const FD_CLOEXEC: u64 = 1;
+
+/// Limits of the strings `execve` copies from user memory.
+const MAX_STRING_LEN: usize = 4096;
+const MAX_STRINGS: usize = 256;
+
+/// Errors of system calls, with the values of the Linux error numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Error {
+    NoEntry = 2,
+    NoProcess = 3,
+    Interrupted = 4,
+    ArgumentsTooLong = 7,
+    NotExecutable = 8,
+    BadFd = 9,
+    NoChild = 10,
+    WouldBlock = 11,
+    NoMemory = 12,
+    BadAddress = 14,
+    InvalidArgument = 22,
+    NoSyscall = 38,
+}
+
+impl From<ProcessError> for Error {
+    fn from(error: ProcessError) -> Error {
+        match error {
+            ProcessError::Elf(_) => Error::NotExecutable,
+            ProcessError::Memory(_) => Error::NoMemory,
+            ProcessError::NoSuchProcess => Error::NoProcess,
+            ProcessError::NotFound => Error::NoEntry,
+            ProcessError::TooManyProcesses | ProcessError::NotExited => Error::WouldBlock,
+            ProcessError::NotReady | ProcessError::InvalidSignal => Error::InvalidArgument,
+            ProcessError::NoChild => Error::NoChild,
+        }
+    }
+}
+
+/// The arguments of a system call, as they were in the registers.
+pub struct Args([u64; 6]);
+
+/// A type a system call argument can be decoded to.
+pub trait FromArg: Sized {
+    fn from_arg(raw: u64) -> Result<Self, Error>;
+}
+
+impl FromArg for u64 {
+    fn from_arg(raw: u64) -> Result<Self, Error> {
+        Ok(raw)
+    }
+}
+
+impl FromArg for usize {
+    fn from_arg(raw: u64) -> Result<Self, Error> {
+        Ok(raw as usize)
+    }
+}
+
+impl FromArg for u32 {
+    fn from_arg(raw: u64) -> Result<Self, Error> {
+        if raw > u64::from(u32::MAX) {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(raw as u32)
+    }
+}
+
+/// Exit codes are passed like C passes an `int`, the upper half is ignored.
+impl FromArg for i32 {
+    fn from_arg(raw: u64) -> Result<Self, Error> {
+        Ok(raw as i32)
+    }
+}
+
+impl FromArg for VirtAddr {
+    fn from_arg(raw: u64) -> Result<Self, Error> {
+        VirtAddr::try_new(raw).map_err(|_| Error::BadAddress)
+    }
+}
+
+impl Args {
+    pub fn new(args: [u64; 6]) -> Args {
+        Args(args)
+    }
+
+    /// Decodes argument `index`.
+    pub fn get<T: FromArg>(&self, index: usize) -> Result<T, Error> {
+        T::from_arg(self.0[index])
+    }
+
+    /// Decodes arguments `index` and `index + 1` as the address and length
+    /// of a buffer of the user, which the kernel reads from.
+    pub fn user_slice(&self, index: usize) -> Result<&'static [u8], Error> {
+        let (address, len) = self.user_buffer(index, false)?;
+        Ok(unsafe { slice::from_raw_parts(address.as_ptr(), len) })
+    }
+
+    /// Like `user_slice`, for a buffer the kernel writes to.
+    pub fn user_slice_mut(&self, index: usize) -> Result<&'static mut [u8], Error> {
+        let (address, len) = self.user_buffer(index, true)?;
+        Ok(unsafe { slice::from_raw_parts_mut(address.as_mut_ptr(), len) })
+    }
+
+    /// Decodes argument `index` as a NUL-terminated string of the user,
+    /// which is copied.
+    pub fn user_string(&self, index: usize) -> Result<String, Error> {
+        user_string(self.get(index)?)
+    }
+
+    /// Decodes argument `index` as a null-terminated array of strings of
+    /// the user, like `argv`. A null pointer is an empty array.
+    pub fn user_strings(&self, index: usize) -> Result<Vec<String>, Error> {
+        let address: u64 = self.get(index)?;
+        let mut strings = Vec::new();
+        if address == 0 {
+            return Ok(strings);
+        }
+
+        let mut address = VirtAddr::try_new(address).map_err(|_| Error::BadAddress)?;
+        loop {
+            address_space::check_user_range(address, 8, false).map_err(|_| Error::BadAddress)?;
+            let string = unsafe { address.as_ptr::<u64>().read_unaligned() };
+            if string == 0 {
+                return Ok(strings);
+            }
+            if strings.len() == MAX_STRINGS {
+                return Err(Error::ArgumentsTooLong);
+            }
+            strings.push(user_string(
+                VirtAddr::try_new(string).map_err(|_| Error::BadAddress)?,
+            )?);
+            address += 8u64;
+        }
+    }
+
+    fn user_buffer(&self, index: usize, write: bool) -> Result<(VirtAddr, usize), Error> {
+        let address: VirtAddr = self.get(index)?;
+        let len: usize = self.get(index + 1)?;
+
+        address_space::check_user_range(address, len as u64, write)
+            .map_err(|_| Error::BadAddress)?;
+        Ok((address, len))
+    }
+}
+
+/// Copies the NUL-terminated string at `address` from user memory.
+fn user_string(address: VirtAddr) -> Result<String, Error> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = address + bytes.len();
+        // Every page the string touches has to be checked
+        if bytes.is_empty() || byte.is_aligned(4096u64) {
+            address_space::check_user_range(byte, 1, false).map_err(|_| Error::BadAddress)?;
+        }
+
+        match unsafe { *byte.as_ptr::<u8>() } {
+            0 => break,
+            _ if bytes.len() == MAX_STRING_LEN => return Err(Error::ArgumentsTooLong),
+            value => bytes.push(value),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| Error::InvalidArgument)
+}
+
+/// A system call. `run` gets the arguments and the registers of the
+/// caller, and returns the result.
+#[derive(Clone, Copy)]
+pub struct Syscall {
+    pub name: &'static str,
+    pub run: fn(&Args, &mut Registers) -> Result<u64, Error>,
+}
+
+/// The system calls, indexed by their number.
+static SYSCALLS: &[Syscall] = &[
+    Syscall {
+        name: "read",
+        run: read,
+    },
+    Syscall {
+        name: "write",
+        run: write,
+    },
+    Syscall {
+        name: "exit",
+        run: exit,
+    },
+    Syscall {
+        name: "yield",
+        run: yield_now,
+    },
+    Syscall {
+        name: "sleep",
+        run: sleep,
+    },
+    Syscall {
+        name: "getpid",
+        run: getpid,
+    },
+    Syscall {
+        name: "wait",
+        run: wait,
+    },
+    Syscall {
+        name: "fork",
+        run: fork,
+    },
+    Syscall {
+        name: "execve",
+        run: execve,
+    },
+    Syscall {
+        name: "fcntl",
+        run: fcntl,
+    },
+    Syscall {
+        name: "kill",
+        run: kill,
+    },
+    Syscall {
+        name: "sigaction",
+        run: sigaction,
+    },
+    Syscall {
+        name: "sigprocmask",
+        run: sigprocmask,
+    },
+    Syscall {
+        name: "sigreturn",
+        run: sigreturn,
+    },
+];
+
+/// Runs the system call in `registers`, and stores its result in `rax`.
+pub fn dispatch(registers: &mut Registers) {
+    let number = registers.rax;
+    let args = Args::new([
+        registers.rdi,
+        registers.rsi,
+        registers.rdx,
+        registers.r10,
+        registers.r8,
+        registers.r9,
+    ]);
+
+    let result = match SYSCALLS.get(number as usize) {
+        Some(syscall) => {
+            trace!("System call {} {:x?}", syscall.name, args.0);
+            (syscall.run)(&args, registers)
+        }
+        None => {
+            debug!("Unknown system call {}", number);
+            Err(Error::NoSyscall)
+        }
+    };
+    registers.rax = encode(result);
+
+    signal::deliver(registers);
+}
+
+/// Errors become negative, like Linux returns them.
+fn encode(result: Result<u64, Error>) -> u64 {
+    match result {
+        Ok(value) => value,
+        Err(error) => (-(error as i64)) as u64,
+    }
+}
+
+/// Blocks until there is console input, then reads what is there.
+fn read(args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    let fd: u32 = args.get(0)?;
+    let buffer = args.user_slice_mut(1)?;
+
+    if process::handle(fd).ok_or(Error::BadFd)?.handle != Handle::ConsoleInput {
+        return Err(Error::BadFd);
+    }
+    if buffer.is_empty() {
+        return Ok(0);
+    }
+
+    loop {
+        let mut count = 0;
+        while count < buffer.len() {
+            match console::input::read() {
+                Some(byte) => buffer[count] = byte,
+                None => break,
+            }
+            count += 1;
+        }
+
+        if count > 0 {
+            return Ok(count as u64);
+        }
+        if signal::interrupted() {
+            return Err(Error::Interrupted);
+        }
+        interrupts::pause();
+    }
+}
+
+fn write(args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    let fd: u32 = args.get(0)?;
+    let buffer = args.user_slice(1)?;
+
+    if process::handle(fd).ok_or(Error::BadFd)?.handle != Handle::ConsoleOutput {
+        return Err(Error::BadFd);
+    }
+
+    match str::from_utf8(buffer) {
+        Ok(text) => print!("{}", text),
+        Err(_) => print!("{}", String::from_utf8_lossy(buffer)),
+    }
+
+    Ok(buffer.len() as u64)
+}
+
+fn exit(args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    let code: i32 = args.get(0)?;
+    unsafe { user::exit_user_mode(UserExit::Exit(code)) }
+}
+
+/// There is no scheduler yet, the caller keeps running.
+fn yield_now(_args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    Ok(0)
+}
+
+fn sleep(args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    let milliseconds: u64 = args.get(0)?;
+    let end = TIME.get_ticks() + time::ticks_from_ms(milliseconds);
+
+    while TIME.get_ticks() < end {
+        if signal::interrupted() {
+            return Err(Error::Interrupted);
+        }
+        interrupts::pause();
+    }
+    Ok(0)
+}
+
+/// User code the kernel runs without a process gets 0.
+fn getpid(_args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    Ok(process::current().map_or(0, |pid| pid.0))
+}
+
+/// Reaps the child `pid`, or any child if it is -1, and stores its status
+/// if `status` is not null. Children run until they exit before their
+/// parent goes on, so there is nothing to block for.
+fn wait(args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    let pid: u64 = args.get(0)?;
+    let status: u64 = args.get(1)?;
+    let child = match pid as i64 {
+        -1 => None,
+        pid if pid > 0 => Some(Pid(pid as u64)),
+        _ => return Err(Error::InvalidArgument),
+    };
+    let status = if status == 0 {
+        None
+    } else {
+        let address = VirtAddr::try_new(status).map_err(|_| Error::BadAddress)?;
+        address_space::check_user_range(address, 4, true).map_err(|_| Error::BadAddress)?;
+        Some(address.as_mut_ptr::<i32>())
+    };
+
+    let (pid, exit_status) =
+        process::wait(process::current(), child).map_err(|error| match error {
+            ProcessError::NotExited => Error::WouldBlock,
+            _ => Error::NoChild,
+        })?;
+
+    if let Some(status) = status {
+        unsafe { status.write_unaligned(exit_status.wait_status()) };
+    }
+    Ok(pid.0)
+}
+
+/// There is no scheduler, the child runs until it exits before the
+/// parent goes on.
+fn fork(_args: &Args, registers: &mut Registers) -> Result<u64, Error> {
+    let child = process::fork(registers)?;
+    if let Err(error) = process::run(child) {
+        // The child holds references to the pages it shares with the
+        // parent, so end and reap it rather than leave it behind
+        let _ = signal::send(child, signal::SIGKILL);
+        let _ = process::wait(process::current(), Some(child));
+        return Err(error.into());
+    }
+    Ok(child.0)
+}
+
+fn execve(args: &Args, registers: &mut Registers) -> Result<u64, Error> {
+    let path = args.user_string(0)?;
+    let argv = args.user_strings(1)?;
+    let envp = args.user_strings(2)?;
+
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+    process::exec(registers, &path, &argv, &envp)?;
+    Ok(0)
+}
+
+/// Gets or sets the close-on-exec flag, the only flag handles have.
+fn fcntl(args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    let fd: u32 = args.get(0)?;
+    let command: u64 = args.get(1)?;
+    let value: u64 = args.get(2)?;
+
+    let handle = process::handle(fd).ok_or(Error::BadFd)?;
+    match command {
+        F_GETFD if handle.close_on_exec => Ok(FD_CLOEXEC),
+        F_GETFD => Ok(0),
+        F_SETFD => {
+            process::set_close_on_exec(fd, value & FD_CLOEXEC != 0).ok_or(Error::BadFd)?;
+            Ok(0)
+        }
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// Only single processes can be signalled, there are no process groups.
+fn kill(args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    let pid: u64 = args.get(0)?;
+    let signal: u32 = args.get(1)?;
+    if pid as i64 <= 0 || signal > u32::from(u8::MAX) {
+        return Err(Error::InvalidArgument);
+    }
+
+    signal::send(Pid(pid), signal as u8)?;
+    Ok(0)
+}
+
+/// Installs the `SigAction` at `action` if it is not null, and stores
+/// the previous one at `old_action` if that is not null.
+fn sigaction(args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    let signal: u32 = args.get(0)?;
+    let action: Option<*mut SigAction> = user_pointer(args.get(1)?, false)?;
+    let old_action: Option<*mut SigAction> = user_pointer(args.get(2)?, true)?;
+    if signal > u32::from(u8::MAX) {
+        return Err(Error::InvalidArgument);
+    }
+
+    let action = action.map(|action| unsafe { action.read_unaligned() });
+    let old = signal::change_action(signal as u8, action)?;
+    if let Some(old_action) = old_action {
+        unsafe { old_action.write_unaligned(old) };
+    }
+    Ok(0)
+}
+
+/// Changes the mask with the set at `set` if it is not null, and stores
+/// the previous mask at `old_set` if that is not null.
+fn sigprocmask(args: &Args, _registers: &mut Registers) -> Result<u64, Error> {
+    let how: u64 = args.get(0)?;
+    let set: Option<*mut u64> = user_pointer(args.get(1)?, false)?;
+    let old_set: Option<*mut u64> = user_pointer(args.get(2)?, true)?;
+
+    let set = set.map(|set| unsafe { set.read_unaligned() });
+    let old = signal::change_mask(how, set)?;
+    if let Some(old_set) = old_set {
+        unsafe { old_set.write_unaligned(old) };
+    }
+    Ok(0)
+}
+
+/// Goes on where the signal handler was entered. `rax` is restored too.
+fn sigreturn(_args: &Args, registers: &mut Registers) -> Result<u64, Error> {
+    signal::restore(registers).map_err(|_| Error::BadAddress)?;
+    Ok(registers.rax)
+}
+
+/// Checks that a `T` at `address` is user memory, `None` if it is null.
+fn user_pointer<T>(address: u64, write: bool) -> Result<Option<*mut T>, Error> {
+    if address == 0 {
+        return Ok(None);
+    }
+
+    let address = VirtAddr::try_new(address).map_err(|_| Error::BadAddress)?;
+    address_space::check_user_range(address, mem::size_of::<T>() as u64, write)
+        .map_err(|_| Error::BadAddress)?;
+    Ok(Some(address.as_mut_ptr()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call(number: u64, args: [u64; 6]) -> i64 {
+        let mut registers = Registers {
+            rax: number,
+            rdi: args[0],
+            rsi: args[1],
+            rdx: args[2],
+            r10: args[3],
+            r8: args[4],
+            r9: args[5],
+            ..Registers::default()
+        };
+        dispatch(&mut registers);
+        registers.rax as i64
+    }
+
+    #[test]
+    fn unknown_syscall() {
+        assert_eq!(call(1000, [0; 6]), -(Error::NoSyscall as i64));
+    }
+
+    #[test]
+    fn arguments_are_decoded() {
+        let args = Args::new([1 << 32, 7, 0, 0, 0, 0]);
+        assert_eq!(args.get::<u32>(0), Err(Error::InvalidArgument));
+        assert_eq!(args.get::<u32>(1), Ok(7));
+        assert_eq!(args.get::<i32>(0), Ok(0));
+    }
+
+    #[test]
+    fn kernel_buffers_are_rejected() {
+        let args = Args::new([STDOUT as u64, 0x1000, 16, 0, 0, 0]);
+        assert_eq!(args.user_slice(1).err(), Some(Error::BadAddress));
+        assert_eq!(
+            call(WRITE, [STDOUT as u64, 0x1000, 16, 0, 0, 0]),
+            -(Error::BadAddress as i64)
+        );
+    }
+
+    #[test]
+    fn bad_fd() {
+        let buffer = address_space::USER_SPACE_START;
+        assert_eq!(call(WRITE, [STDIN as u64, buffer, 0, 0, 0, 0]), -9);
+        assert_eq!(call(READ, [STDOUT as u64, buffer, 0, 0, 0, 0]), -9);
+        assert_eq!(call(FCNTL, [7, F_GETFD, 0, 0, 0, 0]), -9);
+    }
+
+    #[test]
+    fn kernel_strings_are_rejected() {
+        let args = Args::new([0x1000, 0x1000, 0, 0, 0, 0]);
+        assert_eq!(args.user_string(0), Err(Error::BadAddress));
+        assert_eq!(args.user_strings(1), Err(Error::BadAddress));
+        assert_eq!(args.user_strings(2), Ok(Vec::new()));
+        assert_eq!(call(EXECVE, [0x1000, 0, 0, 0, 0, 0]), -14);
+    }
+
+    #[test]
+    fn fork_needs_a_process() {
+        assert_eq!(call(FORK, [0; 6]), -(Error::NoProcess as i64));
+    }
+
+    #[test]
+    fn signals_need_a_process() {
+        assert_eq!(call(KILL, [0, 9, 0, 0, 0, 0]), -22);
+        assert_eq!(call(SIGPROCMASK, [0, 0, 0, 0, 0, 0]), -3);
+        assert_eq!(call(SIGACTION, [2, 0x1000, 0, 0, 0, 0]), -14);
+    }
+}
@@ -2,34 +2,48 @@
 /// 
 /// Keeps track of the time since the system has booted.
 use core::sync::atomic::{Ordering, AtomicUsize};
-use alloc::sync::Arc;
 
 
 const PIC_FREQ: f64 = 18.2065;
 
 
 pub struct Time {
-    ticks: Arc<AtomicUsize>,
+    ticks: AtomicUsize,
 }
 
 impl Time {
+    pub const fn new() -> Time {
+        Time {
+            ticks: AtomicUsize::new(0),
+        }
+    }
+
     pub fn tick(&self) {
        self.ticks.fetch_add(1, Ordering::SeqCst);
     }
 
+    pub fn get_ticks(&self) -> usize {
+        self.ticks.load(Ordering::SeqCst)
+    }
+
     pub fn get_seconds(&self) -> f64 {
-        self.ticks.load(Ordering::SeqCst) as f64 / PIC_FREQ
+        self.get_ticks() as f64 / PIC_FREQ
     }
 }
 
-// IMPORTANT: lazy_static is evaluated when first called,
-// this means that this onyl can be called after memory init has
-// been done...
-lazy_static!
-{
-    pub static ref TIME: Time = {
-        Time {
-            ticks: Arc::new(AtomicUsize::new(0)),
-        }
-    };
-}
\ No newline at end of file
+/// Returns the number of ticks that take at least `ms` milliseconds.
+pub fn ticks_from_ms(ms: u64) -> usize {
+    let ticks = ms as f64 * PIC_FREQ / 1000.0;
+    let whole = ticks as usize;
+
+    // Round up, there is no f64::ceil without std
+    if (whole as f64) < ticks {
+        whole + 1
+    } else {
+        whole
+    }
+}
+
+// The tick counter is a plain atomic, so the time can be read before
+// the heap exists (for example to timestamp early log records).
+pub static TIME: Time = Time::new();
@@ -0,0 +1,81 @@
+//! # Heap
+//!
+//! A bump allocator in a static arena: allocations are carved off its
+//! free end, and only the last one can be given back. That is enough for
+//! small programs, which exit before they run out.
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Size of the heap. It is part of the .bss, so the loader maps it.
+pub const HEAP_SIZE: usize = 256 * 1024;
+
+#[repr(align(4096))]
+struct Arena(UnsafeCell<[u8; HEAP_SIZE]>);
+
+/// Only the allocator hands out parts of the arena.
+unsafe impl Sync for Arena {}
+
+static ARENA: Arena = Arena(UnsafeCell::new([0; HEAP_SIZE]));
+
+pub struct BumpAllocator {
+    /// Offset of the free part of the arena.
+    next: AtomicUsize,
+}
+
+impl BumpAllocator {
+    /// Returns how many bytes are allocated.
+    pub fn used(&self) -> usize {
+        self.next.load(Ordering::SeqCst)
+    }
+}
+
+unsafe impl GlobalAlloc for BumpAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let base = ARENA.0.get() as usize;
+        let mut next = self.next.load(Ordering::SeqCst);
+
+        loop {
+            // Alignments are powers of two
+            let start = (base + next + layout.align() - 1) & !(layout.align() - 1);
+            let start = start - base;
+            let end = match start.checked_add(layout.size()) {
+                Some(end) if end <= HEAP_SIZE => end,
+                _ => return ptr::null_mut(),
+            };
+
+            match self
+                .next
+                .compare_exchange(next, end, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return (base + start) as *mut u8,
+                Err(current) => next = current,
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        let start = pointer as usize - ARENA.0.get() as usize;
+        let end = start + layout.size();
+
+        // Fails unless this was the last allocation, its memory is lost
+        let _ = self
+            .next
+            .compare_exchange(end, start, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+pub static ALLOCATOR: BumpAllocator = BumpAllocator {
+    next: AtomicUsize::new(0),
+};
+
+#[alloc_error_handler]
+fn out_of_memory(layout: Layout) -> ! {
+    panic!(
+        "Error allocating {} bytes with alignment {}, the heap is full",
+        layout.size(),
+        layout.align()
+    );
+}
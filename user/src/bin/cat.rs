@@ -0,0 +1,45 @@
+//! Echoes its input to its output, until Ctrl+D.
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user;
+
+use user::syscall::{self, STDIN, STDOUT};
+use user::Args;
+
+const CTRL_D: u8 = 0x04;
+
+entry!(main);
+
+fn main(_args: &Args) -> i32 {
+    let mut buffer = [0; 64];
+
+    loop {
+        let count = match syscall::read(STDIN, &mut buffer) {
+            Ok(count) => count,
+            Err(error) => {
+                eprintln!("cat: {}", error);
+                return 1;
+            }
+        };
+
+        let input = &mut buffer[..count];
+        let end = input.iter().position(|&byte| byte == CTRL_D);
+        // Serial terminals send a carriage return for enter
+        for byte in input.iter_mut() {
+            if *byte == b'\r' {
+                *byte = b'\n';
+            }
+        }
+
+        let output = &input[..end.unwrap_or(count)];
+        if let Err(error) = syscall::write(STDOUT, output) {
+            eprintln!("cat: {}", error);
+            return 1;
+        }
+        if end.is_some() {
+            return 0;
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Counts to the first argument, 10 by default, one number per half
+//! second. Ctrl+C stops it early with a message.
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user::signal::{self, SIGINT};
+use user::syscall;
+use user::Args;
+
+entry!(main);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupt(_signal: i32) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn main(args: &Args) -> i32 {
+    let limit = match args.get(1).map(str::parse::<u64>) {
+        None => 10,
+        Some(Ok(limit)) => limit,
+        Some(Err(_)) => {
+            eprintln!("usage: counter [count]");
+            return 1;
+        }
+    };
+
+    if let Err(error) = signal::handle(SIGINT, interrupt) {
+        eprintln!("counter: {}", error);
+        return 1;
+    }
+
+    for count in 1..=limit {
+        println!("{}", count);
+        let _ = syscall::sleep(500);
+
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            println!("interrupted at {}", count);
+            return 128 + i32::from(SIGINT);
+        }
+    }
+    0
+}
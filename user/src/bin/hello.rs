@@ -0,0 +1,28 @@
+//! Greets the arguments, or the world.
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user::syscall;
+use user::Args;
+
+entry!(main);
+
+fn main(args: &Args) -> i32 {
+    let names: Vec<&str> = args.iter().skip(1).collect();
+
+    if names.is_empty() {
+        println!("Hello, world! I am process {}", syscall::getpid());
+    } else {
+        println!(
+            "Hello, {}! I am process {}",
+            names.join(" and "),
+            syscall::getpid()
+        );
+    }
+    0
+}
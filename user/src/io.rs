@@ -0,0 +1,56 @@
+//! Printing to the standard output and error.
+use core::fmt::{self, Write};
+
+use crate::syscall::{self, STDERR, STDOUT};
+
+/// Writes to a file descriptor. Errors are dropped, there is nowhere to
+/// report them.
+pub struct Writer(pub u32);
+
+impl Write for Writer {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        let mut bytes = string.as_bytes();
+        while !bytes.is_empty() {
+            match syscall::write(self.0, bytes) {
+                Ok(count) if count > 0 => bytes = &bytes[count..],
+                _ => return Err(fmt::Error),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: u32, args: fmt::Arguments) {
+    let _ = Writer(fd).write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::syscall::STDOUT, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::syscall::STDERR, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
+
+pub fn stdout() -> Writer {
+    Writer(STDOUT)
+}
+
+pub fn stderr() -> Writer {
+    Writer(STDERR)
+}
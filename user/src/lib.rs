@@ -0,0 +1,113 @@
+//! # User space runtime
+//!
+//! The library the user programs of the kernel are built with. It has
+//! `_start`, which reads the arguments the kernel put on the stack and
+//! calls the function given to `entry!`, wrappers for the system calls,
+//! signal handlers, `print!` and `println!`, a heap and a panic handler.
+//!
+//! The programs are built for `x86_64-user.json`, which links them at
+//! the start of the user part of an address space:
+//!
+//! ```text
+//! cargo build --release --target x86_64-user.json
+//! ```
+#![no_std]
+#![feature(alloc_error_handler, global_asm, llvm_asm)]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use core::{slice, str};
+
+#[macro_use]
+pub mod io;
+pub mod allocator;
+pub mod signal;
+pub mod syscall;
+
+global_asm!(include_str!("start.asm"));
+
+/// Makes `$main` the function the program runs. It gets the arguments
+/// and returns the exit code.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        pub extern "C" fn user_main(args: &$crate::Args) -> i32 {
+            let main: fn(&$crate::Args) -> i32 = $main;
+            main(args)
+        }
+    };
+}
+
+extern "C" {
+    fn user_main(args: &Args) -> i32;
+}
+
+/// The arguments and the environment of the program.
+#[repr(C)]
+pub struct Args {
+    argc: usize,
+    argv: *const *const u8,
+    envp: *const *const u8,
+}
+
+impl Args {
+    pub fn len(&self) -> usize {
+        self.argc
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.argc == 0
+    }
+
+    /// Returns argument `index`, the name of the program is argument 0.
+    pub fn get(&self, index: usize) -> Option<&'static str> {
+        if index >= self.argc {
+            return None;
+        }
+        Some(unsafe { c_str(*self.argv.add(index)) })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'static str> + '_ {
+        (0..self.argc).filter_map(move |index| self.get(index))
+    }
+
+    /// Returns the environment variables, as `NAME=value`.
+    pub fn env(&self) -> impl Iterator<Item = &'static str> + '_ {
+        (0..)
+            .map(move |index| unsafe { *self.envp.add(index) })
+            .take_while(|pointer| !pointer.is_null())
+            .map(|pointer| unsafe { c_str(pointer) })
+    }
+}
+
+/// The strings come from the kernel, which only passes UTF-8.
+unsafe fn c_str(pointer: *const u8) -> &'static str {
+    let mut len = 0;
+    while *pointer.add(len) != 0 {
+        len += 1;
+    }
+    str::from_utf8(slice::from_raw_parts(pointer, len)).unwrap_or("")
+}
+
+/// Called by `_start` with the stack the kernel built: argc, then the
+/// argv and envp pointers, each list ending with a null pointer.
+#[no_mangle]
+unsafe extern "C" fn user_start(stack: *const u64) -> ! {
+    let argc = *stack as usize;
+    let argv = stack.add(1) as *const *const u8;
+    let args = Args {
+        argc,
+        argv,
+        envp: argv.add(argc + 1),
+    };
+
+    syscall::exit(user_main(&args))
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("{}", info);
+    syscall::exit(101)
+}
@@ -0,0 +1,74 @@
+//! Signals. The numbers and `SigAction` are the ones of
+//! `src/process/signal.rs` in the kernel.
+use crate::syscall::{self, Result};
+
+pub const SIGHUP: u8 = 1;
+pub const SIGINT: u8 = 2;
+pub const SIGQUIT: u8 = 3;
+pub const SIGKILL: u8 = 9;
+pub const SIGUSR1: u8 = 10;
+pub const SIGSEGV: u8 = 11;
+pub const SIGUSR2: u8 = 12;
+pub const SIGTERM: u8 = 15;
+pub const SIGCHLD: u8 = 17;
+pub const SIGCONT: u8 = 18;
+pub const SIGSTOP: u8 = 19;
+pub const SIGTSTP: u8 = 20;
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+/// Lets the handler be entered again while it runs.
+pub const SA_NODEFER: u64 = 0x4000_0000;
+
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+extern "C" {
+    /// Calls `sigreturn`, see `start.asm`.
+    fn user_sigreturn();
+}
+
+/// What the process does on a signal.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SigAction {
+    pub handler: u64,
+    pub flags: u64,
+    pub restorer: u64,
+    pub mask: u64,
+}
+
+/// The bit of `signal` in a signal set.
+pub const fn bit(signal: u8) -> u64 {
+    1 << signal
+}
+
+/// Runs `handler` with the signal number when `signal` arrives.
+pub fn handle(signal: u8, handler: extern "C" fn(i32)) -> Result<()> {
+    let action = SigAction {
+        handler: handler as usize as u64,
+        flags: 0,
+        restorer: user_sigreturn as usize as u64,
+        mask: 0,
+    };
+    syscall::sigaction(signal, Some(&action)).map(|_| ())
+}
+
+pub fn ignore(signal: u8) -> Result<()> {
+    set_handler(signal, SIG_IGN)
+}
+
+/// Goes back to the default action of `signal`.
+pub fn reset(signal: u8) -> Result<()> {
+    set_handler(signal, SIG_DFL)
+}
+
+fn set_handler(signal: u8, handler: u64) -> Result<()> {
+    let action = SigAction {
+        handler,
+        ..SigAction::default()
+    };
+    syscall::sigaction(signal, Some(&action)).map(|_| ())
+}
@@ -0,0 +1,18 @@
+.intel_syntax noprefix
+.global _start
+
+# The kernel starts programs with rsp pointing at argc, 16 byte aligned,
+# see build_stack in src/elf/loader.rs of the kernel.
+_start:
+    xor rbp, rbp
+    mov rdi, rsp
+    call user_start
+    ud2
+
+# Signal handlers return here, the kernel restores the registers the
+# signal interrupted. 13 is SIGRETURN.
+.global user_sigreturn
+user_sigreturn:
+    mov eax, 13
+    syscall
+    ud2
@@ -0,0 +1,218 @@
+//! System call wrappers. The numbers, the errors and the calling
+//! convention are the ones of `src/syscall/mod.rs` in the kernel.
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr;
+
+use crate::signal::SigAction;
+
+pub const READ: u64 = 0;
+pub const WRITE: u64 = 1;
+pub const EXIT: u64 = 2;
+pub const YIELD: u64 = 3;
+pub const SLEEP: u64 = 4;
+pub const GETPID: u64 = 5;
+pub const WAIT: u64 = 6;
+pub const FORK: u64 = 7;
+pub const EXECVE: u64 = 8;
+pub const FCNTL: u64 = 9;
+pub const KILL: u64 = 10;
+pub const SIGACTION: u64 = 11;
+pub const SIGPROCMASK: u64 = 12;
+pub const SIGRETURN: u64 = 13;
+
+pub const STDIN: u32 = 0;
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+
+pub const F_GETFD: u64 = 1;
+pub const F_SETFD: u64 = 2;
+pub const FD_CLOEXEC: u64 = 1;
+
+/// An error number the kernel returned, like `errno`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error(pub i64);
+
+impl Error {
+    pub const NO_ENTRY: Error = Error(2);
+    pub const NO_PROCESS: Error = Error(3);
+    pub const INTERRUPTED: Error = Error(4);
+    pub const BAD_FD: Error = Error(9);
+    pub const NO_CHILD: Error = Error(10);
+    pub const BAD_ADDRESS: Error = Error(14);
+    pub const INVALID_ARGUMENT: Error = Error(22);
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NO_ENTRY => write!(f, "no such program"),
+            Error::NO_PROCESS => write!(f, "no such process"),
+            Error::INTERRUPTED => write!(f, "interrupted"),
+            Error::BAD_FD => write!(f, "bad file descriptor"),
+            Error::NO_CHILD => write!(f, "no child process"),
+            Error::BAD_ADDRESS => write!(f, "bad address"),
+            Error::INVALID_ARGUMENT => write!(f, "invalid argument"),
+            Error(number) => write!(f, "error {}", number),
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+unsafe fn syscall0(number: u64) -> u64 {
+    syscall3(number, 0, 0, 0)
+}
+
+unsafe fn syscall1(number: u64, a: u64) -> u64 {
+    syscall3(number, a, 0, 0)
+}
+
+unsafe fn syscall2(number: u64, a: u64, b: u64) -> u64 {
+    syscall3(number, a, b, 0)
+}
+
+/// The kernel keeps every register but `rax`, `syscall` itself changes
+/// `rcx` and `r11`.
+unsafe fn syscall3(number: u64, a: u64, b: u64, c: u64) -> u64 {
+    let result: u64;
+    llvm_asm!("syscall"
+        : "={rax}"(result)
+        : "{rax}"(number), "{rdi}"(a), "{rsi}"(b), "{rdx}"(c)
+        : "rcx", "r11", "memory"
+        : "volatile");
+    result
+}
+
+/// Errors come back as negative error numbers.
+fn check(value: u64) -> Result<u64> {
+    match value as i64 {
+        error if error < 0 => Err(Error(-error)),
+        _ => Ok(value),
+    }
+}
+
+/// Blocks until there is input, or a signal arrives.
+pub fn read(fd: u32, buffer: &mut [u8]) -> Result<usize> {
+    let result = unsafe {
+        syscall3(
+            READ,
+            u64::from(fd),
+            buffer.as_mut_ptr() as u64,
+            buffer.len() as u64,
+        )
+    };
+    check(result).map(|count| count as usize)
+}
+
+pub fn write(fd: u32, buffer: &[u8]) -> Result<usize> {
+    let result = unsafe {
+        syscall3(
+            WRITE,
+            u64::from(fd),
+            buffer.as_ptr() as u64,
+            buffer.len() as u64,
+        )
+    };
+    check(result).map(|count| count as usize)
+}
+
+pub fn exit(code: i32) -> ! {
+    unsafe { syscall1(EXIT, code as u64) };
+    unreachable!("exit returned")
+}
+
+pub fn yield_now() {
+    unsafe { syscall0(YIELD) };
+}
+
+/// Returns early with `Error::INTERRUPTED` if a signal arrives.
+pub fn sleep(milliseconds: u64) -> Result<()> {
+    check(unsafe { syscall1(SLEEP, milliseconds) }).map(|_| ())
+}
+
+pub fn getpid() -> u64 {
+    unsafe { syscall0(GETPID) }
+}
+
+/// Reaps the child `pid`, or any child if it is `None`. Returns its PID
+/// and its status, encoded like `waitpid` does.
+pub fn wait(pid: Option<u64>) -> Result<(u64, i32)> {
+    let mut status = 0i32;
+    let pid = pid.unwrap_or(u64::MAX);
+    let result = unsafe { syscall2(WAIT, pid, &mut status as *mut i32 as u64) };
+    check(result).map(|pid| (pid, status))
+}
+
+/// Returns the PID of the child to the parent, and 0 to the child.
+pub fn fork() -> Result<u64> {
+    check(unsafe { syscall0(FORK) })
+}
+
+/// Replaces the program with `path`. Only returns if that failed.
+pub fn execve(path: &str, args: &[&str], env: &[&str]) -> Error {
+    let path = c_string(path);
+    let args: Vec<Vec<u8>> = args.iter().map(|arg| c_string(arg)).collect();
+    let env: Vec<Vec<u8>> = env.iter().map(|var| c_string(var)).collect();
+    let argv = pointers(&args);
+    let envp = pointers(&env);
+
+    let result = unsafe {
+        syscall3(
+            EXECVE,
+            path.as_ptr() as u64,
+            argv.as_ptr() as u64,
+            envp.as_ptr() as u64,
+        )
+    };
+    check(result).err().unwrap_or(Error::INVALID_ARGUMENT)
+}
+
+pub fn fcntl(fd: u32, command: u64, value: u64) -> Result<u64> {
+    check(unsafe { syscall3(FCNTL, u64::from(fd), command, value) })
+}
+
+pub fn kill(pid: u64, signal: u8) -> Result<()> {
+    check(unsafe { syscall2(KILL, pid, u64::from(signal)) }).map(|_| ())
+}
+
+/// Installs `action` for `signal` if it is not `None`, and returns the
+/// previous action.
+pub fn sigaction(signal: u8, action: Option<&SigAction>) -> Result<SigAction> {
+    let action = action.map_or(ptr::null(), |action| action as *const SigAction);
+    let mut old = SigAction::default();
+    let result = unsafe {
+        syscall3(
+            SIGACTION,
+            u64::from(signal),
+            action as u64,
+            &mut old as *mut SigAction as u64,
+        )
+    };
+    check(result).map(|_| old)
+}
+
+/// Changes the blocked signals with `how`, one of the `SIG_*MASK`
+/// constants, and returns the previous set.
+pub fn sigprocmask(how: u64, set: Option<u64>) -> Result<u64> {
+    let set = set.as_ref().map_or(ptr::null(), |set| set as *const u64);
+    let mut old = 0u64;
+    let result = unsafe { syscall3(SIGPROCMASK, how, set as u64, &mut old as *mut u64 as u64) };
+    check(result).map(|_| old)
+}
+
+fn c_string(string: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(string.len() + 1);
+    bytes.extend_from_slice(string.as_bytes());
+    bytes.push(0);
+    bytes
+}
+
+/// A null-terminated array of pointers to `strings`.
+fn pointers(strings: &[Vec<u8>]) -> Vec<*const u8> {
+    strings
+        .iter()
+        .map(|string| string.as_ptr())
+        .chain(Some(ptr::null()))
+        .collect()
+}